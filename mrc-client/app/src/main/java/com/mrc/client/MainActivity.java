@@ -4,10 +4,12 @@ import android.annotation.SuppressLint;
 import android.content.Context;
 import android.content.Intent;
 import android.content.SharedPreferences;
+import android.content.pm.PackageInfo;
 import android.content.pm.PackageManager;
 import android.media.AudioManager;
 import android.os.Build;
 import android.os.Bundle;
+import android.provider.Settings;
 import android.util.Log;
 import android.view.KeyEvent;
 import android.view.View;
@@ -28,13 +30,25 @@ import androidx.core.view.ViewCompat;
 import androidx.core.view.WindowInsetsCompat;
 
 import com.google.gson.Gson;
+import com.mrc.client.proto.ArtworkChunk;
+import com.mrc.client.proto.ArtworkRequest;
+import com.mrc.client.proto.Hello;
+import com.mrc.client.proto.HelloResponse;
+import com.mrc.client.proto.MediaCommand;
+import com.mrc.client.proto.MediaKeyEventAck;
 import com.mrc.client.proto.Message;
+import com.mrc.client.proto.NowPlayingNtf;
 import com.mrc.client.proto.Ping;
 import com.mrc.client.proto.Pong;
+import com.mrc.client.proto.PushMediaCommand;
 import com.mrc.client.proto.PushMediaKeyEvent;
+import com.mrc.client.proto.RegisterDeviceRequest;
+import com.mrc.client.proto.RegisterDeviceResponse;
+import com.mrc.client.proto.VolumeStateNtf;
 
 import java.nio.ByteBuffer;
 import java.nio.charset.StandardCharsets;
+import java.util.ArrayList;
 import java.util.Map;
 import java.util.concurrent.atomic.AtomicBoolean;
 import java.util.concurrent.atomic.AtomicInteger;
@@ -58,6 +72,7 @@ public class MainActivity extends AppCompatActivity {
     private final String[] REQUIRED_PERMISSIONS = new String[]{"android.permission.POST_NOTIFICATIONS"};
 
     TcpClient client = new TcpClient();
+    NowPlayingMonitor nowPlayingMonitor;
 
     TextView textViewStatus;
     EditText editTextToken;
@@ -69,6 +84,8 @@ public class MainActivity extends AppCompatActivity {
     AtomicBoolean isForeground = new AtomicBoolean(true);
     // 连接id
     AtomicInteger connectionId = new AtomicInteger(-1);
+    // 当前连接是否已注册, 注册后才上报正在播放信息
+    AtomicBoolean registered = new AtomicBoolean(false);
 
     // 服务器地址
     String serverIpAddress;
@@ -97,6 +114,7 @@ public class MainActivity extends AppCompatActivity {
 
         initClient();
         initService();
+        initNowPlaying();
 
         loadText();
         updateUI();
@@ -140,15 +158,60 @@ public class MainActivity extends AppCompatActivity {
                             break;
                         case "Pong":
                             break;
+                        case "HelloResponse":
+                            HelloResponse helloResponse = gson.fromJson(message.data, HelloResponse.class);
+                            if (!helloResponse.ok) {
+                                Log.e(TAG, "Protocol negotiation failed: " + helloResponse.error);
+                            }
+                            break;
+                        case "RegisterDeviceResponse":
+                            RegisterDeviceResponse registerResponse = gson.fromJson(message.data, RegisterDeviceResponse.class);
+                            if (!registerResponse.ok) {
+                                Log.e(TAG, "Register device failed: " + registerResponse.error);
+                            } else {
+                                registered.set(true);
+                                reportVolumeState(connection_id);
+                                runOnUiThread(() -> reportNowPlaying(nowPlayingMonitor.snapshot()));
+                            }
+                            break;
+                        case "ArtworkRequest":
+                            ArtworkRequest artworkRequest = gson.fromJson(message.data, ArtworkRequest.class);
+                            runOnUiThread(() -> uploadArtwork(connection_id, artworkRequest.hash));
+                            break;
                         case "PushMediaKeyEvent":
                             PushMediaKeyEvent keyEvent = gson.fromJson(message.data, PushMediaKeyEvent.class);
+                            MediaKeyEventAck ack = new MediaKeyEventAck();
+                            ack.request_id = keyEvent.request_id;
+                            ack.result = MediaKeyEventAck.RESULT_REJECTED;
+                            ack.error = "";
                             if (token.equals(keyEvent.token)) {
                                 // https://stackoverflow.com/questions/18800198/control-the-default-music-player-of-android-or-any-other-music-player#comment137646196_53961746
                                 AudioManager audioManager = (AudioManager) getSystemService(Context.AUDIO_SERVICE);
                                 if (audioManager != null) {
                                     audioManager.dispatchMediaKeyEvent(new KeyEvent(keyEvent.action, keyEvent.code));
                                     //audioManager.dispatchMediaKeyEvent(new KeyEvent(KeyEvent.ACTION_DOWN, KeyEvent.KEYCODE_MEDIA_NEXT));
+                                    ack.result = MediaKeyEventAck.RESULT_EXECUTED;
+                                } else {
+                                    ack.error = "audio service unavailable";
                                 }
+                            } else {
+                                ack.error = "token mismatch";
+                            }
+                            MainActivity.sendMessage(client, connection_id, ack);
+                            break;
+                        case "PushMediaCommand":
+                            PushMediaCommand commandEvent = gson.fromJson(message.data, PushMediaCommand.class);
+                            MediaKeyEventAck commandAck = new MediaKeyEventAck();
+                            commandAck.request_id = commandEvent.request_id;
+                            if (token.equals(commandEvent.token)) {
+                                commandAck.error = executeMediaCommand(commandEvent.command);
+                            } else {
+                                commandAck.error = "token mismatch";
+                            }
+                            commandAck.result = commandAck.error.isEmpty() ? MediaKeyEventAck.RESULT_EXECUTED : MediaKeyEventAck.RESULT_REJECTED;
+                            MainActivity.sendMessage(client, connection_id, commandAck);
+                            if (commandAck.error.isEmpty() && commandEvent.command.isVolumeCommand()) {
+                                reportVolumeState(connection_id);
                             }
                             break;
                     }
@@ -158,6 +221,29 @@ public class MainActivity extends AppCompatActivity {
                 }
             }
             else {
+                registered.set(false);
+                if (event_type == TcpClient.EVENT_ON_CONNECT_SUCCESS) {
+                    // 连接成功后先声明协议版本和支持的功能
+                    Hello hello = new Hello();
+                    hello.protocol_version = Hello.PROTOCOL_VERSION;
+                    hello.min_protocol_version = Hello.MIN_PROTOCOL_VERSION;
+                    hello.features = new ArrayList<>();
+                    hello.features.add(Hello.FEATURE_MEDIA_KEY_ACK);
+                    hello.features.add(Hello.FEATURE_MEDIA_COMMAND);
+                    hello.features.add(Hello.FEATURE_NOW_PLAYING);
+                    hello.features.add(Hello.FEATURE_ARTWORK);
+                    MainActivity.sendMessage(client, connection_id, hello);
+
+                    // 再向服务器注册token, 服务器只会把该token的按键事件转发过来
+                    RegisterDeviceRequest request = new RegisterDeviceRequest();
+                    request.role = RegisterDeviceRequest.ROLE_DEVICE;
+                    request.token = token;
+                    request.device_name = Build.MANUFACTURER + " " + Build.MODEL;
+                    request.client_version = getClientVersion();
+                    request.capabilities = new ArrayList<>();
+                    MainActivity.sendMessage(client, connection_id, request);
+                }
+
                 runOnUiThread(() -> {
                     switch (event_type) {
                         case TcpClient.EVENT_ON_CONNECT_SUCCESS:
@@ -202,6 +288,102 @@ public class MainActivity extends AppCompatActivity {
         startForegroundService(new Intent(getBaseContext(), ControlService.class));
     }
 
+    // 跟踪当前播放器, 需要通知使用权, 未授权时打开系统设置
+    private void initNowPlaying() {
+        nowPlayingMonitor = new NowPlayingMonitor(this, this::reportNowPlaying);
+        if (!nowPlayingMonitor.start()) {
+            Toast.makeText(MainActivity.this, "Allow notification access to report the playing media", Toast.LENGTH_LONG).show();
+            startActivity(new Intent(Settings.ACTION_NOTIFICATION_LISTENER_SETTINGS));
+        }
+    }
+
+    // 上报正在播放信息, 未注册时丢弃, 注册成功后会上报一次最新状态
+    private void reportNowPlaying(NowPlayingNtf nowPlaying) {
+        if (registered.get()) {
+            MainActivity.sendMessage(client, connectionId.get(), nowPlaying);
+        }
+    }
+
+    // 按顺序分片上传服务器请求的封面, 已经换成其他封面时不上传
+    private void uploadArtwork(int connection_id, String hash) {
+        byte[] data = nowPlayingMonitor.artworkData(hash);
+        if (data == null) {
+            Log.w(TAG, "Requested artwork is no longer playing: " + hash);
+            return;
+        }
+
+        for (int offset = 0; offset < data.length; offset += ArtworkChunk.CHUNK_SIZE) {
+            ArtworkChunk chunk = new ArtworkChunk();
+            chunk.hash = hash;
+            chunk.total_size = data.length;
+            chunk.offset = offset;
+            chunk.data = new int[Math.min(ArtworkChunk.CHUNK_SIZE, data.length - offset)];
+            for (int i = 0; i < chunk.data.length; i++) {
+                chunk.data[i] = data[offset + i] & 0xff;
+            }
+            MainActivity.sendMessage(client, connection_id, chunk);
+        }
+    }
+
+    // 执行媒体控制指令, 返回错误原因, 成功时为空字符串
+    private String executeMediaCommand(MediaCommand command) {
+        AudioManager audioManager = (AudioManager) getSystemService(Context.AUDIO_SERVICE);
+        if (audioManager == null) {
+            return "audio service unavailable";
+        }
+
+        switch (command.type) {
+            case MediaCommand.TYPE_PLAY:
+                return dispatchMediaKey(audioManager, KeyEvent.KEYCODE_MEDIA_PLAY);
+            case MediaCommand.TYPE_PAUSE:
+                return dispatchMediaKey(audioManager, KeyEvent.KEYCODE_MEDIA_PAUSE);
+            case MediaCommand.TYPE_TOGGLE:
+                return dispatchMediaKey(audioManager, KeyEvent.KEYCODE_MEDIA_PLAY_PAUSE);
+            case MediaCommand.TYPE_NEXT:
+                return dispatchMediaKey(audioManager, KeyEvent.KEYCODE_MEDIA_NEXT);
+            case MediaCommand.TYPE_PREVIOUS:
+                return dispatchMediaKey(audioManager, KeyEvent.KEYCODE_MEDIA_PREVIOUS);
+            case MediaCommand.TYPE_STOP:
+                return dispatchMediaKey(audioManager, KeyEvent.KEYCODE_MEDIA_STOP);
+            case MediaCommand.TYPE_VOLUME_UP:
+                audioManager.adjustStreamVolume(AudioManager.STREAM_MUSIC, AudioManager.ADJUST_RAISE, AudioManager.FLAG_SHOW_UI);
+                return "";
+            case MediaCommand.TYPE_VOLUME_DOWN:
+                audioManager.adjustStreamVolume(AudioManager.STREAM_MUSIC, AudioManager.ADJUST_LOWER, AudioManager.FLAG_SHOW_UI);
+                return "";
+            case MediaCommand.TYPE_SET_VOLUME:
+                int maxVolume = audioManager.getStreamMaxVolume(AudioManager.STREAM_MUSIC);
+                audioManager.setStreamVolume(AudioManager.STREAM_MUSIC, Math.round(maxVolume * command.percent / 100.0f), AudioManager.FLAG_SHOW_UI);
+                return "";
+            case MediaCommand.TYPE_MUTE:
+                audioManager.adjustStreamVolume(AudioManager.STREAM_MUSIC, AudioManager.ADJUST_TOGGLE_MUTE, AudioManager.FLAG_SHOW_UI);
+                return "";
+            default:
+                // 跳转和随机/循环需要访问播放器的 MediaSession
+                return "unsupported command: " + command.type;
+        }
+    }
+
+    // 上报当前媒体音量
+    private void reportVolumeState(int connection_id) {
+        AudioManager audioManager = (AudioManager) getSystemService(Context.AUDIO_SERVICE);
+        if (audioManager == null) {
+            return;
+        }
+
+        int maxVolume = audioManager.getStreamMaxVolume(AudioManager.STREAM_MUSIC);
+        VolumeStateNtf ntf = new VolumeStateNtf();
+        ntf.percent = maxVolume > 0 ? Math.round(audioManager.getStreamVolume(AudioManager.STREAM_MUSIC) * 100.0f / maxVolume) : 0;
+        ntf.muted = audioManager.isStreamMute(AudioManager.STREAM_MUSIC);
+        MainActivity.sendMessage(client, connection_id, ntf);
+    }
+
+    private static String dispatchMediaKey(AudioManager audioManager, int code) {
+        audioManager.dispatchMediaKeyEvent(new KeyEvent(KeyEvent.ACTION_DOWN, code));
+        audioManager.dispatchMediaKeyEvent(new KeyEvent(KeyEvent.ACTION_UP, code));
+        return "";
+    }
+
     static void sendMessage(TcpClient client, int connectionId, Object data) {
         Gson gson = new Gson();
         Message message = new Message();
@@ -216,12 +398,31 @@ public class MainActivity extends AppCompatActivity {
         client.send(connectionId, buffer.array());
     }
 
+    private String getClientVersion() {
+        try {
+            PackageInfo packageInfo = getPackageManager().getPackageInfo(getPackageName(), 0);
+            return packageInfo.versionName;
+        } catch (PackageManager.NameNotFoundException e) {
+            return "";
+        }
+    }
+
     @Override
     protected void onResume() {
         super.onResume();
         // 应用程序回到前台
         isForeground.set(true);
         updateUI();
+        // 从系统设置授权返回后开始跟踪
+        if (!nowPlayingMonitor.isStarted() && nowPlayingMonitor.start()) {
+            reportNowPlaying(nowPlayingMonitor.snapshot());
+        }
+    }
+
+    @Override
+    protected void onDestroy() {
+        super.onDestroy();
+        nowPlayingMonitor.stop();
     }
 
     @Override
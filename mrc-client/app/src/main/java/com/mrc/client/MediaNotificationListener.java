@@ -0,0 +1,7 @@
+package com.mrc.client;
+
+import android.service.notification.NotificationListenerService;
+
+// 只用于获得通知使用权, MediaSessionManager.getActiveSessions 需要该授权才能读取其他应用的播放器
+public class MediaNotificationListener extends NotificationListenerService {
+}
@@ -0,0 +1,267 @@
+package com.mrc.client;
+
+import android.content.ComponentName;
+import android.content.Context;
+import android.graphics.Bitmap;
+import android.media.MediaMetadata;
+import android.media.session.MediaController;
+import android.media.session.MediaSessionManager;
+import android.media.session.PlaybackState;
+import android.os.Handler;
+import android.os.Looper;
+import android.os.SystemClock;
+import android.util.Log;
+
+import androidx.annotation.NonNull;
+import androidx.annotation.Nullable;
+import androidx.core.app.NotificationManagerCompat;
+
+import com.mrc.client.proto.ArtworkChunk;
+import com.mrc.client.proto.NowPlayingNtf;
+
+import java.io.ByteArrayOutputStream;
+import java.security.MessageDigest;
+import java.security.NoSuchAlgorithmException;
+import java.util.List;
+
+// 通过 MediaSessionManager 跟踪当前播放器, 播放信息变化时回调
+// 需要用户在系统设置中授予通知使用权(MediaNotificationListener), 未授权时不上报
+public class NowPlayingMonitor {
+    public final static String TAG = "NowPlayingMonitor";
+    // 封面缩放后的最大边长, 控制上传的数据量
+    private final static int ARTWORK_MAX_DIMENSION = 512;
+
+    public interface Listener {
+        void onNowPlayingChanged(NowPlayingNtf nowPlaying);
+    }
+
+    private final Context context;
+    private final Listener listener;
+    private final Handler handler = new Handler(Looper.getMainLooper());
+
+    private MediaSessionManager sessionManager;
+    // 正在跟踪的播放器, 没有活动的播放器时为 null
+    private MediaController controller;
+
+    // 最近一次编码的封面, 封面不变时不重复编码
+    private Bitmap artwork;
+    private byte[] artworkData;
+    private String artworkHash = "";
+
+    private final MediaSessionManager.OnActiveSessionsChangedListener sessionsChangedListener = this::onActiveSessionsChanged;
+
+    private final MediaController.Callback controllerCallback = new MediaController.Callback() {
+        @Override
+        public void onPlaybackStateChanged(@Nullable PlaybackState state) {
+            publish();
+        }
+
+        @Override
+        public void onMetadataChanged(@Nullable MediaMetadata metadata) {
+            publish();
+        }
+
+        @Override
+        public void onSessionDestroyed() {
+            setController(null);
+            publish();
+        }
+    };
+
+    public NowPlayingMonitor(Context context, Listener listener) {
+        this.context = context.getApplicationContext();
+        this.listener = listener;
+    }
+
+    public static boolean isAccessGranted(Context context) {
+        return NotificationManagerCompat.getEnabledListenerPackages(context).contains(context.getPackageName());
+    }
+
+    public boolean isStarted() {
+        return sessionManager != null;
+    }
+
+    // 开始跟踪播放器, 未授权时返回 false
+    public boolean start() {
+        if (isStarted()) {
+            return true;
+        }
+        if (!isAccessGranted(context)) {
+            return false;
+        }
+
+        MediaSessionManager manager = (MediaSessionManager) context.getSystemService(Context.MEDIA_SESSION_SERVICE);
+        ComponentName component = new ComponentName(context, MediaNotificationListener.class);
+        try {
+            manager.addOnActiveSessionsChangedListener(sessionsChangedListener, component, handler);
+            sessionManager = manager;
+            onActiveSessionsChanged(manager.getActiveSessions(component));
+        } catch (SecurityException e) {
+            Log.w(TAG, "Media session access denied: " + e);
+            return false;
+        }
+        return true;
+    }
+
+    public void stop() {
+        if (sessionManager != null) {
+            sessionManager.removeOnActiveSessionsChangedListener(sessionsChangedListener);
+            sessionManager = null;
+        }
+        setController(null);
+    }
+
+    // 当前的正在播放信息, 没有活动的播放器时状态为 none
+    @NonNull
+    public NowPlayingNtf snapshot() {
+        NowPlayingNtf nowPlaying = new NowPlayingNtf();
+        if (controller == null) {
+            return nowPlaying;
+        }
+
+        MediaMetadata metadata = controller.getMetadata();
+        if (metadata != null) {
+            nowPlaying.title = nonNull(metadata.getString(MediaMetadata.METADATA_KEY_TITLE));
+            nowPlaying.artist = nonNull(metadata.getString(MediaMetadata.METADATA_KEY_ARTIST));
+            nowPlaying.album = nonNull(metadata.getString(MediaMetadata.METADATA_KEY_ALBUM));
+            nowPlaying.duration_ms = Math.max(0, metadata.getLong(MediaMetadata.METADATA_KEY_DURATION));
+            updateArtwork(artworkBitmap(metadata));
+            nowPlaying.artwork_hash = artworkHash;
+        }
+
+        PlaybackState state = controller.getPlaybackState();
+        if (state != null) {
+            nowPlaying.state = toState(state.getState());
+            nowPlaying.position_ms = currentPosition(state);
+        }
+        return nowPlaying;
+    }
+
+    // 当前封面的编码数据, 哈希不是当前封面时返回 null
+    @Nullable
+    public byte[] artworkData(String hash) {
+        if (artworkData == null || !artworkHash.equals(hash)) {
+            return null;
+        }
+        return artworkData;
+    }
+
+    private void updateArtwork(@Nullable Bitmap bitmap) {
+        // 每次读取的元数据都是新对象, 按像素比较
+        if (bitmap == artwork || (bitmap != null && artwork != null && bitmap.sameAs(artwork))) {
+            return;
+        }
+        artwork = bitmap;
+        artworkData = null;
+        artworkHash = "";
+        if (bitmap == null) {
+            return;
+        }
+
+        int dimension = Math.max(bitmap.getWidth(), bitmap.getHeight());
+        if (dimension > ARTWORK_MAX_DIMENSION) {
+            float scale = (float) ARTWORK_MAX_DIMENSION / dimension;
+            bitmap = Bitmap.createScaledBitmap(bitmap, Math.max(1, Math.round(bitmap.getWidth() * scale)), Math.max(1, Math.round(bitmap.getHeight() * scale)), true);
+        }
+        ByteArrayOutputStream stream = new ByteArrayOutputStream();
+        if (!bitmap.compress(Bitmap.CompressFormat.JPEG, 90, stream) || stream.size() > ArtworkChunk.MAX_ARTWORK_SIZE) {
+            return;
+        }
+
+        try {
+            byte[] digest = MessageDigest.getInstance("SHA-256").digest(stream.toByteArray());
+            StringBuilder hash = new StringBuilder();
+            for (byte x : digest) {
+                hash.append(String.format("%02x", x));
+            }
+            artworkData = stream.toByteArray();
+            artworkHash = hash.toString();
+        } catch (NoSuchAlgorithmException e) {
+            Log.e(TAG, "SHA-256 unavailable: " + e);
+        }
+    }
+
+    @Nullable
+    private static Bitmap artworkBitmap(MediaMetadata metadata) {
+        for (String key : new String[]{MediaMetadata.METADATA_KEY_ALBUM_ART, MediaMetadata.METADATA_KEY_ART, MediaMetadata.METADATA_KEY_DISPLAY_ICON}) {
+            Bitmap bitmap = metadata.getBitmap(key);
+            if (bitmap != null) {
+                return bitmap;
+            }
+        }
+        return null;
+    }
+
+    private void onActiveSessionsChanged(@Nullable List<MediaController> controllers) {
+        // 列表按优先级排序, 优先跟踪正在播放的播放器
+        MediaController selected = null;
+        if (controllers != null) {
+            for (MediaController x : controllers) {
+                PlaybackState state = x.getPlaybackState();
+                if (state != null && state.getState() == PlaybackState.STATE_PLAYING) {
+                    selected = x;
+                    break;
+                }
+            }
+            if (selected == null && !controllers.isEmpty()) {
+                selected = controllers.get(0);
+            }
+        }
+
+        if (controller != null && selected != null && controller.getSessionToken().equals(selected.getSessionToken())) {
+            return;
+        }
+        setController(selected);
+        publish();
+    }
+
+    private void setController(@Nullable MediaController newController) {
+        if (controller != null) {
+            controller.unregisterCallback(controllerCallback);
+        }
+        controller = newController;
+        if (controller != null) {
+            controller.registerCallback(controllerCallback, handler);
+        }
+    }
+
+    private void publish() {
+        listener.onNowPlayingChanged(snapshot());
+    }
+
+    // 播放中的位置按上次更新后经过的时间推算
+    private static long currentPosition(PlaybackState state) {
+        long position = state.getPosition();
+        if (state.getState() == PlaybackState.STATE_PLAYING && state.getLastPositionUpdateTime() > 0) {
+            long elapsed = SystemClock.elapsedRealtime() - state.getLastPositionUpdateTime();
+            position += (long) (elapsed * state.getPlaybackSpeed());
+        }
+        return Math.max(0, position);
+    }
+
+    private static String toState(int state) {
+        switch (state) {
+            case PlaybackState.STATE_PLAYING:
+            case PlaybackState.STATE_FAST_FORWARDING:
+            case PlaybackState.STATE_REWINDING:
+                return NowPlayingNtf.STATE_PLAYING;
+            case PlaybackState.STATE_PAUSED:
+                return NowPlayingNtf.STATE_PAUSED;
+            case PlaybackState.STATE_STOPPED:
+            case PlaybackState.STATE_ERROR:
+                return NowPlayingNtf.STATE_STOPPED;
+            case PlaybackState.STATE_BUFFERING:
+            case PlaybackState.STATE_CONNECTING:
+            case PlaybackState.STATE_SKIPPING_TO_NEXT:
+            case PlaybackState.STATE_SKIPPING_TO_PREVIOUS:
+            case PlaybackState.STATE_SKIPPING_TO_QUEUE_ITEM:
+                return NowPlayingNtf.STATE_BUFFERING;
+            default:
+                return NowPlayingNtf.STATE_NONE;
+        }
+    }
+
+    private static String nonNull(@Nullable String value) {
+        return value == null ? "" : value;
+    }
+}
@@ -0,0 +1,16 @@
+package com.mrc.client.proto;
+
+// 封面数据分片, 按 offset 顺序发送
+public class ArtworkChunk {
+    // 单个分片的最大字节数
+    public static final int CHUNK_SIZE = 32 * 1024;
+    // 单个封面的最大字节数
+    public static final int MAX_ARTWORK_SIZE = 4 * 1024 * 1024;
+
+    // 完整数据的 SHA-256(十六进制小写)
+    public String hash;
+    public long total_size;
+    public long offset;
+    // 无符号字节(0-255), Gson 会把 byte[] 序列化为有符号数
+    public int[] data;
+}
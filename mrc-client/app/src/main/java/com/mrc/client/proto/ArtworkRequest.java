@@ -0,0 +1,6 @@
+package com.mrc.client.proto;
+
+// 服务器缓存中没有该封面时请求设备上传
+public class ArtworkRequest {
+    public String hash;
+}
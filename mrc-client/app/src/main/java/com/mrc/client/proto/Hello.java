@@ -0,0 +1,17 @@
+package com.mrc.client.proto;
+
+import java.util.List;
+
+public class Hello {
+    public static final int PROTOCOL_VERSION = 2;
+    public static final int MIN_PROTOCOL_VERSION = 1;
+
+    public static final String FEATURE_MEDIA_KEY_ACK = "media_key_ack";
+    public static final String FEATURE_MEDIA_COMMAND = "media_command";
+    public static final String FEATURE_NOW_PLAYING = "now_playing";
+    public static final String FEATURE_ARTWORK = "artwork";
+
+    public int protocol_version;
+    public int min_protocol_version;
+    public List<String> features;
+}
@@ -0,0 +1,10 @@
+package com.mrc.client.proto;
+
+import java.util.List;
+
+public class HelloResponse {
+    public boolean ok;
+    public String error;
+    public int protocol_version;
+    public List<String> features;
+}
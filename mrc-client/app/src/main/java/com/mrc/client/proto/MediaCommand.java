@@ -0,0 +1,29 @@
+package com.mrc.client.proto;
+
+// 媒体控制指令, type 决定使用哪些参数
+public class MediaCommand {
+    public static final String TYPE_PLAY = "play";
+    public static final String TYPE_PAUSE = "pause";
+    public static final String TYPE_TOGGLE = "toggle";
+    public static final String TYPE_NEXT = "next";
+    public static final String TYPE_PREVIOUS = "previous";
+    public static final String TYPE_STOP = "stop";
+    public static final String TYPE_SEEK_RELATIVE = "seek_relative";
+    public static final String TYPE_SEEK_ABSOLUTE = "seek_absolute";
+    public static final String TYPE_VOLUME_UP = "volume_up";
+    public static final String TYPE_VOLUME_DOWN = "volume_down";
+    public static final String TYPE_SET_VOLUME = "set_volume";
+    public static final String TYPE_MUTE = "mute";
+    public static final String TYPE_TOGGLE_SHUFFLE = "toggle_shuffle";
+    public static final String TYPE_TOGGLE_REPEAT = "toggle_repeat";
+
+    public String type;
+    public long offset_ms;
+    public long position_ms;
+    public int percent;
+
+    // 执行后音量会变化的指令
+    public boolean isVolumeCommand() {
+        return TYPE_VOLUME_UP.equals(type) || TYPE_VOLUME_DOWN.equals(type) || TYPE_SET_VOLUME.equals(type) || TYPE_MUTE.equals(type);
+    }
+}
@@ -0,0 +1,10 @@
+package com.mrc.client.proto;
+
+public class MediaKeyEventAck {
+    public static final String RESULT_EXECUTED = "executed";
+    public static final String RESULT_REJECTED = "rejected";
+
+    public long request_id;
+    public String result;
+    public String error;
+}
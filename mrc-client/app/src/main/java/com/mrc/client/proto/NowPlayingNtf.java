@@ -0,0 +1,21 @@
+package com.mrc.client.proto;
+
+// 正在播放信息变化后上报, token 和 updated_at 由服务器填充
+public class NowPlayingNtf {
+    public static final String STATE_NONE = "none";
+    public static final String STATE_STOPPED = "stopped";
+    public static final String STATE_PAUSED = "paused";
+    public static final String STATE_PLAYING = "playing";
+    public static final String STATE_BUFFERING = "buffering";
+
+    public String title = "";
+    public String artist = "";
+    public String album = "";
+    // 总时长(毫秒), 未知时为0
+    public long duration_ms;
+    // 上报时的播放位置(毫秒)
+    public long position_ms;
+    public String state = STATE_NONE;
+    // 封面内容哈希, 没有封面时为空
+    public String artwork_hash = "";
+}
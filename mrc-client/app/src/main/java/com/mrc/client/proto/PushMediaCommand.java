@@ -0,0 +1,7 @@
+package com.mrc.client.proto;
+
+public class PushMediaCommand {
+    public String token;
+    public long request_id;
+    public MediaCommand command;
+}
@@ -4,4 +4,5 @@ public class PushMediaKeyEvent {
     public int action;
     public int code;
     public String token;
+    public long request_id;
 }
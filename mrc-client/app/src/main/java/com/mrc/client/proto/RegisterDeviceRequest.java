@@ -0,0 +1,14 @@
+package com.mrc.client.proto;
+
+import java.util.List;
+
+public class RegisterDeviceRequest {
+    public static final String ROLE_DEVICE = "Device";
+    public static final String ROLE_CONTROLLER = "Controller";
+
+    public String role;
+    public String token;
+    public String device_name;
+    public String client_version;
+    public List<String> capabilities;
+}
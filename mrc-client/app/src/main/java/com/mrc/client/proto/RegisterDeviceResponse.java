@@ -0,0 +1,6 @@
+package com.mrc.client.proto;
+
+public class RegisterDeviceResponse {
+    public boolean ok;
+    public String error;
+}
@@ -0,0 +1,7 @@
+package com.mrc.client.proto;
+
+// 音量变化后上报, token 由服务器填充
+public class VolumeStateNtf {
+    public int percent;
+    public boolean muted;
+}
@@ -0,0 +1,209 @@
+use crate::proto::{artwork_hash, ArtworkAssembler, ArtworkChunk, ArtworkResponse};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// 缓存目录的大小上限, 超出时删除最久未使用的封面
+const MAX_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// 封面磁盘缓存, 文件名为内容哈希, 每个连接一个实例
+pub struct ArtworkStore {
+    dir: PathBuf,
+    max_size: u64,
+    assembler: ArtworkAssembler,
+    /// 已向服务器请求、尚未收到的封面
+    requested: HashSet<String>,
+    /// 本次连接已交给前端的封面
+    delivered: HashSet<String>,
+}
+
+impl ArtworkStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self::with_max_size(dir, MAX_CACHE_SIZE)
+    }
+
+    fn with_max_size(dir: PathBuf, max_size: u64) -> Self {
+        Self {
+            dir,
+            max_size,
+            assembler: ArtworkAssembler::default(),
+            requested: HashSet::new(),
+            delivered: HashSet::new(),
+        }
+    }
+
+    /// 磁盘中缓存的、尚未交给前端的封面
+    pub fn take_cached(&mut self, hash: &str) -> Option<Vec<u8>> {
+        if !is_valid_hash(hash) || self.delivered.contains(hash) {
+            return None;
+        }
+        let path = self.dir.join(hash);
+        let data = std::fs::read(&path).ok()?;
+        // 文件损坏或被改动时删除, 由调用方重新向服务器请求
+        if !artwork_hash(&data).eq_ignore_ascii_case(hash) {
+            println!("cached artwork {} is corrupted, removing", hash);
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        // 以修改时间记录最近使用, 清理时保留常用的封面
+        let _ = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        self.delivered.insert(hash.to_string());
+        Some(data)
+    }
+
+    /// 没有缓存且尚未请求过时返回 true, 由调用方向服务器请求
+    pub fn should_request(&mut self, hash: &str) -> bool {
+        is_valid_hash(hash)
+            && !self.delivered.contains(hash)
+            && self.requested.insert(hash.to_string())
+    }
+
+    pub fn on_response(&mut self, response: &ArtworkResponse) {
+        if !response.ok {
+            println!("artwork {} unavailable: {}", response.hash, response.error);
+            self.requested.remove(&response.hash);
+        }
+    }
+
+    /// 收到分片, 接收完成后写入磁盘并返回 (hash, 完整数据)
+    pub fn on_chunk(&mut self, chunk: ArtworkChunk) -> Option<(String, Vec<u8>)> {
+        let hash = chunk.hash.clone();
+        match self.assembler.push(chunk) {
+            Ok(None) => None,
+            Ok(Some((hash, data))) => {
+                self.requested.remove(&hash);
+                self.delivered.insert(hash.clone());
+                // 写入失败只影响下次连接, 本次仍然显示
+                let result = std::fs::create_dir_all(&self.dir)
+                    .and_then(|_| std::fs::write(self.dir.join(&hash), &data));
+                if let Err(err) = result {
+                    println!("failed to cache artwork {}: {}", hash, err);
+                }
+                self.evict(&hash);
+                Some((hash, data))
+            }
+            Err(err) => {
+                println!("artwork error: {}", err);
+                self.requested.remove(&hash);
+                None
+            }
+        }
+    }
+
+    /// 目录超出大小上限时从最久未使用的封面开始删除, 保留刚写入的 keep
+    fn evict(&self, keep: &str) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let metadata = entry.metadata().ok()?;
+                metadata.is_file().then(|| {
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    (modified, metadata.len(), entry.file_name())
+                })
+            })
+            .collect();
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort();
+        for (_, len, name) in files {
+            if total <= self.max_size {
+                break;
+            }
+            if name == keep {
+                continue;
+            }
+            if std::fs::remove_file(self.dir.join(&name)).is_ok() {
+                total -= len;
+            }
+        }
+    }
+}
+
+/// 哈希用作文件名, 只接受 SHA-256 十六进制
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|x| x.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::artwork_chunks;
+
+    /// 每个测试使用单独的空目录
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rmc-artwork-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// 模拟从服务器接收一个封面
+    fn receive(store: &mut ArtworkStore, data: &[u8]) -> String {
+        let hash = artwork_hash(data);
+        let mut result = None;
+        for chunk in artwork_chunks(&hash, data) {
+            result = store.on_chunk(chunk);
+        }
+        assert_eq!(result, Some((hash.clone(), data.to_vec())));
+        hash
+    }
+
+    #[test]
+    fn received_artwork_is_cached() {
+        let dir = temp_dir("cached");
+        let hash = receive(&mut ArtworkStore::new(dir.clone()), b"cover");
+
+        let mut store = ArtworkStore::new(dir.clone());
+        assert_eq!(store.take_cached(&hash), Some(b"cover".to_vec()));
+        // 同一连接内只交给前端一次
+        assert_eq!(store.take_cached(&hash), None);
+        assert!(!store.should_request(&hash));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn corrupted_file_is_removed() {
+        let dir = temp_dir("corrupted");
+        let hash = receive(&mut ArtworkStore::new(dir.clone()), b"cover");
+        std::fs::write(dir.join(&hash), b"tampered").unwrap();
+
+        let mut store = ArtworkStore::new(dir.clone());
+        assert_eq!(store.take_cached(&hash), None);
+        assert!(!dir.join(&hash).exists());
+        assert!(store.should_request(&hash));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cache_size_is_capped() {
+        let dir = temp_dir("capped");
+        let mut store = ArtworkStore::with_max_size(dir.clone(), 25);
+        let first = receive(&mut store, &[1; 10]);
+        let second = receive(&mut store, &[2; 10]);
+        // 修改时间精度不足时仍能区分先后
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        File::options()
+            .append(true)
+            .open(dir.join(&first))
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        let third = receive(&mut store, &[3; 10]);
+
+        assert!(!dir.join(&first).exists());
+        assert!(dir.join(&second).exists());
+        assert!(dir.join(&third).exists());
+
+        // 单个封面超出上限时仍保留
+        let mut store = ArtworkStore::with_max_size(dir.clone(), 5);
+        let large = receive(&mut store, &[4; 10]);
+        assert!(dir.join(&large).exists());
+        assert!(!dir.join(&second).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
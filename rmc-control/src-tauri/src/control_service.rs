@@ -1,10 +1,17 @@
-use crate::proto;
+use crate::artwork_store::ArtworkStore;
+use crate::proto::{self, Encoding, Packet};
 use anyhow::anyhow;
-use byteorder::{BigEndian, ByteOrder};
 use bytes::BytesMut;
+use hmac::{Hmac, Mac};
+use rmc_proto::codec::{encode_frame, FrameCodec};
+use rmc_proto::{type_name_of, RttEstimator};
 use serde::Serialize;
+use sha2::Sha256;
 use socket2::{SockRef, TcpKeepalive};
+use std::collections::BTreeSet;
 use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
@@ -13,11 +20,15 @@ use tokio::select;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::{mpsc, Mutex, RwLock};
 use tokio::time::{sleep, Instant};
+use tokio_util::codec::Decoder;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone)]
 pub enum ControlServiceStatus {
     Connected,
     Connecting,
+    /// 连接断开, 正在等待自动重连
+    Reconnecting,
     Disconnected,
 }
 
@@ -26,27 +37,72 @@ impl Display for ControlServiceStatus {
         match self {
             ControlServiceStatus::Connected => write!(f, "Connected"),
             ControlServiceStatus::Connecting => write!(f, "Connecting"),
+            ControlServiceStatus::Reconnecting => write!(f, "Reconnecting"),
             ControlServiceStatus::Disconnected => write!(f, "Disconnected"),
         }
     }
 }
 
+/// 重连等待时间的初始值, 每次失败翻倍
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+/// 重连等待时间的上限
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// 服务器拒绝了握手(协议版本、注册或认证), 重连也不会成功, 不再自动重连
+#[derive(Debug)]
+struct HandshakeRejected(String);
+
+impl Display for HandshakeRejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for HandshakeRejected {}
+
+/// 认证所用凭据
+struct Credential {
+    name: String,
+    secret: String,
+}
+
 pub struct ControlService {
     pub status: Arc<RwLock<ControlServiceStatus>>,
-    pub tx: RwLock<Option<UnboundedSender<proto::Message>>>,
+    pub tx: Arc<RwLock<Option<UnboundedSender<Packet>>>>,
+    /// 前端订阅的设备, 重连认证后重新订阅
+    subscriptions: Arc<Mutex<BTreeSet<String>>>,
+    /// 取消当前连接和自动重连
+    cancel: Mutex<Option<CancellationToken>>,
+    /// 封面磁盘缓存目录
+    artwork_dir: PathBuf,
 }
 
 impl ControlService {
-    pub fn new() -> Self {
+    pub fn new(artwork_dir: PathBuf) -> Self {
         Self {
             status: Arc::new(RwLock::new(ControlServiceStatus::Disconnected)),
-            tx: RwLock::new(None),
+            tx: Arc::new(RwLock::new(None)),
+            subscriptions: Arc::new(Mutex::new(BTreeSet::new())),
+            cancel: Mutex::new(None),
+            artwork_dir,
         }
     }
     pub async fn get_status(&self) -> String {
         self.status.read().await.to_string()
     }
 
+    /// 调整设备音量, 前端拖动滑块时会频繁调用, 由服务器合并后发给设备
+    pub async fn set_volume(
+        &self,
+        token: String,
+        change: proto::VolumeChange,
+    ) -> anyhow::Result<()> {
+        match *self.tx.read().await {
+            Some(ref tx) => Ok(tx.send(Packet::from(proto::SetVolumeRequest { token, change }))?),
+            None => Err(anyhow!("not connected")),
+        }
+    }
+
     pub async fn run(
         &self,
         mut read_js_rx: mpsc::Receiver<String>,
@@ -56,16 +112,43 @@ impl ControlService {
             let message: proto::Message = serde_json::from_str(&input)?;
             match message.name.as_str() {
                 "ConnectRequest" => self.on_connect_request(&message, &write_to_js_tx).await?,
-                _ => {
-                    if let Some(ref tx) = *self.tx.read().await {
-                        let _ = tx.send(message);
+                "DisconnectRequest" => {
+                    // 连接任务退出时发送 DisconnectNtf
+                    if let Some(cancel) = self.cancel.lock().await.take() {
+                        cancel.cancel();
                     }
                 }
+                // 其余消息原样转发给服务器
+                _ => match Packet::from_message(&message)? {
+                    Packet::Unknown(name) => println!("unknown message from js: {}", name),
+                    packet => {
+                        self.remember_subscriptions(&packet).await;
+                        if let Some(ref tx) = *self.tx.read().await {
+                            let _ = tx.send(packet);
+                        }
+                    }
+                },
             }
         }
         Ok(())
     }
 
+    /// 记录前端的订阅变化, 断线期间的修改在重连后生效
+    async fn remember_subscriptions(&self, packet: &Packet) {
+        let mut subscriptions = self.subscriptions.lock().await;
+        match packet {
+            Packet::SubscribeRequest(request) => {
+                subscriptions.extend(request.tokens.iter().cloned());
+            }
+            Packet::UnsubscribeRequest(request) => {
+                for token in request.tokens.iter() {
+                    subscriptions.remove(token);
+                }
+            }
+            _ => {}
+        }
+    }
+
     async fn on_connect_request(
         &self,
         message: &proto::Message,
@@ -86,55 +169,33 @@ impl ControlService {
                 )
                 .await?;
             }
-            ControlServiceStatus::Connecting => {}
+            ControlServiceStatus::Connecting | ControlServiceStatus::Reconnecting => {}
             ControlServiceStatus::Disconnected => {
                 let request: proto::ConnectRequest = serde_json::from_str(&message.data)?;
 
                 *self.status.write().await = ControlServiceStatus::Connecting;
                 match do_connect(&request.addr).await {
                     Ok(stream) => {
-                        let (tx, rx) = unbounded_channel();
-
-                        *self.tx.write().await = Some(tx);
-                        *self.status.write().await = ControlServiceStatus::Connected;
-                        send_message_to_js(
-                            &write_to_js_tx,
-                            &proto::ConnectResponse {
-                                ok: true,
-                                error: "".to_string(),
-                            },
-                        )
-                        .await?;
-
-                        let status = self.status.clone();
-                        let write_to_js_tx = write_to_js_tx.clone();
-                        tokio::spawn(async move {
-                            let (reader, writer) = tokio::io::split(stream);
-
-                            let writer = Arc::new(Mutex::new(writer));
-                            let last_active_time = Arc::new(RwLock::new(Instant::now()));
-
-                            let result;
-                            select! {
-                                r1= run_client(rx, reader, writer.clone(), last_active_time.clone(), write_to_js_tx.clone()) => { result = r1 },
-                                r2= ping_forever(writer, last_active_time.clone()) => { result = r2 },
-                            }
-
-                            *status.write().await = ControlServiceStatus::Disconnected;
-
-                            let reason;
-                            if let Err(err) = result {
-                                reason = err.to_string();
-                                println!("client error: {}", reason);
-                            } else {
-                                reason = String::from("");
-                            }
-                            let _ = send_message_to_js(
-                                &write_to_js_tx,
-                                &proto::DisconnectNtf { reason },
-                            )
-                            .await;
-                        });
+                        // 新的连接由前端重新订阅
+                        self.subscriptions.lock().await.clear();
+
+                        let cancel = CancellationToken::new();
+                        *self.cancel.lock().await = Some(cancel.clone());
+
+                        let connection = Connection {
+                            addr: request.addr,
+                            credential: Arc::new(Credential {
+                                name: request.name,
+                                secret: request.secret,
+                            }),
+                            artwork_dir: self.artwork_dir.clone(),
+                            status: self.status.clone(),
+                            tx: self.tx.clone(),
+                            subscriptions: self.subscriptions.clone(),
+                            write_to_js_tx: write_to_js_tx.clone(),
+                            cancel,
+                        };
+                        tokio::spawn(connection.run(stream));
                     }
                     Err(err) => {
                         *self.status.write().await = ControlServiceStatus::Disconnected;
@@ -154,20 +215,184 @@ impl ControlService {
     }
 }
 
+/// 用户发起的一次连接, 断线后自动重连, 直到收到 DisconnectRequest
+struct Connection {
+    addr: String,
+    credential: Arc<Credential>,
+    artwork_dir: PathBuf,
+    status: Arc<RwLock<ControlServiceStatus>>,
+    tx: Arc<RwLock<Option<UnboundedSender<Packet>>>>,
+    subscriptions: Arc<Mutex<BTreeSet<String>>>,
+    write_to_js_tx: mpsc::Sender<String>,
+    cancel: CancellationToken,
+}
+
+impl Connection {
+    async fn run(self, mut stream: TcpStream) {
+        let mut disconnect_reason = String::new();
+        // 连续重连失败的次数, 只有认证成功后才清零,
+        // 连接成功但握手前断开时不会重新从最短的等待时间开始
+        let mut attempt = 0;
+        loop {
+            let authenticated = Arc::new(AtomicBool::new(false));
+            let result;
+            select! {
+                r = self.run_session(stream, authenticated.clone()) => { result = r },
+                _ = self.cancel.cancelled() => break,
+            }
+            *self.tx.write().await = None;
+            if authenticated.load(Ordering::Relaxed) {
+                attempt = 0;
+            }
+
+            let reason = match result {
+                Ok(()) => String::from("connection closed"),
+                // 只有网络错误才重连, 握手被拒绝时断开并告知前端
+                Err(err) if err.is::<HandshakeRejected>() => {
+                    println!("handshake rejected: {}", err);
+                    disconnect_reason = err.to_string();
+                    break;
+                }
+                Err(err) => err.to_string(),
+            };
+            println!("client error: {}", reason);
+
+            match self.reconnect(reason, &mut attempt).await {
+                Some(new_stream) => stream = new_stream,
+                None => break,
+            }
+        }
+
+        *self.tx.write().await = None;
+        *self.status.write().await = ControlServiceStatus::Disconnected;
+        let _ = send_message_to_js(
+            &self.write_to_js_tx,
+            &proto::DisconnectNtf {
+                reason: disconnect_reason,
+            },
+        )
+        .await;
+    }
+
+    /// 每次连接成功后重新握手: 协商协议版本, 注册, 认证后恢复订阅,
+    /// 认证成功前不转发前端消息, 也不通知前端连接成功
+    async fn run_session(
+        &self,
+        stream: TcpStream,
+        authenticated: Arc<AtomicBool>,
+    ) -> anyhow::Result<()> {
+        let (tx, rx) = unbounded_channel();
+        // 连接后先协商协议版本, 再以控制端身份注册
+        tx.send(Packet::from(proto::Protocol::hello()))?;
+        tx.send(Packet::from(proto::RegisterDeviceRequest {
+            role: proto::DeviceRole::Controller,
+            token: "".to_string(),
+            device_name: device_name(),
+            client_version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: vec![],
+            encodings: vec![proto::ENCODING_MSGPACK.to_string()],
+        }))?;
+
+        let (reader, writer) = tokio::io::split(stream);
+
+        let writer = Arc::new(Mutex::new(writer));
+        let last_active_time = Arc::new(RwLock::new(Instant::now()));
+        let encoding = Arc::new(RwLock::new(Encoding::Json));
+        let context = SessionContext {
+            credential: self.credential.clone(),
+            artworks: ArtworkStore::new(self.artwork_dir.clone()),
+            subscriptions: self.subscriptions.clone(),
+            rtt: RttEstimator::default(),
+            status: self.status.clone(),
+            shared_tx: self.tx.clone(),
+            session_tx: tx,
+            authenticated,
+        };
+
+        let result;
+        select! {
+            r1= run_client(rx, reader, writer.clone(), last_active_time.clone(), encoding.clone(), self.write_to_js_tx.clone(), context) => { result = r1 },
+            r2= ping_forever(writer, last_active_time.clone(), encoding) => { result = r2 },
+        }
+        result
+    }
+
+    /// 按指数退避重连, 被取消时返回 None
+    async fn reconnect(&self, mut reason: String, attempt: &mut u32) -> Option<TcpStream> {
+        *self.status.write().await = ControlServiceStatus::Reconnecting;
+
+        loop {
+            *attempt += 1;
+            let attempt = *attempt;
+            let delay = reconnect_delay(attempt);
+            let _ = send_message_to_js(
+                &self.write_to_js_tx,
+                &proto::ReconnectingNtf {
+                    attempt,
+                    delay_ms: delay.as_millis() as u64,
+                    reason,
+                },
+            )
+            .await;
+
+            let result;
+            select! {
+                r = async {
+                    sleep(delay).await;
+                    do_connect(&self.addr).await
+                } => { result = r },
+                _ = self.cancel.cancelled() => return None,
+            }
+            match result {
+                Ok(stream) => return Some(stream),
+                Err(err) => {
+                    reason = err.to_string();
+                    println!("reconnect attempt {} failed: {}", attempt, reason);
+                }
+            }
+        }
+    }
+}
+
+/// 第 attempt 次重连前的等待时间, 在退避时间的一半到全部之间随机取值,
+/// 避免服务器重启后所有控制端同时重连
+fn reconnect_delay(attempt: u32) -> Duration {
+    let backoff = RECONNECT_BASE_DELAY
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX_DELAY);
+    let half = backoff / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
+/// 单个连接内处理服务器消息所需的状态
+struct SessionContext {
+    credential: Arc<Credential>,
+    artworks: ArtworkStore,
+    subscriptions: Arc<Mutex<BTreeSet<String>>>,
+    rtt: RttEstimator,
+    status: Arc<RwLock<ControlServiceStatus>>,
+    /// 认证成功后放入, 供前端消息转发给服务器
+    shared_tx: Arc<RwLock<Option<UnboundedSender<Packet>>>>,
+    session_tx: UnboundedSender<Packet>,
+    authenticated: Arc<AtomicBool>,
+}
+
 async fn run_client<S>(
-    rx: UnboundedReceiver<proto::Message>,
+    rx: UnboundedReceiver<Packet>,
     reader: ReadHalf<S>,
     writer: Arc<Mutex<WriteHalf<S>>>,
     last_active_time: Arc<RwLock<Instant>>,
+    encoding: Arc<RwLock<Encoding>>,
     write_to_js_tx: mpsc::Sender<String>,
+    context: SessionContext,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Send + 'static,
 {
     let result;
     select! {
-        r1 = poll_read(reader, writer.clone(), last_active_time, write_to_js_tx) => { result = r1 }
-        r2 = poll_write(rx, writer) => { result = r2 }
+        r1 = poll_read(reader, writer.clone(), last_active_time, encoding.clone(), write_to_js_tx, context) => { result = r1 }
+        r2 = poll_write(rx, writer, encoding) => { result = r2 }
     }
     result
 }
@@ -176,13 +401,16 @@ async fn poll_read<S>(
     mut reader: ReadHalf<S>,
     writer: Arc<Mutex<WriteHalf<S>>>,
     last_active_time: Arc<RwLock<Instant>>,
+    encoding: Arc<RwLock<Encoding>>,
     write_to_js_tx: mpsc::Sender<String>,
+    mut context: SessionContext,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Send + 'static,
 {
     const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
     let mut buffer = BytesMut::with_capacity(65536);
+    let mut codec = FrameCodec::default();
     loop {
         let len = reader.read_buf(&mut buffer).await?;
         // len为0表示对端已经关闭连接。
@@ -200,13 +428,13 @@ where
                     break;
                 }
 
-                let result = try_extract_frame(&mut buffer)?;
+                let result = codec.decode(&mut buffer)?;
                 if let Some(frame) = result {
-                    let str = String::from_utf8(frame)?;
-                    let message: proto::Message = serde_json::from_str(&str)?;
+                    let packet = Packet::decode(&frame)?;
 
                     // 收到完整消息
-                    on_recv_message(&writer, message, &write_to_js_tx).await?;
+                    on_recv_message(&writer, &encoding, packet, &write_to_js_tx, &mut context)
+                        .await?;
                 } else {
                     // 消息包接收还未完成
                     break;
@@ -218,70 +446,137 @@ where
 
 async fn on_recv_message<S>(
     writer: &Arc<Mutex<WriteHalf<S>>>,
-    message: proto::Message,
+    encoding: &Arc<RwLock<Encoding>>,
+    packet: Packet,
     write_to_js_tx: &mpsc::Sender<String>,
+    context: &mut SessionContext,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Send + 'static,
 {
-    match message.name.as_str() {
-        "Ping" => {
-            let ping: proto::Ping = serde_json::from_str(&message.data)?;
-            send_message_to_server(writer, &proto::Pong { time: ping.time }).await?;
+    match packet {
+        Packet::AuthChallengeNtf(challenge) => {
+            send_message_to_server(
+                writer,
+                encoding,
+                proto::AuthRequest {
+                    name: context.credential.name.clone(),
+                    proof: compute_proof(&context.credential.secret, &challenge.nonce).into(),
+                },
+            )
+            .await?;
         }
-        _ => {
-            let str = serde_json::to_string(&message)?;
-            write_to_js_tx.send(str).await?;
+        Packet::Ping(ping) => {
+            send_message_to_server(writer, encoding, proto::Pong { time: ping.time }).await?;
         }
-    }
-
-    Ok(())
-}
-
-/// 数据粘包处理
-///
-/// 注意：这个函数只能使用消耗 buffer 数据的函数，否则框架会一直循环调用本函数来驱动处理消息
-///
-fn try_extract_frame(buffer: &mut BytesMut) -> anyhow::Result<Option<Vec<u8>>> {
-    // 数据小于4字节,继续读取数据
-    if buffer.len() < 4 {
-        return Ok(None);
-    }
-
-    // 读取包长度
-    let buf = buffer.get(0..4).unwrap();
-    let len = BigEndian::read_u32(buf) as usize;
-
-    // 超出最大限制
-    if len <= 0 || len >= 1024 * 1024 * 2 {
-        return Err(anyhow!("Message too long"));
-    }
+        Packet::Pong(pong) => {
+            // Pong 带回发送 Ping 时的时间
+            let now = now_millis();
+            if pong.time <= now {
+                context.rtt.update(Duration::from_millis(now - pong.time));
+                send_message_to_js(
+                    write_to_js_tx,
+                    &proto::ConnectionQualityNtf {
+                        rtt_ms: context.rtt.srtt_ms().unwrap_or_default(),
+                        jitter_ms: context.rtt.jitter_ms().unwrap_or_default(),
+                    },
+                )
+                .await?;
+            }
+        }
+        Packet::HelloResponse(response) => {
+            if !response.ok {
+                return Err(HandshakeRejected(response.error).into());
+            }
+        }
+        Packet::RegisterDeviceResponse(ref response) => {
+            if response.ok {
+                *encoding.write().await = Encoding::from_name(&response.encoding);
+            }
+            send_packet_to_js(write_to_js_tx, &packet).await?;
+            if !response.ok {
+                return Err(HandshakeRejected(response.error.clone()).into());
+            }
+        }
+        Packet::AuthResponse(ref response) => {
+            // 重连后恢复断线前的订阅, 首次连接时为空, 由前端订阅
+            if response.ok {
+                *context.shared_tx.write().await = Some(context.session_tx.clone());
+                *context.status.write().await = ControlServiceStatus::Connected;
+                context.authenticated.store(true, Ordering::Relaxed);
+                send_message_to_js(
+                    write_to_js_tx,
+                    &proto::ConnectResponse {
+                        ok: true,
+                        error: "".to_string(),
+                    },
+                )
+                .await?;
 
-    // 数据不够,继续读取数据
-    if buffer.len() < 4 + len {
-        return Ok(None);
+                let tokens: Vec<String> =
+                    context.subscriptions.lock().await.iter().cloned().collect();
+                if !tokens.is_empty() {
+                    send_message_to_server(writer, encoding, proto::SubscribeRequest { tokens })
+                        .await?;
+                }
+            }
+            send_packet_to_js(write_to_js_tx, &packet).await?;
+            if !response.ok {
+                return Err(HandshakeRejected(response.error.clone()).into());
+            }
+        }
+        Packet::MediaKeyEventAck(ack) => {
+            send_message_to_js(
+                write_to_js_tx,
+                &proto::MediaKeyEventOutcomeNtf {
+                    request_id: ack.request_id,
+                    token: ack.token,
+                    outcome: ack.result,
+                    error: ack.error,
+                },
+            )
+            .await?;
+        }
+        Packet::NowPlayingNtf(ref now_playing) => {
+            send_packet_to_js(write_to_js_tx, &packet).await?;
+
+            // 封面优先从磁盘缓存读取, 没有时向服务器请求
+            let hash = &now_playing.artwork_hash;
+            if let Some(data) = context.artworks.take_cached(hash) {
+                send_message_to_js(write_to_js_tx, &proto::ArtworkNtf::new(hash.clone(), &data))
+                    .await?;
+            } else if context.artworks.should_request(hash) {
+                send_message_to_server(
+                    writer,
+                    encoding,
+                    proto::ArtworkRequest { hash: hash.clone() },
+                )
+                .await?;
+            }
+        }
+        Packet::ArtworkResponse(response) => context.artworks.on_response(&response),
+        Packet::ArtworkChunk(chunk) => {
+            if let Some((hash, data)) = context.artworks.on_chunk(chunk) {
+                send_message_to_js(write_to_js_tx, &proto::ArtworkNtf::new(hash, &data)).await?;
+            }
+        }
+        Packet::Unknown(name) => println!("unknown message from server: {}", name),
+        _ => send_packet_to_js(write_to_js_tx, &packet).await?,
     }
 
-    // 拆出这个包的数据
-    let frame = buffer.split_to(4 + len).split_off(4).to_vec();
-
-    Ok(Some(frame))
+    Ok(())
 }
 
 async fn poll_write<S>(
-    mut rx: UnboundedReceiver<proto::Message>,
+    mut rx: UnboundedReceiver<Packet>,
     writer: Arc<Mutex<WriteHalf<S>>>,
+    encoding: Arc<RwLock<Encoding>>,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Send + 'static,
 {
-    while let Some(message) = rx.recv().await {
-        let str = serde_json::to_string(&message)?;
-
-        let mut buf = Vec::with_capacity(str.len() + 4);
-        byteorder::WriteBytesExt::write_u32::<BigEndian>(&mut buf, str.len() as u32)?;
-        buf.extend_from_slice(str.as_bytes());
-
+    while let Some(packet) = rx.recv().await {
+        let buf = encode_frame(&packet.encode(*encoding.read().await)?)?;
         writer.lock().await.write_all(&buf).await?;
     }
     Ok(())
@@ -290,16 +585,21 @@ where
 async fn ping_forever<S>(
     writer: Arc<Mutex<WriteHalf<S>>>,
     last_active_time: Arc<RwLock<Instant>>,
+    encoding: Arc<RwLock<Encoding>>,
 ) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Send + 'static,
 {
     const PING_INTERVAL: Duration = Duration::from_secs(5);
     const PING_TIMEOUT: Duration = Duration::from_secs(15);
+    let mut last_ping_time = Instant::now();
     loop {
         sleep(Duration::from_secs(1)).await;
 
-        if last_active_time.read().await.elapsed() < PING_INTERVAL {
+        // 连接活跃时也定期发送心跳, 用于测量往返时间
+        if last_active_time.read().await.elapsed() < PING_INTERVAL
+            && last_ping_time.elapsed() < PING_INTERVAL
+        {
             continue;
         }
 
@@ -307,19 +607,19 @@ where
             return Err(anyhow!("ping timeout"));
         }
 
-        // 获取当前时间
-        let now = SystemTime::now();
-
-        // 计算自UNIX_EPOCH以来的持续时间
-        let since_epoch = now.duration_since(UNIX_EPOCH).expect("Time went backwards");
-
-        // 将时间转换为毫秒
-        let nanos = since_epoch.as_millis();
-
-        send_message_to_server(&writer, &proto::Ping { time: nanos as u64 }).await?;
+        last_ping_time = Instant::now();
+        send_message_to_server(&writer, &encoding, proto::Ping { time: now_millis() }).await?;
     }
 }
 
+/// 当前时间(毫秒时间戳)
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
 async fn do_connect<A: ToSocketAddrs>(addr: &A) -> anyhow::Result<TcpStream> {
     let stream = TcpStream::connect(&addr).await?;
 
@@ -329,47 +629,263 @@ async fn do_connect<A: ToSocketAddrs>(addr: &A) -> anyhow::Result<TcpStream> {
     Ok(stream)
 }
 
-fn type_name_of<T>() -> &'static str {
-    let full_type_name = std::any::type_name::<T>();
-    full_type_name
-        .rsplit("::")
-        .next()
-        .unwrap_or_else(|| full_type_name)
-}
-
-async fn send_message_to_server<T, U>(
+async fn send_message_to_server<T>(
     writer: &Arc<Mutex<WriteHalf<T>>>,
-    data: &U,
+    encoding: &Arc<RwLock<Encoding>>,
+    packet: impl Into<Packet>,
 ) -> anyhow::Result<()>
 where
     T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let frame = packet.into().encode(*encoding.read().await)?;
+    writer
+        .lock()
+        .await
+        .write_all(&encode_frame(&frame)?)
+        .await?;
+    Ok(())
+}
+
+fn make_message<U>(data: &U) -> anyhow::Result<proto::Message>
+where
     U: Serialize,
 {
-    let message = proto::Message {
+    Ok(proto::Message {
         name: type_name_of::<U>().to_string(),
         data: serde_json::to_string(data)?,
-    };
-
-    let str = serde_json::to_string(&message)?;
+    })
+}
 
-    let mut buf = Vec::with_capacity(str.len() + 4);
-    byteorder::WriteBytesExt::write_u32::<BigEndian>(&mut buf, str.len() as u32)?;
-    buf.extend_from_slice(str.as_bytes());
+/// 认证证明: hex(HMAC-SHA256(secret, nonce))
+fn compute_proof(secret: &str, nonce: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(nonce.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
 
-    writer.lock().await.write_all(&buf).await?;
-    Ok(())
+fn device_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "rmc-control".to_string())
 }
 
 async fn send_message_to_js<U>(tx: &mpsc::Sender<String>, data: &U) -> anyhow::Result<()>
 where
     U: Serialize,
 {
-    let message = proto::Message {
-        name: type_name_of::<U>().to_string(),
-        data: serde_json::to_string(data)?,
-    };
+    let message = make_message(data)?;
 
     let str = serde_json::to_string(&message)?;
     tx.send(str).await?;
     Ok(())
 }
+
+/// 服务器消息统一转换为 JSON 形式交给前端
+async fn send_packet_to_js(tx: &mpsc::Sender<String>, packet: &Packet) -> anyhow::Result<()> {
+    let str = serde_json::to_string(&packet.to_message()?)?;
+    tx.send(str).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn connection(addr: String, write_to_js_tx: mpsc::Sender<String>) -> Connection {
+        Connection {
+            addr,
+            credential: Arc::new(Credential {
+                name: "test".to_string(),
+                secret: "secret".to_string(),
+            }),
+            artwork_dir: std::env::temp_dir().join("rmc-control-test"),
+            status: Arc::new(RwLock::new(ControlServiceStatus::Connected)),
+            tx: Arc::new(RwLock::new(None)),
+            subscriptions: Arc::new(Mutex::new(BTreeSet::new())),
+            write_to_js_tx,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// 服务器依次发送 packets 后保持连接, 返回客户端一侧的连接
+    async fn serve(packets: Vec<Packet>) -> (String, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        tokio::spawn(async move {
+            for packet in packets {
+                let frame = encode_frame(&packet.encode(Encoding::Json).unwrap()).unwrap();
+                server.write_all(&frame).await.unwrap();
+            }
+            // 保持连接直到客户端断开
+            let _ = server.read(&mut [0; 1024]).await;
+            let _ = listener;
+        });
+        (addr, stream)
+    }
+
+    /// 读取发给前端的消息直到名称为 name, 返回之前收到的消息名和该消息
+    async fn read_until(
+        rx: &mut mpsc::Receiver<String>,
+        name: &str,
+    ) -> (Vec<String>, proto::Message) {
+        let mut skipped = vec![];
+        loop {
+            let input = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .expect("timed out")
+                .expect("channel closed");
+            let message: proto::Message = serde_json::from_str(&input).unwrap();
+            if message.name == name {
+                return (skipped, message);
+            }
+            skipped.push(message.name);
+        }
+    }
+
+    #[test]
+    fn reconnect_delay_is_bounded() {
+        for attempt in [0, 1, 2, 3, 6, 7, 17, 100, u32::MAX] {
+            let backoff = RECONNECT_BASE_DELAY
+                .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+                .min(RECONNECT_MAX_DELAY);
+            for _ in 0..100 {
+                let delay = reconnect_delay(attempt);
+                assert!(
+                    delay >= backoff / 2 && delay <= backoff,
+                    "attempt {attempt}: {delay:?}"
+                );
+            }
+        }
+        assert!(reconnect_delay(1) <= RECONNECT_BASE_DELAY);
+        assert!(reconnect_delay(u32::MAX) >= RECONNECT_MAX_DELAY / 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_handshake_is_not_retried() {
+        let rejections = [
+            Packet::from(proto::HelloResponse {
+                ok: false,
+                error: "unsupported protocol version".to_string(),
+                protocol_version: 0,
+                features: vec![],
+            }),
+            Packet::from(proto::AuthResponse {
+                ok: false,
+                error: "authentication failed".to_string(),
+            }),
+        ];
+        for rejection in rejections {
+            let (addr, stream) = serve(vec![rejection]).await;
+            let (write_to_js_tx, mut write_to_js_rx) = mpsc::channel(16);
+            let connection = connection(addr, write_to_js_tx);
+            let status = connection.status.clone();
+            tokio::spawn(connection.run(stream));
+
+            let (skipped, message) = read_until(&mut write_to_js_rx, "DisconnectNtf").await;
+            assert!(!skipped.contains(&"ReconnectingNtf".to_string()));
+            let ntf: proto::DisconnectNtf = serde_json::from_str(&message.data).unwrap();
+            assert!(!ntf.reason.is_empty());
+            assert!(matches!(
+                *status.read().await,
+                ControlServiceStatus::Disconnected
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn connected_only_after_authentication() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        // 前两次连接在认证前断开, 第三次认证成功后断开
+        tokio::spawn(async move {
+            for i in 0..3 {
+                let (mut server, _) = listener.accept().await.unwrap();
+                let mut packets = vec![
+                    Packet::from(proto::HelloResponse {
+                        ok: true,
+                        error: "".to_string(),
+                        protocol_version: proto::PROTOCOL_VERSION,
+                        features: vec![],
+                    }),
+                    Packet::from(proto::RegisterDeviceResponse {
+                        ok: true,
+                        error: "".to_string(),
+                        encoding: "".to_string(),
+                    }),
+                ];
+                if i == 2 {
+                    packets.push(Packet::from(proto::AuthResponse {
+                        ok: true,
+                        error: "".to_string(),
+                    }));
+                }
+                for packet in packets {
+                    let frame = encode_frame(&packet.encode(Encoding::Json).unwrap()).unwrap();
+                    server.write_all(&frame).await.unwrap();
+                }
+                server.shutdown().await.unwrap();
+                let _ = server.read_to_end(&mut vec![]).await;
+            }
+        });
+
+        let (write_to_js_tx, mut write_to_js_rx) = mpsc::channel(16);
+        let connection = connection(addr, write_to_js_tx);
+        let cancel = connection.cancel.clone();
+        tokio::spawn(connection.run(stream));
+
+        for attempt in 1..=2 {
+            let (skipped, message) = read_until(&mut write_to_js_rx, "ReconnectingNtf").await;
+            assert!(!skipped.contains(&"ConnectResponse".to_string()));
+            let ntf: proto::ReconnectingNtf = serde_json::from_str(&message.data).unwrap();
+            assert_eq!(ntf.attempt, attempt);
+        }
+
+        let (_, message) = read_until(&mut write_to_js_rx, "ConnectResponse").await;
+        let response: proto::ConnectResponse = serde_json::from_str(&message.data).unwrap();
+        assert!(response.ok);
+        read_until(&mut write_to_js_rx, "AuthResponse").await;
+        // 认证成功后重新从第一次开始计数
+        let (_, message) = read_until(&mut write_to_js_rx, "ReconnectingNtf").await;
+        let ntf: proto::ReconnectingNtf = serde_json::from_str(&message.data).unwrap();
+        assert_eq!(ntf.attempt, 1);
+        cancel.cancel();
+    }
+
+    #[tokio::test]
+    async fn cancel_stops_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        // 服务器关闭连接, 客户端开始重连
+        drop(listener.accept().await.unwrap());
+
+        let (write_to_js_tx, mut write_to_js_rx) = mpsc::channel(16);
+        let connection = connection(addr, write_to_js_tx);
+        let cancel = connection.cancel.clone();
+        let status = connection.status.clone();
+        let task = tokio::spawn(connection.run(stream));
+
+        let (_, message) = read_until(&mut write_to_js_rx, "ReconnectingNtf").await;
+        let ntf: proto::ReconnectingNtf = serde_json::from_str(&message.data).unwrap();
+        assert_eq!(ntf.attempt, 1);
+
+        cancel.cancel();
+        let (skipped, message) = read_until(&mut write_to_js_rx, "DisconnectNtf").await;
+        assert!(skipped.is_empty());
+        let ntf: proto::DisconnectNtf = serde_json::from_str(&message.data).unwrap();
+        assert_eq!(ntf.reason, "");
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            *status.read().await,
+            ControlServiceStatus::Disconnected
+        ));
+    }
+}
@@ -3,11 +3,13 @@
 
 use crate::control_service::ControlService;
 use log::info;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::Manager;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 
+mod artwork_store;
 mod control_service;
 mod proto;
 
@@ -20,14 +22,18 @@ fn main() {
     let (async_proc_input_tx, async_proc_input_rx) = mpsc::channel(1);
     let (async_proc_output_tx, mut async_proc_output_rx) = mpsc::channel(1);
 
-    let control_service = Arc::new(ControlService::new());
+    let control_service = Arc::new(ControlService::new(artwork_dir()));
 
     tauri::Builder::default()
         .manage(AsyncProcInputTx {
             inner: Mutex::new(async_proc_input_tx),
             control_service: control_service.clone(),
         })
-        .invoke_handler(tauri::generate_handler![js2rs, get_control_service_status])
+        .invoke_handler(tauri::generate_handler![
+            js2rs,
+            get_control_service_status,
+            set_volume
+        ])
         .setup(|app| {
             tauri::async_runtime::spawn(async move {
                 control_service
@@ -49,6 +55,14 @@ fn main() {
         .expect("error while running tauri application");
 }
 
+/// 封面磁盘缓存目录
+fn artwork_dir() -> PathBuf {
+    tauri::api::path::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("rmc-control")
+        .join("artwork")
+}
+
 #[tauri::command]
 async fn get_control_service_status(
     state: tauri::State<'_, AsyncProcInputTx>,
@@ -56,6 +70,19 @@ async fn get_control_service_status(
     Ok(state.control_service.get_status().await)
 }
 
+#[tauri::command]
+async fn set_volume(
+    token: String,
+    change: proto::VolumeChange,
+    state: tauri::State<'_, AsyncProcInputTx>,
+) -> Result<(), String> {
+    state
+        .control_service
+        .set_volume(token, change)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn js2rs(message: String, state: tauri::State<'_, AsyncProcInputTx>) -> Result<(), String> {
     info!("js2rs {}", message);
@@ -1,14 +1,14 @@
-#[derive(serde::Serialize, serde::Deserialize)]
-pub struct Message {
-    pub name: String,
-    pub data: String,
-}
+use base64::prelude::{Engine as _, BASE64_STANDARD};
 
 //////////////////////////////////////////////// local ////////////////////////////////////////////////
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct ConnectRequest {
     pub addr: String,
+    /// 凭据名称
+    pub name: String,
+    /// 凭据密钥, 只用于计算认证证明, 不会发送给服务器
+    pub secret: String,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -22,22 +22,53 @@ pub struct DisconnectNtf {
     pub reason: String,
 }
 
-//////////////////////////////////////////////// server ////////////////////////////////////////////////
+/// 主动断开连接并停止自动重连
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct DisconnectRequest {}
+
+/// 连接断开, 等待 delay_ms 后进行第 attempt 次重连
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ReconnectingNtf {
+    pub attempt: u32,
+    pub delay_ms: u64,
+    pub reason: String,
+}
 
+/// 按键事件的最终执行结果
 #[derive(serde::Serialize, serde::Deserialize)]
-pub struct Ping {
-    pub time: u64,
+pub struct MediaKeyEventOutcomeNtf {
+    pub request_id: u64,
+    pub token: String,
+    pub outcome: MediaKeyEventResult,
+    pub error: String,
 }
 
+/// 与服务器之间的连接质量, 每次收到心跳回复时更新
 #[derive(serde::Serialize, serde::Deserialize)]
-pub struct Pong {
-    pub time: u64,
+pub struct ConnectionQualityNtf {
+    /// 平滑往返时间(毫秒)
+    pub rtt_ms: u64,
+    /// 往返时间的抖动(毫秒)
+    pub jitter_ms: u64,
 }
 
+/// 封面数据, 前端转换为图片显示
 #[derive(serde::Serialize, serde::Deserialize)]
-pub struct SendControlMediaKeyEventRequest {
-    pub action: u32,
-    pub code: u32,
-    pub token: String,
-    pub authorization_code: String,
+pub struct ArtworkNtf {
+    pub hash: String,
+    /// base64编码, 避免字节数组序列化为数字数组
+    pub data: String,
 }
+
+impl ArtworkNtf {
+    pub fn new(hash: String, data: &[u8]) -> Self {
+        Self {
+            hash,
+            data: BASE64_STANDARD.encode(data),
+        }
+    }
+}
+
+//////////////////////////////////////////////// server ////////////////////////////////////////////////
+
+pub use rmc_proto::*;
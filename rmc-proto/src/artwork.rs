@@ -0,0 +1,145 @@
+use crate::messages::ArtworkChunk;
+use sha2::{Digest, Sha256};
+
+/// 单个分片的最大字节数, JSON 编码后仍远小于默认的消息大小上限
+pub const ARTWORK_CHUNK_SIZE: usize = 32 * 1024;
+
+/// 单个封面的最大字节数
+pub const MAX_ARTWORK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// 封面内容哈希: SHA-256 十六进制小写
+pub fn artwork_hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// 把封面拆分为按顺序发送的分片
+pub fn artwork_chunks<'a>(
+    hash: &'a str,
+    data: &'a [u8],
+) -> impl Iterator<Item = ArtworkChunk> + 'a {
+    data.chunks(ARTWORK_CHUNK_SIZE)
+        .enumerate()
+        .map(move |(index, chunk)| ArtworkChunk {
+            hash: hash.to_string(),
+            total_size: data.len() as u64,
+            offset: (index * ARTWORK_CHUNK_SIZE) as u64,
+            data: chunk.to_vec(),
+        })
+}
+
+/// 按顺序接收分片并重组, offset 为0的分片开始新的传输并丢弃未完成的数据
+#[derive(Default)]
+pub struct ArtworkAssembler {
+    hash: String,
+    total_size: u64,
+    data: Vec<u8>,
+}
+
+impl ArtworkAssembler {
+    /// 正在接收的封面哈希, 空闲时为空
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// 收到一个分片, 接收完成且哈希校验通过时返回 (hash, 完整数据)
+    ///
+    /// 分片不合法时丢弃已接收的数据并返回错误说明
+    pub fn push(&mut self, chunk: ArtworkChunk) -> Result<Option<(String, Vec<u8>)>, String> {
+        let result = self.try_push(chunk);
+        if !matches!(result, Ok(None)) {
+            *self = Self::default();
+        }
+        result
+    }
+
+    fn try_push(&mut self, chunk: ArtworkChunk) -> Result<Option<(String, Vec<u8>)>, String> {
+        let ArtworkChunk {
+            hash,
+            total_size,
+            offset,
+            data,
+        } = chunk;
+
+        if offset == 0 {
+            if total_size == 0 || total_size > MAX_ARTWORK_SIZE {
+                return Err(format!("invalid artwork size: {total_size}"));
+            }
+            self.hash = hash;
+            self.total_size = total_size;
+            self.data = Vec::with_capacity(total_size as usize);
+        } else if hash != self.hash
+            || total_size != self.total_size
+            || offset != self.data.len() as u64
+        {
+            return Err(format!("unexpected artwork chunk: {hash} at {offset}"));
+        }
+
+        if data.is_empty() || (self.data.len() + data.len()) as u64 > self.total_size {
+            return Err(format!("invalid artwork chunk length: {}", data.len()));
+        }
+        self.data.extend_from_slice(&data);
+        if (self.data.len() as u64) < self.total_size {
+            return Ok(None);
+        }
+
+        if artwork_hash(&self.data) != self.hash {
+            return Err(format!("artwork hash mismatch: {}", self.hash));
+        }
+        Ok(Some((
+            std::mem::take(&mut self.hash),
+            std::mem::take(&mut self.data),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artwork() -> Vec<u8> {
+        (0..ARTWORK_CHUNK_SIZE * 2 + 100)
+            .map(|x| (x % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn chunks_reassemble_to_original() {
+        let data = artwork();
+        let hash = artwork_hash(&data);
+        let chunks: Vec<ArtworkChunk> = artwork_chunks(&hash, &data).collect();
+        assert_eq!(chunks.len(), 3);
+
+        let mut assembler = ArtworkAssembler::default();
+        assert_eq!(assembler.push(chunks[0].clone()), Ok(None));
+        assert_eq!(assembler.hash(), hash);
+        assert_eq!(assembler.push(chunks[1].clone()), Ok(None));
+        assert_eq!(
+            assembler.push(chunks[2].clone()),
+            Ok(Some((hash.clone(), data)))
+        );
+        assert_eq!(assembler.hash(), "");
+    }
+
+    #[test]
+    fn out_of_order_or_corrupted_chunks_are_rejected() {
+        let data = artwork();
+        let hash = artwork_hash(&data);
+        let chunks: Vec<ArtworkChunk> = artwork_chunks(&hash, &data).collect();
+
+        let mut assembler = ArtworkAssembler::default();
+        assert!(assembler.push(chunks[1].clone()).is_err());
+
+        // 重新从 offset 0 开始, 最后一片内容被篡改
+        assembler.push(chunks[0].clone()).unwrap();
+        assembler.push(chunks[1].clone()).unwrap();
+        let mut last = chunks[2].clone();
+        last.data[0] ^= 1;
+        let error = assembler.push(last).unwrap_err();
+        assert!(error.contains("hash mismatch"));
+        assert_eq!(assembler.hash(), "");
+
+        let mut oversized = chunks[0].clone();
+        oversized.total_size = MAX_ARTWORK_SIZE + 1;
+        assert!(assembler.push(oversized).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::anyhow;
+use byteorder::{BigEndian, ByteOrder};
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// 默认单个消息最大字节数
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024 * 2;
+
+/// 4字节大端长度前缀分帧
+///
+/// 注意：decode 只能使用消耗 buffer 数据的函数，否则调用方会一直循环调用来驱动处理消息
+#[derive(Clone, Copy, Debug)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Vec<u8>;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> anyhow::Result<Option<Vec<u8>>> {
+        // 数据小于4字节,继续读取数据
+        if buffer.len() < 4 {
+            return Ok(None);
+        }
+
+        // 读取包长度
+        let len = BigEndian::read_u32(&buffer[0..4]) as usize;
+
+        // 超出最大限制
+        if len == 0 || len > self.max_frame_size {
+            return Err(anyhow!("Message too long"));
+        }
+
+        // 数据不够,继续读取数据
+        if buffer.len() < 4 + len {
+            buffer.reserve(4 + len - buffer.len());
+            return Ok(None);
+        }
+
+        // 拆出这个包的数据
+        Ok(Some(buffer.split_to(4 + len).split_off(4).to_vec()))
+    }
+}
+
+impl Encoder<&[u8]> for FrameCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, frame: &[u8], buffer: &mut BytesMut) -> anyhow::Result<()> {
+        if frame.is_empty() || frame.len() > self.max_frame_size {
+            return Err(anyhow!("Message too long"));
+        }
+        buffer.reserve(4 + frame.len());
+        buffer.put_u32(frame.len() as u32);
+        buffer.put_slice(frame);
+        Ok(())
+    }
+}
+
+/// 加上长度前缀, 用于直接写入流或发送通道
+pub fn encode_frame(frame: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = BytesMut::new();
+    FrameCodec::new(usize::MAX).encode(frame, &mut buffer)?;
+    Ok(buffer.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Ping;
+    use crate::packet::{Encoding, Packet};
+
+    #[test]
+    fn split_and_sticky_frames() {
+        let frame = Packet::from(Ping { time: 3 })
+            .encode(Encoding::MessagePack)
+            .unwrap();
+        let encoded = encode_frame(&frame).unwrap();
+
+        // 两个消息粘在一起, 逐字节送入
+        let mut codec = FrameCodec::default();
+        let mut buffer = BytesMut::new();
+        let mut frames = Vec::new();
+        for byte in encoded.iter().chain(encoded.iter()) {
+            buffer.put_u8(*byte);
+            while let Some(frame) = codec.decode(&mut buffer).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, vec![frame.clone(), frame]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn oversized_and_empty_frames_are_rejected() {
+        let mut codec = FrameCodec::new(16);
+        let mut buffer = BytesMut::from(&[0u8, 0, 0, 17][..]);
+        assert!(codec.decode(&mut buffer).is_err());
+        let mut buffer = BytesMut::from(&[0u8, 0, 0, 0][..]);
+        assert!(codec.decode(&mut buffer).is_err());
+        assert!(codec.encode(&[0u8; 17][..], &mut BytesMut::new()).is_err());
+    }
+}
@@ -0,0 +1,32 @@
+//! rmc-server 与 rmc-control 共用的协议定义
+//!
+//! - 消息类型, [`MediaCommand`] 媒体控制指令
+//! - [`Packet`] 所有消息的枚举, 负责 JSON / MessagePack 编解码
+//! - [`codec::FrameCodec`] 4字节大端长度前缀分帧
+//! - [`Protocol`] 协议版本和功能协商
+//! - [`ArtworkAssembler`] 封面分片的拆分和重组
+//! - [`RttEstimator`] 根据心跳计算往返时间和抖动
+//! - [`Secret`] 不在日志中显示的密钥、认证证明
+
+mod artwork;
+pub mod codec;
+mod media;
+mod messages;
+mod packet;
+mod rtt;
+mod secret;
+mod version;
+
+pub use artwork::*;
+pub use media::*;
+pub use messages::*;
+pub use packet::{Encoding, Packet, ENCODING_JSON, ENCODING_MSGPACK};
+pub use rtt::RttEstimator;
+pub use secret::Secret;
+pub use version::*;
+
+/// 类型名(不含路径), 用作消息名
+pub fn type_name_of<T>() -> &'static str {
+    let full_type_name = std::any::type_name::<T>();
+    full_type_name.rsplit("::").next().unwrap_or(full_type_name)
+}
@@ -0,0 +1,189 @@
+/// KeyEvent.ACTION_DOWN
+pub const ACTION_DOWN: u32 = 0;
+/// KeyEvent.ACTION_UP
+pub const ACTION_UP: u32 = 1;
+
+pub const KEYCODE_VOLUME_UP: u32 = 24;
+pub const KEYCODE_VOLUME_DOWN: u32 = 25;
+pub const KEYCODE_MEDIA_PLAY_PAUSE: u32 = 85;
+pub const KEYCODE_MEDIA_STOP: u32 = 86;
+pub const KEYCODE_MEDIA_NEXT: u32 = 87;
+pub const KEYCODE_MEDIA_PREVIOUS: u32 = 88;
+pub const KEYCODE_MEDIA_REWIND: u32 = 89;
+pub const KEYCODE_MEDIA_FAST_FORWARD: u32 = 90;
+pub const KEYCODE_MEDIA_PLAY: u32 = 126;
+pub const KEYCODE_MEDIA_PAUSE: u32 = 127;
+pub const KEYCODE_VOLUME_MUTE: u32 = 164;
+
+/// 允许通过 SendControlMediaKeyEventRequest 发送的按键, 其余按键由服务器拒绝
+pub const MEDIA_KEY_CODES: &[u32] = &[
+    KEYCODE_VOLUME_UP,
+    KEYCODE_VOLUME_DOWN,
+    KEYCODE_MEDIA_PLAY_PAUSE,
+    KEYCODE_MEDIA_STOP,
+    KEYCODE_MEDIA_NEXT,
+    KEYCODE_MEDIA_PREVIOUS,
+    KEYCODE_MEDIA_REWIND,
+    KEYCODE_MEDIA_FAST_FORWARD,
+    KEYCODE_MEDIA_PLAY,
+    KEYCODE_MEDIA_PAUSE,
+    KEYCODE_VOLUME_MUTE,
+];
+
+/// 跳转的最大距离(毫秒)
+pub const MAX_SEEK_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// 原始按键事件是否合法
+pub fn is_valid_key_event(action: u32, code: u32) -> bool {
+    (action == ACTION_DOWN || action == ACTION_UP) && MEDIA_KEY_CODES.contains(&code)
+}
+
+/// 与平台无关的媒体控制指令, 控制端不需要知道安卓的按键编码
+///
+/// JSON 形式: `{"type": "play"}`, `{"type": "seek_relative", "offset_ms": -10000}`
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MediaCommand {
+    Play,
+    Pause,
+    /// 播放/暂停切换
+    Toggle,
+    Next,
+    Previous,
+    Stop,
+    /// 相对当前位置跳转, 负数为后退
+    SeekRelative {
+        offset_ms: i64,
+    },
+    /// 跳转到指定位置
+    SeekAbsolute {
+        position_ms: u64,
+    },
+    VolumeUp,
+    VolumeDown,
+    /// 设置音量百分比 0..=100
+    SetVolume {
+        percent: u32,
+    },
+    /// 静音切换
+    Mute,
+    ToggleShuffle,
+    ToggleRepeat,
+}
+
+impl MediaCommand {
+    /// 参数是否合法
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            MediaCommand::SeekRelative { offset_ms } => {
+                offset_ms != 0 && offset_ms.unsigned_abs() <= MAX_SEEK_MS
+            }
+            MediaCommand::SeekAbsolute { position_ms } => position_ms <= MAX_SEEK_MS,
+            MediaCommand::SetVolume { percent } => percent <= 100,
+            _ => true,
+        }
+    }
+
+    /// 不支持 PushMediaCommand 的旧设备使用的等价按键, 没有等价按键时为 None
+    pub fn key_code(&self) -> Option<u32> {
+        match self {
+            MediaCommand::Play => Some(KEYCODE_MEDIA_PLAY),
+            MediaCommand::Pause => Some(KEYCODE_MEDIA_PAUSE),
+            MediaCommand::Toggle => Some(KEYCODE_MEDIA_PLAY_PAUSE),
+            MediaCommand::Next => Some(KEYCODE_MEDIA_NEXT),
+            MediaCommand::Previous => Some(KEYCODE_MEDIA_PREVIOUS),
+            MediaCommand::Stop => Some(KEYCODE_MEDIA_STOP),
+            MediaCommand::VolumeUp => Some(KEYCODE_VOLUME_UP),
+            MediaCommand::VolumeDown => Some(KEYCODE_VOLUME_DOWN),
+            MediaCommand::Mute => Some(KEYCODE_VOLUME_MUTE),
+            MediaCommand::SeekRelative { .. }
+            | MediaCommand::SeekAbsolute { .. }
+            | MediaCommand::SetVolume { .. }
+            | MediaCommand::ToggleShuffle
+            | MediaCommand::ToggleRepeat => None,
+        }
+    }
+}
+
+/// SetVolumeRequest 单次相对调整的最大步数
+pub const MAX_VOLUME_STEPS: i32 = 15;
+
+/// 音量调整: `{"type": "absolute", "percent": 40}`, `{"type": "relative", "steps": -2}`
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VolumeChange {
+    /// 设置为音量百分比 0..=100
+    Absolute { percent: u32 },
+    /// 按设备的音量档位调整, 负数为降低
+    Relative { steps: i32 },
+}
+
+impl VolumeChange {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            VolumeChange::Absolute { percent } => percent <= 100,
+            VolumeChange::Relative { steps } => {
+                steps != 0 && steps.unsigned_abs() <= MAX_VOLUME_STEPS as u32
+            }
+        }
+    }
+
+    /// 发给设备的媒体控制指令, 相对调整为单步指令, 重复 |steps| 次
+    pub fn command(&self) -> MediaCommand {
+        match *self {
+            VolumeChange::Absolute { percent } => MediaCommand::SetVolume { percent },
+            VolumeChange::Relative { steps } if steps > 0 => MediaCommand::VolumeUp,
+            VolumeChange::Relative { .. } => MediaCommand::VolumeDown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_form_is_internally_tagged() {
+        let command: MediaCommand = serde_json::from_str(r#"{"type":"play"}"#).unwrap();
+        assert_eq!(command, MediaCommand::Play);
+
+        let command = MediaCommand::SeekRelative { offset_ms: -10_000 };
+        let json = serde_json::to_string(&command).unwrap();
+        assert_eq!(json, r#"{"type":"seek_relative","offset_ms":-10000}"#);
+
+        assert!(serde_json::from_str::<MediaCommand>(r#"{"type":"eject"}"#).is_err());
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        assert!(MediaCommand::SetVolume { percent: 100 }.is_valid());
+        assert!(!MediaCommand::SetVolume { percent: 101 }.is_valid());
+        assert!(!MediaCommand::SeekRelative { offset_ms: 0 }.is_valid());
+        assert!(!MediaCommand::SeekAbsolute {
+            position_ms: MAX_SEEK_MS + 1
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn volume_change_is_internally_tagged() {
+        let change: VolumeChange =
+            serde_json::from_str(r#"{"type":"relative","steps":-2}"#).unwrap();
+        assert_eq!(change, VolumeChange::Relative { steps: -2 });
+        assert!(change.is_valid());
+        assert!(!VolumeChange::Relative { steps: 0 }.is_valid());
+        assert!(!VolumeChange::Relative {
+            steps: MAX_VOLUME_STEPS + 1
+        }
+        .is_valid());
+        assert!(!VolumeChange::Absolute { percent: 101 }.is_valid());
+    }
+
+    #[test]
+    fn raw_key_events_are_limited_to_media_keys() {
+        assert!(is_valid_key_event(ACTION_DOWN, KEYCODE_MEDIA_NEXT));
+        assert!(!is_valid_key_event(2, KEYCODE_MEDIA_NEXT));
+        // KEYCODE_POWER
+        assert!(!is_valid_key_event(ACTION_DOWN, 26));
+    }
+}
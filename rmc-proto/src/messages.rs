@@ -0,0 +1,360 @@
+use crate::media::{MediaCommand, VolumeChange};
+use crate::secret::Secret;
+
+/// JSON 编码的消息外层, data 为负载的 JSON 字符串
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Message {
+    pub name: String,
+    pub data: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Ping {
+    pub time: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Pong {
+    pub time: u64,
+}
+
+/// 连接后的第一条消息(可选), 声明协议版本区间和支持的功能, 应在注册之前发送
+///
+/// 没有发送 Hello 的旧客户端按版本1处理, 不使用任何可选功能
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Hello {
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub min_protocol_version: u32,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+/// 协商结果, 版本不兼容时 ok 为 false, 服务器随后断开连接
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct HelloResponse {
+    pub ok: bool,
+    pub error: String,
+    pub protocol_version: u32,
+    /// 双方都支持的功能
+    pub features: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DeviceRole {
+    /// 接收按键事件的设备(手机)
+    #[default]
+    Device,
+    /// 发送控制指令的控制端
+    Controller,
+}
+
+/// 会话建立后的第一条消息, 声明会话身份
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct RegisterDeviceRequest {
+    #[serde(default)]
+    pub role: DeviceRole,
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub device_name: String,
+    #[serde(default)]
+    pub client_version: String,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// 客户端支持的消息编码, 如 "msgpack", 为空时只使用 JSON
+    #[serde(default)]
+    pub encodings: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct RegisterDeviceResponse {
+    pub ok: bool,
+    pub error: String,
+    /// 协商结果, 本响应之后服务器使用该编码发送消息
+    #[serde(default)]
+    pub encoding: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct PushMediaKeyEvent {
+    pub action: u32,
+    pub code: u32,
+    pub token: String,
+    /// 服务器分配的请求id, 设备回复 MediaKeyEventAck 时带回
+    pub request_id: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKeyEventResult {
+    /// 设备已执行
+    Executed,
+    /// 设备拒绝执行
+    Rejected,
+    /// 设备未在规定时间内确认
+    TimedOut,
+}
+
+/// 按键事件执行确认
+///
+/// 设备 -> 服务器: 回复 PushMediaKeyEvent
+///
+/// 服务器 -> 控制端: 转发设备的确认(token由服务器填写), 或确认超时
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct MediaKeyEventAck {
+    pub request_id: u64,
+    #[serde(default)]
+    pub token: String,
+    pub result: MediaKeyEventResult,
+    #[serde(default)]
+    pub error: String,
+}
+
+/// 会话开始时服务器下发的随机挑战
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AuthChallengeNtf {
+    pub nonce: String,
+}
+
+/// 控制端认证, proof = hex(HMAC-SHA256(secret, nonce))
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AuthRequest {
+    /// 凭据名称
+    pub name: String,
+    pub proof: Secret,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct AuthResponse {
+    pub ok: bool,
+    pub error: String,
+}
+
+/// 需要会话已认证
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SendControlMediaKeyEventRequest {
+    pub action: u32,
+    pub code: u32,
+    pub token: String,
+}
+
+/// 没有权限
+pub const ERROR_NO_PERMISSION: &str = "no permission";
+/// 会话未认证
+pub const ERROR_NOT_AUTHENTICATED: &str = "not authenticated";
+/// 该token下没有在线设备
+pub const ERROR_DEVICE_OFFLINE: &str = "device offline";
+/// 不允许发送的按键或按键动作
+pub const ERROR_INVALID_KEY_CODE: &str = "invalid key code";
+/// 指令参数不合法
+pub const ERROR_INVALID_COMMAND: &str = "invalid command";
+/// 在线设备都无法执行该指令
+pub const ERROR_UNSUPPORTED_COMMAND: &str = "unsupported command";
+/// 服务器和在线设备都没有该封面
+pub const ERROR_ARTWORK_NOT_FOUND: &str = "artwork not found";
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SendControlMediaKeyEventResponse {
+    pub ok: bool,
+    pub error: String,
+    /// 实际收到该按键事件的设备会话数量
+    pub delivered: u32,
+    /// 本次按键事件的请求id, 用于关联之后的 MediaKeyEventAck
+    pub request_id: u64,
+}
+
+/// 发送媒体控制指令, 需要会话已认证
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SendMediaCommandRequest {
+    pub token: String,
+    pub command: MediaCommand,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SendMediaCommandResponse {
+    pub ok: bool,
+    pub error: String,
+    /// 实际收到该指令的设备会话数量
+    pub delivered: u32,
+    /// 本次指令的请求id, 设备执行后以 MediaKeyEventAck 回复
+    pub request_id: u64,
+}
+
+/// 服务器推送给支持 media_command 功能的设备, 旧设备收到等价的 PushMediaKeyEvent
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct PushMediaCommand {
+    pub token: String,
+    pub request_id: u64,
+    pub command: MediaCommand,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+    /// 没有正在播放的媒体
+    #[default]
+    None,
+    Stopped,
+    Paused,
+    Playing,
+    Buffering,
+}
+
+/// 设备上报的正在播放信息, 服务器缓存后转发给订阅了该设备的控制端
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NowPlayingNtf {
+    /// 设备发送时可以为空, 服务器转发时填充为设备注册的token
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub artist: String,
+    #[serde(default)]
+    pub album: String,
+    /// 总时长(毫秒), 未知时为0
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// 上报时的播放位置(毫秒)
+    #[serde(default)]
+    pub position_ms: u64,
+    #[serde(default)]
+    pub state: PlaybackState,
+    /// 封面内容哈希, 没有封面时为空
+    #[serde(default)]
+    pub artwork_hash: String,
+    /// 服务器收到上报的时间(毫秒时间戳), 播放中的进度 = position_ms + (当前时间 - updated_at)
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+/// 调整设备音量, 服务器合并短时间内的多次调整后以 PushMediaCommand 发给设备
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SetVolumeRequest {
+    pub token: String,
+    pub change: VolumeChange,
+}
+
+/// 调整已被接受, 设备调整后上报 VolumeStateNtf
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SetVolumeResponse {
+    pub ok: bool,
+    pub error: String,
+}
+
+/// 设备上报的音量, 服务器缓存后转发给订阅了该设备的控制端
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct VolumeStateNtf {
+    /// 设备发送时可以为空, 服务器转发时填充为设备注册的token
+    #[serde(default)]
+    pub token: String,
+    /// 音量百分比 0..=100
+    pub percent: u32,
+    #[serde(default)]
+    pub muted: bool,
+    /// 服务器收到上报的时间(毫秒时间戳)
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+/// 请求封面数据: 控制端发给服务器, 或服务器发给缓存中没有该封面的设备
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ArtworkRequest {
+    pub hash: String,
+}
+
+/// 服务器对控制端 ArtworkRequest 的回复, ok 时随后发送该封面的所有 ArtworkChunk
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ArtworkResponse {
+    pub ok: bool,
+    pub error: String,
+    pub hash: String,
+    pub total_size: u64,
+}
+
+/// 封面数据分片, 按 offset 顺序发送, 同一会话同时只传输一个封面
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct ArtworkChunk {
+    /// 完整数据的 SHA-256(十六进制小写)
+    pub hash: String,
+    pub total_size: u64,
+    pub offset: u64,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// 需要会话已认证
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ListDevicesRequest {}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct DeviceInfo {
+    pub session_id: u32,
+    pub name: String,
+    pub token: String,
+    pub addr: String,
+    /// 连接时间(毫秒时间戳)
+    pub connect_time: u64,
+    /// 最后活跃时间(毫秒时间戳)
+    pub last_active_time: u64,
+    /// 服务器与设备之间的平滑往返时间(毫秒), 尚未测量时为空
+    #[serde(default)]
+    pub rtt_ms: Option<u64>,
+    /// 往返时间的抖动(毫秒)
+    #[serde(default)]
+    pub jitter_ms: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ListDevicesResponse {
+    pub ok: bool,
+    pub error: String,
+    pub devices: Vec<DeviceInfo>,
+}
+
+/// 设备上线通知, 推送给订阅了该设备的控制端
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct DeviceOnlineNtf {
+    pub device: DeviceInfo,
+}
+
+/// 设备下线通知, 推送给订阅了该设备的控制端
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct DeviceOfflineNtf {
+    pub session_id: u32,
+    pub token: String,
+}
+
+/// 订阅所有可访问设备的token通配符
+pub const SUBSCRIBE_ALL: &str = "*";
+
+/// 订阅设备的通知(上下线、正在播放、音量等), 需要会话已认证
+///
+/// 订阅时立即收到这些设备已缓存的状态
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SubscribeRequest {
+    /// 设备token, "*" 为凭据可访问的所有设备
+    pub tokens: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct SubscribeResponse {
+    pub ok: bool,
+    pub error: String,
+    /// 本会话当前订阅的全部token
+    pub tokens: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct UnsubscribeRequest {
+    pub tokens: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct UnsubscribeResponse {
+    pub ok: bool,
+    pub error: String,
+    /// 本会话当前订阅的全部token
+    pub tokens: Vec<String>,
+}
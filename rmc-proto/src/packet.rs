@@ -0,0 +1,508 @@
+use crate::messages::*;
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// 注册时协商的编码名称
+pub const ENCODING_JSON: &str = "json";
+pub const ENCODING_MSGPACK: &str = "msgpack";
+
+/// MessagePack 消息以 2 元素数组 [name, payload] 开头, JSON 消息以 '{' 开头
+const MSGPACK_FRAME_MARKER: u8 = 0x92;
+
+/// 消息编码
+///
+/// - Json: `{"name": ..., "data": "<payload json字符串>"}`, 兼容旧客户端
+/// - MessagePack: `[name, payload]`, payload 直接编码为 map, 没有二次序列化
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    /// 从客户端支持的编码中选择, 优先 MessagePack
+    pub fn negotiate(encodings: &[String]) -> Self {
+        if encodings.iter().any(|x| x == ENCODING_MSGPACK) {
+            Encoding::MessagePack
+        } else {
+            Encoding::Json
+        }
+    }
+
+    /// 协商结果, 未知编码按 JSON 处理
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            ENCODING_MSGPACK => Encoding::MessagePack,
+            _ => Encoding::Json,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Json => ENCODING_JSON,
+            Encoding::MessagePack => ENCODING_MSGPACK,
+        }
+    }
+}
+
+/// 待解析的消息负载
+enum Payload<'a> {
+    Json(&'a str),
+    MessagePack(&'a [u8]),
+}
+
+impl Payload<'_> {
+    fn parse<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        Ok(match self {
+            Payload::Json(data) => serde_json::from_str(data)?,
+            Payload::MessagePack(data) => rmp_serde::from_slice(data)?,
+        })
+    }
+}
+
+fn write_payload<T: Serialize>(
+    encoding: Encoding,
+    name: &str,
+    data: &T,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match encoding {
+        Encoding::Json => {
+            let message = Message {
+                name: name.to_string(),
+                data: serde_json::to_string(data)?,
+            };
+            serde_json::to_writer(&mut buf, &message)?;
+        }
+        Encoding::MessagePack => {
+            rmp::encode::write_array_len(&mut buf, 2)?;
+            rmp::encode::write_str(&mut buf, name)?;
+            // 结构体按字段名编码, 新旧版本增删字段时保持兼容
+            rmp_serde::encode::write_named(&mut buf, data)?;
+        }
+    }
+    Ok(buf)
+}
+
+macro_rules! packets {
+    ($($name:ident),* $(,)?) => {
+        /// 服务器与客户端之间的所有消息, 消息名即类型名
+        #[derive(Clone, Debug)]
+        pub enum Packet {
+            $($name($name),)*
+            /// 不认识的消息(来自新版本的对端), 只保留名称
+            Unknown(String),
+        }
+
+        impl Packet {
+            pub fn name(&self) -> &str {
+                match self {
+                    $(Packet::$name(_) => stringify!($name),)*
+                    Packet::Unknown(name) => name,
+                }
+            }
+
+            fn from_payload(name: &str, payload: Payload<'_>) -> anyhow::Result<Self> {
+                Ok(match name {
+                    $(stringify!($name) => Packet::$name(payload.parse()?),)*
+                    _ => Packet::Unknown(name.to_string()),
+                })
+            }
+
+            /// 编码为一个完整消息(不含长度前缀)
+            pub fn encode(&self, encoding: Encoding) -> anyhow::Result<Vec<u8>> {
+                match self {
+                    $(Packet::$name(data) => write_payload(encoding, stringify!($name), data),)*
+                    Packet::Unknown(name) => Err(anyhow!("cannot encode unknown message: {name}")),
+                }
+            }
+
+            /// 转换为 JSON 外层消息
+            pub fn to_message(&self) -> anyhow::Result<Message> {
+                let data = match self {
+                    $(Packet::$name(data) => serde_json::to_string(data)?,)*
+                    Packet::Unknown(name) => {
+                        return Err(anyhow!("cannot encode unknown message: {name}"))
+                    }
+                };
+                Ok(Message {
+                    name: self.name().to_string(),
+                    data,
+                })
+            }
+        }
+
+        $(
+            impl From<$name> for Packet {
+                fn from(data: $name) -> Self {
+                    Packet::$name(data)
+                }
+            }
+        )*
+    };
+}
+
+packets! {
+    Ping,
+    Pong,
+    Hello,
+    HelloResponse,
+    RegisterDeviceRequest,
+    RegisterDeviceResponse,
+    AuthChallengeNtf,
+    AuthRequest,
+    AuthResponse,
+    SendControlMediaKeyEventRequest,
+    SendControlMediaKeyEventResponse,
+    PushMediaKeyEvent,
+    SendMediaCommandRequest,
+    SendMediaCommandResponse,
+    PushMediaCommand,
+    MediaKeyEventAck,
+    NowPlayingNtf,
+    SetVolumeRequest,
+    SetVolumeResponse,
+    VolumeStateNtf,
+    ArtworkRequest,
+    ArtworkResponse,
+    ArtworkChunk,
+    ListDevicesRequest,
+    ListDevicesResponse,
+    DeviceOnlineNtf,
+    DeviceOfflineNtf,
+    SubscribeRequest,
+    SubscribeResponse,
+    UnsubscribeRequest,
+    UnsubscribeResponse,
+}
+
+impl Packet {
+    /// 解码一个完整消息(不含长度前缀), 按首字节识别编码, 同一会话中两种编码可以混用
+    pub fn decode(frame: &[u8]) -> anyhow::Result<Self> {
+        if frame.first() == Some(&MSGPACK_FRAME_MARKER) {
+            let mut rd = &frame[1..];
+            let len = rmp::decode::read_str_len(&mut rd)
+                .map_err(|err| anyhow!("invalid msgpack frame: {err:?}"))?
+                as usize;
+            if rd.len() < len {
+                return Err(anyhow!("invalid msgpack frame: truncated name"));
+            }
+            let name = std::str::from_utf8(&rd[..len])?;
+            Self::from_payload(name, Payload::MessagePack(&rd[len..]))
+        } else {
+            let message: Message = serde_json::from_slice(frame)?;
+            Self::from_message(&message)
+        }
+    }
+
+    /// 从 JSON 外层消息解析
+    pub fn from_message(message: &Message) -> anyhow::Result<Self> {
+        Self::from_payload(&message.name, Payload::Json(&message.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::{MediaCommand, VolumeChange};
+
+    fn samples() -> Vec<Packet> {
+        let device = DeviceInfo {
+            session_id: 7,
+            name: "Pixel 8".to_string(),
+            token: "phone".to_string(),
+            addr: "127.0.0.1:5000".to_string(),
+            connect_time: 1_700_000_000_000,
+            last_active_time: 1_700_000_000_500,
+            rtt_ms: Some(42),
+            jitter_ms: None,
+        };
+        vec![
+            Ping { time: 1 }.into(),
+            Pong { time: u64::MAX }.into(),
+            crate::Protocol::hello().into(),
+            HelloResponse {
+                ok: false,
+                error: "incompatible protocol version".to_string(),
+                protocol_version: 2,
+                features: vec![],
+            }
+            .into(),
+            RegisterDeviceRequest {
+                role: DeviceRole::Controller,
+                token: "".to_string(),
+                device_name: "desk".to_string(),
+                client_version: "1.0".to_string(),
+                capabilities: vec!["ack".to_string()],
+                encodings: vec![ENCODING_MSGPACK.to_string()],
+            }
+            .into(),
+            RegisterDeviceResponse {
+                ok: true,
+                error: "".to_string(),
+                encoding: ENCODING_MSGPACK.to_string(),
+            }
+            .into(),
+            AuthChallengeNtf {
+                nonce: "abc".to_string(),
+            }
+            .into(),
+            AuthRequest {
+                name: "alice".to_string(),
+                proof: "00ff".into(),
+            }
+            .into(),
+            AuthResponse {
+                ok: false,
+                error: "authentication failed".to_string(),
+            }
+            .into(),
+            SendControlMediaKeyEventRequest {
+                action: 0,
+                code: 85,
+                token: "phone".to_string(),
+            }
+            .into(),
+            SendControlMediaKeyEventResponse {
+                ok: true,
+                error: "".to_string(),
+                delivered: 2,
+                request_id: 9,
+            }
+            .into(),
+            PushMediaKeyEvent {
+                action: 1,
+                code: 126,
+                token: "phone".to_string(),
+                request_id: 9,
+            }
+            .into(),
+            MediaKeyEventAck {
+                request_id: 9,
+                token: "phone".to_string(),
+                result: MediaKeyEventResult::Rejected,
+                error: "token mismatch".to_string(),
+            }
+            .into(),
+            SendMediaCommandRequest {
+                token: "phone".to_string(),
+                command: MediaCommand::SeekRelative { offset_ms: -10_000 },
+            }
+            .into(),
+            SendMediaCommandResponse {
+                ok: false,
+                error: ERROR_UNSUPPORTED_COMMAND.to_string(),
+                delivered: 0,
+                request_id: 10,
+            }
+            .into(),
+            PushMediaCommand {
+                token: "phone".to_string(),
+                request_id: 10,
+                command: MediaCommand::SetVolume { percent: 40 },
+            }
+            .into(),
+            PushMediaCommand {
+                token: "phone".to_string(),
+                request_id: 11,
+                command: MediaCommand::Toggle,
+            }
+            .into(),
+            NowPlayingNtf {
+                token: "phone".to_string(),
+                title: "Bohemian Rhapsody".to_string(),
+                artist: "Queen".to_string(),
+                album: "A Night at the Opera".to_string(),
+                duration_ms: 354_000,
+                position_ms: 12_345,
+                state: PlaybackState::Playing,
+                artwork_hash: "9f86d081884c7d65".to_string(),
+                updated_at: 1_700_000_000_000,
+            }
+            .into(),
+            SetVolumeRequest {
+                token: "phone".to_string(),
+                change: VolumeChange::Relative { steps: -3 },
+            }
+            .into(),
+            SetVolumeResponse {
+                ok: false,
+                error: ERROR_INVALID_COMMAND.to_string(),
+            }
+            .into(),
+            VolumeStateNtf {
+                token: "phone".to_string(),
+                percent: 40,
+                muted: true,
+                updated_at: 1_700_000_000_000,
+            }
+            .into(),
+            ArtworkRequest {
+                hash: "9f86d081884c7d65".to_string(),
+            }
+            .into(),
+            ArtworkResponse {
+                ok: true,
+                error: "".to_string(),
+                hash: "9f86d081884c7d65".to_string(),
+                total_size: 3,
+            }
+            .into(),
+            ArtworkChunk {
+                hash: "9f86d081884c7d65".to_string(),
+                total_size: 3,
+                offset: 0,
+                data: vec![0, 0x92, 0xff],
+            }
+            .into(),
+            ListDevicesRequest {}.into(),
+            ListDevicesResponse {
+                ok: true,
+                error: "".to_string(),
+                devices: vec![device.clone()],
+            }
+            .into(),
+            DeviceOnlineNtf { device }.into(),
+            DeviceOfflineNtf {
+                session_id: 7,
+                token: "phone".to_string(),
+            }
+            .into(),
+            SubscribeRequest {
+                tokens: vec![SUBSCRIBE_ALL.to_string()],
+            }
+            .into(),
+            SubscribeResponse {
+                ok: true,
+                error: "".to_string(),
+                tokens: vec!["phone".to_string(), "tablet".to_string()],
+            }
+            .into(),
+            UnsubscribeRequest {
+                tokens: vec!["tablet".to_string()],
+            }
+            .into(),
+            UnsubscribeResponse {
+                ok: false,
+                error: ERROR_NOT_AUTHENTICATED.to_string(),
+                tokens: vec![],
+            }
+            .into(),
+        ]
+    }
+
+    #[test]
+    fn every_packet_roundtrips_in_both_encodings() {
+        for packet in samples() {
+            for encoding in [Encoding::Json, Encoding::MessagePack] {
+                let frame = packet.encode(encoding).unwrap();
+                let decoded = Packet::decode(&frame).unwrap();
+                assert_eq!(decoded.name(), packet.name());
+                // Debug 输出包含所有字段, 用来比较内容
+                assert_eq!(format!("{decoded:?}"), format!("{packet:?}"));
+            }
+        }
+    }
+
+    #[test]
+    fn json_message_roundtrip() {
+        for packet in samples() {
+            let message = packet.to_message().unwrap();
+            let decoded = Packet::from_message(&message).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{packet:?}"));
+        }
+    }
+
+    #[test]
+    fn unknown_message_is_kept_by_name() {
+        let frame = br#"{"name":"FutureNtf","data":"{}"}"#;
+        let packet = Packet::decode(frame).unwrap();
+        assert_eq!(packet.name(), "FutureNtf");
+        assert!(packet.encode(Encoding::Json).is_err());
+    }
+
+    #[test]
+    fn missing_optional_fields_use_defaults() {
+        // 旧版本安卓客户端的注册消息没有 encodings 等字段
+        let frame = br#"{"name":"RegisterDeviceRequest","data":"{\"token\":\"phone\"}"}"#;
+        let Packet::RegisterDeviceRequest(request) = Packet::decode(frame).unwrap() else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(request.role, DeviceRole::Device);
+        assert!(request.encodings.is_empty());
+    }
+
+    #[test]
+    fn media_key_event_result_is_snake_case() {
+        // 安卓客户端发送的确认
+        let frame =
+            br#"{"name":"MediaKeyEventAck","data":"{\"request_id\":9,\"result\":\"executed\"}"}"#;
+        let Packet::MediaKeyEventAck(ack) = Packet::decode(frame).unwrap() else {
+            panic!("unexpected packet");
+        };
+        assert_eq!(ack.result, MediaKeyEventResult::Executed);
+        assert_eq!(
+            serde_json::to_string(&MediaKeyEventResult::TimedOut).unwrap(),
+            r#""timed_out""#
+        );
+    }
+
+    #[test]
+    fn msgpack_is_smaller_than_json() {
+        for packet in samples() {
+            let json = packet.encode(Encoding::Json).unwrap();
+            let msgpack = packet.encode(Encoding::MessagePack).unwrap();
+            assert!(msgpack.len() < json.len(), "{}", packet.name());
+        }
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_json() {
+        assert_eq!(Encoding::negotiate(&[]), Encoding::Json);
+        assert_eq!(
+            Encoding::negotiate(&["cbor".to_string(), ENCODING_MSGPACK.to_string()]),
+            Encoding::MessagePack
+        );
+        assert_eq!(Encoding::from_name("cbor"), Encoding::Json);
+    }
+
+    /// 随机数据和截断的合法消息只能返回错误, 不能 panic
+    #[test]
+    fn fuzz_decode_never_panics() {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..10_000 {
+            let len = (next() % 64) as usize;
+            let mut frame: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            // 一半的样本以 MessagePack 标记开头, 覆盖更深的解码路径
+            if next() % 2 == 0 {
+                frame.insert(0, MSGPACK_FRAME_MARKER);
+            }
+            let _ = Packet::decode(&frame);
+        }
+
+        for packet in samples() {
+            for encoding in [Encoding::Json, Encoding::MessagePack] {
+                let frame = packet.encode(encoding).unwrap();
+                for end in 0..frame.len() {
+                    let _ = Packet::decode(&frame[..end]);
+                }
+                // 随机翻转字节
+                for _ in 0..64 {
+                    let mut corrupted = frame.clone();
+                    let index = (next() as usize) % corrupted.len();
+                    corrupted[index] ^= next() as u8 | 1;
+                    let _ = Packet::decode(&corrupted);
+                }
+            }
+        }
+    }
+}
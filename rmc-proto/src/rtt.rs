@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+/// 按 RFC 6298 计算平滑往返时间和抖动
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+}
+
+impl RttEstimator {
+    /// 加入一次 Ping/Pong 测得的往返时间
+    pub fn update(&mut self, sample: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(sample);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + sample) / 8);
+            }
+        }
+    }
+
+    /// 平滑往返时间, 尚未测量时为 None
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// 往返时间的平均偏差
+    pub fn jitter(&self) -> Duration {
+        self.rttvar
+    }
+
+    /// 平滑往返时间的毫秒数, 尚未测量时为 None
+    pub fn srtt_ms(&self) -> Option<u64> {
+        self.srtt.map(|x| x.as_millis() as u64)
+    }
+
+    /// 抖动的毫秒数, 尚未测量时为 None
+    pub fn jitter_ms(&self) -> Option<u64> {
+        self.srtt.map(|_| self.rttvar.as_millis() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_sample_initializes_estimate() {
+        let mut rtt = RttEstimator::default();
+        assert_eq!(rtt.srtt_ms(), None);
+        assert_eq!(rtt.jitter_ms(), None);
+
+        rtt.update(Duration::from_millis(100));
+        assert_eq!(rtt.srtt_ms(), Some(100));
+        assert_eq!(rtt.jitter_ms(), Some(50));
+    }
+
+    #[test]
+    fn samples_are_smoothed() {
+        let mut rtt = RttEstimator::default();
+        rtt.update(Duration::from_millis(100));
+        rtt.update(Duration::from_millis(180));
+        // srtt = (100 * 7 + 180) / 8, rttvar = (50 * 3 + 80) / 4
+        assert_eq!(rtt.srtt_ms(), Some(110));
+        assert_eq!(rtt.jitter_ms(), Some(57));
+
+        // 稳定后抖动逐渐减小
+        for _ in 0..50 {
+            rtt.update(Duration::from_millis(110));
+        }
+        assert_eq!(rtt.srtt_ms(), Some(110));
+        assert!(rtt.jitter() < Duration::from_millis(2));
+    }
+}
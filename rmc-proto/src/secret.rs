@@ -0,0 +1,74 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// 密钥、授权码、认证证明等敏感字符串, `{:?}` 输出为 `"***"`, 不会出现在日志中
+///
+/// 序列化时与普通字符串相同
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// 原始内容, 只在计算和比较时使用
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::AuthRequest;
+
+    #[test]
+    fn debug_hides_content() {
+        let request = AuthRequest {
+            name: "alice".to_string(),
+            proof: "00ff".into(),
+        };
+        assert_eq!(
+            format!("{request:?}"),
+            r#"AuthRequest { name: "alice", proof: "***" }"#
+        );
+
+        // 传输时仍是普通字符串
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"name":"alice","proof":"00ff"}"#);
+        let decoded: AuthRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.proof.expose(), "00ff");
+    }
+}
@@ -0,0 +1,134 @@
+use crate::messages::Hello;
+use std::collections::BTreeSet;
+
+/// 当前协议版本
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// 仍然兼容的最低协议版本
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// 没有发送 Hello 的旧客户端视为该版本, 不支持任何可选功能
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// 设备执行按键后回复 MediaKeyEventAck
+pub const FEATURE_MEDIA_KEY_ACK: &str = "media_key_ack";
+/// 控制端接收 DeviceOnlineNtf / DeviceOfflineNtf
+pub const FEATURE_DEVICE_PRESENCE: &str = "device_presence";
+
+/// 设备接收 PushMediaCommand, 否则服务器转换为等价的按键事件
+pub const FEATURE_MEDIA_COMMAND: &str = "media_command";
+
+/// 控制端接收 NowPlayingNtf
+pub const FEATURE_NOW_PLAYING: &str = "now_playing";
+
+/// 设备响应服务器的 ArtworkRequest 上传封面
+pub const FEATURE_ARTWORK: &str = "artwork";
+
+/// 控制端接收 VolumeStateNtf
+pub const FEATURE_VOLUME_STATE: &str = "volume_state";
+
+/// 本版本实现的所有可选功能
+pub const FEATURES: &[&str] = &[
+    FEATURE_MEDIA_KEY_ACK,
+    FEATURE_DEVICE_PRESENCE,
+    FEATURE_MEDIA_COMMAND,
+    FEATURE_NOW_PLAYING,
+    FEATURE_ARTWORK,
+    FEATURE_VOLUME_STATE,
+];
+
+/// 与对端协商后的协议版本和双方都支持的功能
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u32,
+    pub features: BTreeSet<String>,
+}
+
+impl Protocol {
+    /// 旧客户端: 版本1, 无可选功能
+    pub fn legacy() -> Self {
+        Self {
+            version: LEGACY_PROTOCOL_VERSION,
+            features: BTreeSet::new(),
+        }
+    }
+
+    /// 本端发送的 Hello
+    pub fn hello() -> Hello {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            features: FEATURES.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    /// 根据对端的 Hello 协商, 版本区间没有交集时返回错误说明
+    pub fn negotiate(hello: &Hello) -> Result<Self, String> {
+        if hello.protocol_version < MIN_PROTOCOL_VERSION
+            || hello.min_protocol_version > PROTOCOL_VERSION
+        {
+            return Err(format!(
+                "incompatible protocol version: peer supports {}..={}, this side supports {}..={}",
+                hello.min_protocol_version,
+                hello.protocol_version,
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION
+            ));
+        }
+
+        Ok(Self {
+            version: hello.protocol_version.min(PROTOCOL_VERSION),
+            features: hello
+                .features
+                .iter()
+                .filter(|x| FEATURES.contains(&x.as_str()))
+                .cloned()
+                .collect(),
+        })
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(min_protocol_version: u32, protocol_version: u32, features: &[&str]) -> Hello {
+        Hello {
+            protocol_version,
+            min_protocol_version,
+            features: features.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn negotiates_lower_version_and_common_features() {
+        let protocol =
+            Protocol::negotiate(&hello(1, 9, &[FEATURE_MEDIA_KEY_ACK, "teleport"])).unwrap();
+        assert_eq!(protocol.version, PROTOCOL_VERSION);
+        assert!(protocol.supports(FEATURE_MEDIA_KEY_ACK));
+        assert!(!protocol.supports("teleport"));
+        assert!(!protocol.supports(FEATURE_DEVICE_PRESENCE));
+
+        let protocol = Protocol::negotiate(&Protocol::hello()).unwrap();
+        assert_eq!(protocol.features.len(), FEATURES.len());
+    }
+
+    #[test]
+    fn rejects_disjoint_version_ranges() {
+        let error = Protocol::negotiate(&hello(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 2, &[]))
+            .unwrap_err();
+        assert!(error.contains("incompatible protocol version"));
+        assert!(Protocol::negotiate(&hello(0, 0, &[])).is_err());
+    }
+
+    #[test]
+    fn legacy_peer_has_no_features() {
+        let protocol = Protocol::legacy();
+        assert_eq!(protocol.version, LEGACY_PROTOCOL_VERSION);
+        assert!(!protocol.supports(FEATURE_MEDIA_KEY_ACK));
+    }
+}
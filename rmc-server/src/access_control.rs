@@ -0,0 +1,330 @@
+use anyhow::anyhow;
+use rmc_proto::Secret;
+use rmc_proto::{MediaCommand, KEYCODE_MEDIA_PAUSE, KEYCODE_MEDIA_PLAY, KEYCODE_MEDIA_PLAY_PAUSE};
+use std::collections::HashSet;
+
+/// 允许控制所有设备的token通配符
+const ANY_TOKEN: &str = "*";
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// 完全控制
+    #[default]
+    Full,
+    /// 只能播放/暂停
+    PlayPause,
+}
+
+impl Permission {
+    pub fn allows_key_code(&self, code: u32) -> bool {
+        match self {
+            Permission::Full => true,
+            Permission::PlayPause => matches!(
+                code,
+                KEYCODE_MEDIA_PLAY_PAUSE | KEYCODE_MEDIA_PLAY | KEYCODE_MEDIA_PAUSE
+            ),
+        }
+    }
+
+    pub fn allows_command(&self, command: &MediaCommand) -> bool {
+        match self {
+            Permission::Full => true,
+            Permission::PlayPause => matches!(
+                command,
+                MediaCommand::Play | MediaCommand::Pause | MediaCommand::Toggle
+            ),
+        }
+    }
+}
+
+/// 控制端凭据
+#[derive(serde::Deserialize, Debug)]
+pub struct Credential {
+    /// 凭据名称, 用于日志和区分不同用户
+    pub name: String,
+    pub secret: Secret,
+    #[serde(default)]
+    pub permission: Permission,
+    /// 可控制的设备token, "*" 表示全部设备
+    pub tokens: Vec<String>,
+}
+
+impl Credential {
+    pub fn can_access(&self, token: &str) -> bool {
+        self.tokens.iter().any(|x| x == ANY_TOKEN || x == token)
+    }
+
+    pub fn can_send_key(&self, token: &str, code: u32) -> bool {
+        self.can_access(token) && self.permission.allows_key_code(code)
+    }
+
+    pub fn can_send_command(&self, token: &str, command: &MediaCommand) -> bool {
+        self.can_access(token) && self.permission.allows_command(command)
+    }
+
+    /// 受限凭据(只能播放/暂停)只能看到明确分配给它的token,
+    /// 通配符可访问的token不返回, 避免被用来冒充设备注册
+    pub fn can_see_token(&self, token: &str) -> bool {
+        match self.permission {
+            Permission::Full => self.can_access(token),
+            Permission::PlayPause => self.tokens.iter().any(|x| x == token),
+        }
+    }
+
+    /// 以该凭据认证的设备可以注册的token, 需要完全控制权限
+    pub fn can_register(&self, token: &str) -> bool {
+        self.permission == Permission::Full && self.can_access(token)
+    }
+}
+
+/// 访问控制表, 启动时从文件加载
+///
+/// ```toml
+/// [[credentials]]
+/// name = "alice"
+/// secret = "alice-secret"
+/// tokens = ["alice-phone"]
+///
+/// [[credentials]]
+/// name = "guest"
+/// secret = "guest-secret"
+/// permission = "play_pause"
+/// tokens = ["*"]
+/// ```
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct AccessControl {
+    #[serde(default)]
+    pub credentials: Vec<Credential>,
+}
+
+impl AccessControl {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read access control file ({path}): {err}"))?;
+        Self::parse(&content)
+            .map_err(|err| anyhow!("failed to parse access control file ({path}): {err}"))
+    }
+
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let access_control: AccessControl = toml::from_str(content)?;
+        access_control.validate()?;
+        Ok(access_control)
+    }
+
+    /// 兼容旧版本的单一授权码, 拥有所有设备的完全控制权限
+    pub fn with_authorization_code(authorization_code: Secret) -> Self {
+        Self {
+            credentials: vec![Credential {
+                name: "default".to_string(),
+                secret: authorization_code,
+                permission: Permission::Full,
+                tokens: vec![ANY_TOKEN.to_string()],
+            }],
+        }
+    }
+
+    /// 配置了多个凭据时, 设备须先认证才能注册, 否则任何用户都可以冒充其他用户的设备
+    pub fn requires_device_auth(&self) -> bool {
+        self.credentials.len() > 1
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<&Credential> {
+        self.credentials
+            .iter()
+            .find(|credential| credential.name == name)
+    }
+
+    /// 按密钥查找凭据, 用于HTTP接口的 Bearer 认证
+    pub fn find_by_secret(&self, secret: &str) -> Option<&Credential> {
+        if secret.is_empty() {
+            return None;
+        }
+        self.credentials.iter().find(|credential| {
+            constant_time_eq(credential.secret.expose().as_bytes(), secret.as_bytes())
+        })
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.credentials.is_empty() {
+            return Err(anyhow!("access control file contains no credentials"));
+        }
+
+        let mut names = HashSet::new();
+        let mut secrets = HashSet::new();
+        for credential in self.credentials.iter() {
+            if credential.name.is_empty() {
+                return Err(anyhow!("credential name must not be empty"));
+            }
+            if !names.insert(credential.name.as_str()) {
+                return Err(anyhow!("duplicate credential name: {}", credential.name));
+            }
+            if credential.secret.is_empty() {
+                return Err(anyhow!(
+                    "credential ({}) has an empty secret",
+                    credential.name
+                ));
+            }
+            if !secrets.insert(credential.secret.expose()) {
+                return Err(anyhow!(
+                    "credential ({}) reuses the secret of another credential",
+                    credential.name
+                ));
+            }
+            if credential.tokens.is_empty() {
+                return Err(anyhow!("credential ({}) has no tokens", credential.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 比较耗时与内容无关, 避免通过响应时间猜测密钥
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmc_proto::KEYCODE_MEDIA_NEXT;
+
+    const EXAMPLE: &str = r#"
+        [[credentials]]
+        name = "alice"
+        secret = "alice-secret"
+        tokens = ["alice-phone"]
+
+        [[credentials]]
+        name = "guest"
+        secret = "guest-secret"
+        permission = "play_pause"
+        tokens = ["*"]
+    "#;
+
+    fn credential<'a>(access_control: &'a AccessControl, name: &str) -> &'a Credential {
+        access_control.find_by_name(name).unwrap()
+    }
+
+    #[test]
+    fn parses_credentials() {
+        let access_control = AccessControl::parse(EXAMPLE).unwrap();
+        let alice = credential(&access_control, "alice");
+        assert_eq!(alice.permission, Permission::Full);
+        assert_eq!(alice.tokens, vec!["alice-phone".to_string()]);
+        assert_eq!(
+            credential(&access_control, "guest").permission,
+            Permission::PlayPause
+        );
+
+        assert_eq!(
+            access_control.find_by_secret("guest-secret").unwrap().name,
+            "guest"
+        );
+        assert!(access_control.find_by_secret("").is_none());
+        assert!(access_control.find_by_secret("guest-secre").is_none());
+        assert!(access_control.find_by_name("mallory").is_none());
+    }
+
+    #[test]
+    fn wildcard_token_grants_every_device() {
+        let access_control = AccessControl::parse(EXAMPLE).unwrap();
+        let alice = credential(&access_control, "alice");
+        assert!(alice.can_access("alice-phone"));
+        assert!(!alice.can_access("bob-phone"));
+
+        let guest = credential(&access_control, "guest");
+        assert!(guest.can_access("alice-phone"));
+        assert!(guest.can_access("bob-phone"));
+    }
+
+    #[test]
+    fn restricted_credential_sees_only_assigned_tokens() {
+        let access_control = AccessControl::parse(EXAMPLE).unwrap();
+        assert!(access_control.requires_device_auth());
+        assert!(!AccessControl::with_authorization_code("code".into()).requires_device_auth());
+
+        let alice = credential(&access_control, "alice");
+        assert!(alice.can_see_token("alice-phone"));
+        assert!(alice.can_register("alice-phone"));
+        assert!(!alice.can_register("bob-phone"));
+
+        // 访客可以控制所有设备, 但看不到token, 也不能注册设备
+        let guest = credential(&access_control, "guest");
+        assert!(!guest.can_see_token("alice-phone"));
+        assert!(!guest.can_register("alice-phone"));
+        let living_room = Credential {
+            name: "living-room-guest".to_string(),
+            secret: "secret".into(),
+            permission: Permission::PlayPause,
+            tokens: vec!["living-room".to_string()],
+        };
+        assert!(living_room.can_see_token("living-room"));
+        assert!(!living_room.can_register("living-room"));
+    }
+
+    #[test]
+    fn play_pause_permission_limits_keys_and_commands() {
+        let access_control = AccessControl::parse(EXAMPLE).unwrap();
+        let guest = credential(&access_control, "guest");
+        assert!(guest.can_send_key("alice-phone", KEYCODE_MEDIA_PLAY_PAUSE));
+        assert!(guest.can_send_key("alice-phone", KEYCODE_MEDIA_PAUSE));
+        assert!(!guest.can_send_key("alice-phone", KEYCODE_MEDIA_NEXT));
+        assert!(guest.can_send_command("alice-phone", &MediaCommand::Toggle));
+        assert!(!guest.can_send_command("alice-phone", &MediaCommand::Next));
+
+        let alice = credential(&access_control, "alice");
+        assert!(alice.can_send_key("alice-phone", KEYCODE_MEDIA_NEXT));
+        assert!(alice.can_send_command("alice-phone", &MediaCommand::Next));
+        assert!(!alice.can_send_key("bob-phone", KEYCODE_MEDIA_PLAY_PAUSE));
+    }
+
+    /// 按 (name, secret, tokens) 生成访问控制文件
+    fn file(credentials: &[(&str, &str, &str)]) -> String {
+        credentials
+            .iter()
+            .map(|(name, secret, tokens)| {
+                format!(
+                    "[[credentials]]\nname = {name:?}\nsecret = {secret:?}\ntokens = {tokens}\n"
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let cases = [
+            (file(&[]), "access control file contains no credentials"),
+            (
+                file(&[("", "s", r#"["*"]"#)]),
+                "credential name must not be empty",
+            ),
+            (
+                file(&[("a", "s1", r#"["*"]"#), ("a", "s2", r#"["*"]"#)]),
+                "duplicate credential name: a",
+            ),
+            (
+                file(&[("a", "", r#"["*"]"#)]),
+                "credential (a) has an empty secret",
+            ),
+            (
+                file(&[("a", "s", r#"["*"]"#), ("b", "s", r#"["*"]"#)]),
+                "credential (b) reuses the secret of another credential",
+            ),
+            (file(&[("a", "s", "[]")]), "credential (a) has no tokens"),
+        ];
+        for (content, error) in cases {
+            let err = AccessControl::parse(&content).unwrap_err();
+            assert_eq!(err.to_string(), error, "{content}");
+        }
+
+        let unknown_permission = file(&[("a", "s", r#"["*"]"#)]) + "permission = \"admin\"\n";
+        assert!(AccessControl::parse(&unknown_permission).is_err());
+    }
+}
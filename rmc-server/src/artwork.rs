@@ -0,0 +1,97 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// 按占用字节数限制大小的封面缓存, 超出时淘汰最近最少使用的封面
+pub struct ArtworkCache {
+    capacity: usize,
+    size: usize,
+    entries: HashMap<String, Arc<Vec<u8>>>,
+    /// 最近使用的在末尾
+    order: VecDeque<String>,
+}
+
+impl ArtworkCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&mut self, hash: &str) -> Option<Arc<Vec<u8>>> {
+        let data = self.entries.get(hash)?.clone();
+        self.touch(hash);
+        Some(data)
+    }
+
+    /// 超过缓存容量的封面不缓存
+    pub fn insert(&mut self, hash: String, data: Arc<Vec<u8>>) {
+        if self.entries.contains_key(&hash) {
+            self.touch(&hash);
+            return;
+        }
+        if data.len() > self.capacity {
+            return;
+        }
+
+        while self.size + data.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.size -= evicted.len();
+            }
+        }
+
+        self.size += data.len();
+        self.order.push_back(hash.clone());
+        self.entries.insert(hash, data);
+    }
+
+    fn touch(&mut self, hash: &str) {
+        if let Some(index) = self.order.iter().position(|x| x == hash) {
+            if let Some(hash) = self.order.remove(index) {
+                self.order.push_back(hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artwork(len: usize) -> Arc<Vec<u8>> {
+        Arc::new(vec![0u8; len])
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ArtworkCache::new(10);
+        cache.insert("a".to_string(), artwork(4));
+        cache.insert("b".to_string(), artwork(4));
+        // 访问 a 后 b 成为最久未使用
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), artwork(4));
+
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert!(cache.contains("c"));
+        assert_eq!(cache.size, 8);
+    }
+
+    #[test]
+    fn oversized_artwork_is_not_cached() {
+        let mut cache = ArtworkCache::new(10);
+        cache.insert("a".to_string(), artwork(4));
+        cache.insert("huge".to_string(), artwork(11));
+        assert!(!cache.contains("huge"));
+        assert!(cache.contains("a"));
+    }
+}
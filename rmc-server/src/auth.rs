@@ -0,0 +1,96 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 随机挑战长度(字节)
+const NONCE_LEN: usize = 32;
+
+/// 生成随机挑战, 十六进制编码
+pub fn new_nonce() -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    hex::encode(nonce)
+}
+
+/// 校验证明 proof == hex(HMAC-SHA256(secret, nonce)), 比较耗时与内容无关
+pub fn verify_proof(secret: &str, nonce: &str, proof: &str) -> bool {
+    let Ok(proof) = hex::decode(proof) else {
+        return false;
+    };
+    let mut mac = new_mac(secret);
+    mac.update(nonce.as_bytes());
+    mac.verify_slice(&proof).is_ok()
+}
+
+fn new_mac(secret: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        authenticate, compute_proof, connect, read_packet, register, write_packet, SECRET,
+    };
+    use rmc_proto::{AuthRequest, DeviceRole, ListDevicesRequest, Packet};
+
+    #[test]
+    fn proof_roundtrip() {
+        let nonce = new_nonce();
+        assert_eq!(nonce.len(), NONCE_LEN * 2);
+        let proof = compute_proof(SECRET, &nonce);
+        assert!(verify_proof(SECRET, &nonce, &proof));
+        assert!(!verify_proof("other", &nonce, &proof));
+        assert!(!verify_proof(SECRET, &nonce, "not hex"));
+    }
+
+    #[tokio::test]
+    async fn challenge_response_over_loopback() {
+        let mut stream = connect().await;
+        register(&mut stream, DeviceRole::Controller, "").await;
+        authenticate(&mut stream, "default", SECRET).await;
+        let response = read_packet!(&mut stream, Packet::AuthResponse);
+        assert!(response.ok, "{}", response.error);
+
+        // 认证后无需再携带密钥
+        write_packet(&mut stream, ListDevicesRequest {}).await;
+        let response = read_packet!(&mut stream, Packet::ListDevicesResponse);
+        assert!(response.ok, "{}", response.error);
+    }
+
+    #[tokio::test]
+    async fn wrong_proof_is_rejected() {
+        let mut stream = connect().await;
+        let challenge = read_packet!(&mut stream, Packet::AuthChallengeNtf);
+        register(&mut stream, DeviceRole::Controller, "").await;
+
+        write_packet(
+            &mut stream,
+            AuthRequest {
+                name: "default".to_string(),
+                proof: compute_proof("wrong-secret", &challenge.nonce).into(),
+            },
+        )
+        .await;
+        let response = read_packet!(&mut stream, Packet::AuthResponse);
+        assert!(!response.ok);
+
+        // 挑战只能使用一次
+        write_packet(
+            &mut stream,
+            AuthRequest {
+                name: "default".to_string(),
+                proof: compute_proof(SECRET, &challenge.nonce).into(),
+            },
+        )
+        .await;
+        let response = read_packet!(&mut stream, Packet::AuthResponse);
+        assert!(!response.ok);
+
+        write_packet(&mut stream, ListDevicesRequest {}).await;
+        let response = read_packet!(&mut stream, Packet::ListDevicesResponse);
+        assert!(!response.ok);
+    }
+}
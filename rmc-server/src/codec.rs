@@ -0,0 +1,23 @@
+use rmc_proto::Encoding;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// 会话当前使用的发送编码, 协商完成前为 Json
+#[derive(Default)]
+pub struct SessionEncoding(AtomicU8);
+
+impl SessionEncoding {
+    pub fn get(&self) -> Encoding {
+        match self.0.load(Ordering::Relaxed) {
+            0 => Encoding::Json,
+            _ => Encoding::MessagePack,
+        }
+    }
+
+    pub fn set(&self, encoding: Encoding) {
+        let value = match encoding {
+            Encoding::Json => 0,
+            Encoding::MessagePack => 1,
+        };
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
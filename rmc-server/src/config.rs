@@ -0,0 +1,698 @@
+use crate::logging::{self, LogFormat};
+use crate::net;
+use crate::net::writer_queue::{self, QueueConfig};
+use crate::Opts;
+use anyhow::anyhow;
+use rmc_proto::Secret;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+/// 服务器配置, 从 TOML 文件加载, 命令行参数优先
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// 监听列表, 所有监听共享同一个全局上下文
+    pub listeners: Vec<ListenerConfig>,
+    /// TLS证书, 供启用了tls的监听使用
+    pub tls: Option<TlsConfig>,
+    pub auth: AuthConfig,
+    pub http: HttpConfig,
+    pub metrics: MetricsConfig,
+    pub timeouts: TimeoutsConfig,
+    pub limits: LimitsConfig,
+    pub log: LogConfig,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    Tcp,
+    Kcp,
+    Udp,
+    Ws,
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub protocol: Protocol,
+    /// 监听地址
+    pub addr: String,
+    /// 是否启用TLS, 仅支持tcp、kcp和ws
+    #[serde(default)]
+    pub tls: bool,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// 证书文件(PEM)
+    pub certificate: String,
+    /// 私钥文件(PEM)
+    pub key: String,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// 单一授权码, 可控制所有设备
+    pub authorization_code: Option<Secret>,
+    /// 访问控制文件, 优先于 authorization_code
+    pub access_control_file: Option<String>,
+    /// 设备注册前须先认证, 且凭据有完全控制权限并可访问注册的token;
+    /// 配置了多个凭据时总是开启, 否则任何人都可以注册任意token
+    pub require_device_auth: bool,
+}
+
+/// HTTP控制接口, 未配置监听地址时不启动
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub listen_addr: Option<String>,
+}
+
+/// 单独的 `GET /metrics` 监听, 未配置时只能通过HTTP控制接口访问
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub listen_addr: Option<String>,
+}
+
+/// 日志配置
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// 日志级别, 可按模块设置, 如 `info,rmc_server::net=debug`
+    pub level: String,
+    pub format: LogFormat,
+}
+
+/// 超时配置, 单位秒
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    /// 检查是否需要发送心跳的间隔
+    pub ping_interval_secs: u64,
+    /// 会话空闲超过该时间后发送心跳
+    pub ping_idle_secs: u64,
+    /// 超过该时间未收到任何消息(包括心跳回复)的会话被关闭
+    pub session_idle_secs: u64,
+    /// 会话必须在此时间内完成注册
+    pub register_secs: u64,
+    /// 设备确认按键事件的超时时间
+    pub media_key_ack_secs: u64,
+    /// UDP会话空闲超时
+    pub udp_idle_secs: u64,
+    /// TLS握手超时
+    pub tls_handshake_secs: u64,
+    /// 优雅退出等待时间
+    pub shutdown_grace_secs: u64,
+    /// 发送队列持续超出容量多久后断开会话
+    pub send_queue_overflow_secs: u64,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// 单个消息包最大字节数
+    pub max_frame_size: usize,
+    /// 封面缓存占用的最大字节数
+    pub artwork_cache_size: usize,
+    /// 每个会话发送队列的容量(消息数)
+    pub send_queue_capacity: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listeners: vec![ListenerConfig {
+                protocol: Protocol::Tcp,
+                addr: "0.0.0.0:8000".to_string(),
+                tls: false,
+            }],
+            tls: None,
+            auth: AuthConfig::default(),
+            http: HttpConfig::default(),
+            metrics: MetricsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            limits: LimitsConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn filter(&self) -> anyhow::Result<EnvFilter> {
+        logging::parse_filter(&self.level).map_err(|err| anyhow!("log.level: {err}"))
+    }
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval_secs: 2,
+            ping_idle_secs: 10,
+            session_idle_secs: 30,
+            register_secs: 10,
+            media_key_ack_secs: 5,
+            udp_idle_secs: net::DEFAULT_UDP_IDLE_TIMEOUT.as_secs(),
+            tls_handshake_secs: net::DEFAULT_TLS_HANDSHAKE_TIMEOUT.as_secs(),
+            shutdown_grace_secs: net::DEFAULT_SHUTDOWN_TIMEOUT.as_secs(),
+            send_queue_overflow_secs: writer_queue::DEFAULT_QUEUE_OVERFLOW_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 1024 * 1024 * 2,
+            artwork_cache_size: 1024 * 1024 * 32,
+            send_queue_capacity: writer_queue::DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+}
+
+/// 单个消息包大小上限, 防止配置错误导致内存耗尽
+const MAX_FRAME_SIZE_LIMIT: usize = 1024 * 1024 * 64;
+
+impl Config {
+    /// 加载配置文件(如果有), 再用命令行参数覆盖, 最后校验
+    pub fn load(opts: &Opts) -> anyhow::Result<Self> {
+        let mut config = match &opts.config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .map_err(|err| anyhow!("failed to read config file ({path}): {err}"))?;
+                toml::from_str(&content)
+                    .map_err(|err| anyhow!("failed to parse config file ({path}): {err}"))?
+            }
+            None => Config::default(),
+        };
+
+        // 命令行指定了监听时替换配置文件中的全部监听
+        let mut listeners = opts
+            .listen
+            .iter()
+            .map(|url| url.parse())
+            .collect::<anyhow::Result<Vec<ListenerConfig>>>()?;
+        if let Some(listen_addr) = &opts.listen_addr {
+            listeners.push(ListenerConfig {
+                protocol: Protocol::Tcp,
+                addr: listen_addr.clone(),
+                tls: false,
+            });
+        }
+        if !listeners.is_empty() {
+            config.listeners = listeners;
+        }
+        if let Some(authorization_code) = &opts.authorization_code {
+            config.auth.authorization_code = Some(authorization_code.clone());
+        }
+        if let Some(access_control_file) = &opts.access_control_file {
+            config.auth.access_control_file = Some(access_control_file.clone());
+        }
+        if let Some(http_listen_addr) = &opts.http_listen_addr {
+            config.http.listen_addr = Some(http_listen_addr.clone());
+        }
+        if let Some(metrics_listen_addr) = &opts.metrics_listen_addr {
+            config.metrics.listen_addr = Some(metrics_listen_addr.clone());
+        }
+        if let (Some(certificate), Some(key)) = (&opts.tls_certificate, &opts.tls_key) {
+            config.tls = Some(TlsConfig {
+                certificate: certificate.clone(),
+                key: key.clone(),
+            });
+        }
+        if let Some(max_frame_size) = opts.max_frame_size {
+            config.limits.max_frame_size = max_frame_size;
+        }
+        if let Some(log_level) = &opts.log_level {
+            config.log.level = log_level.clone();
+        }
+        if let Some(log_format) = opts.log_format {
+            config.log.format = log_format;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.listeners.is_empty() {
+            return Err(anyhow!("at least one listener is required"));
+        }
+        let mut bound = Vec::new();
+        for listener in &self.listeners {
+            let addr = listener
+                .addr
+                .parse::<SocketAddr>()
+                .map_err(|err| anyhow!("invalid listener addr ({}): {err}", listener.addr))?;
+            if listener.tls && listener.protocol == Protocol::Udp {
+                return Err(anyhow!("{listener}: tls is not supported over udp"));
+            }
+            if listener.tls && self.tls.is_none() {
+                return Err(anyhow!("{listener}: tls listener requires a [tls] section"));
+            }
+            // kcp和udp都占用udp端口, ws占用tcp端口
+            let is_udp = matches!(listener.protocol, Protocol::Kcp | Protocol::Udp);
+            if bound.contains(&(is_udp, addr)) {
+                return Err(anyhow!(
+                    "{listener}: address already used by another listener"
+                ));
+            }
+            bound.push((is_udp, addr));
+        }
+
+        let mut http_addrs = vec![];
+        for (name, listen_addr) in [
+            ("http.listen_addr", &self.http.listen_addr),
+            ("metrics.listen_addr", &self.metrics.listen_addr),
+        ] {
+            if let Some(listen_addr) = listen_addr {
+                let addr = listen_addr
+                    .parse::<SocketAddr>()
+                    .map_err(|err| anyhow!("invalid {name} ({listen_addr}): {err}"))?;
+                // HTTP接口与协议监听的tcp端口不能重复
+                if http_addrs.contains(&addr) || bound.contains(&(false, addr)) {
+                    return Err(anyhow!("{name}: address already used by another listener"));
+                }
+                http_addrs.push(addr);
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            check_file_exists("tls.certificate", &tls.certificate)?;
+            check_file_exists("tls.key", &tls.key)?;
+        }
+
+        match (
+            &self.auth.access_control_file,
+            &self.auth.authorization_code,
+        ) {
+            (Some(path), _) => check_file_exists("auth.access_control_file", path)?,
+            (None, Some(authorization_code)) if authorization_code.is_empty() => {
+                return Err(anyhow!("auth.authorization_code must not be empty"));
+            }
+            (None, Some(_)) => {}
+            (None, None) => {
+                return Err(anyhow!(
+                    "either auth.access_control_file or auth.authorization_code is required \
+                     (--access-control-file / --authorization-code)"
+                ));
+            }
+        }
+
+        let timeouts = &self.timeouts;
+        for (name, value) in [
+            ("timeouts.ping_interval_secs", timeouts.ping_interval_secs),
+            ("timeouts.ping_idle_secs", timeouts.ping_idle_secs),
+            ("timeouts.session_idle_secs", timeouts.session_idle_secs),
+            ("timeouts.register_secs", timeouts.register_secs),
+            ("timeouts.media_key_ack_secs", timeouts.media_key_ack_secs),
+            ("timeouts.udp_idle_secs", timeouts.udp_idle_secs),
+            ("timeouts.tls_handshake_secs", timeouts.tls_handshake_secs),
+            ("timeouts.shutdown_grace_secs", timeouts.shutdown_grace_secs),
+            (
+                "timeouts.send_queue_overflow_secs",
+                timeouts.send_queue_overflow_secs,
+            ),
+        ] {
+            if value == 0 {
+                return Err(anyhow!("{name} must be greater than 0"));
+            }
+        }
+        if timeouts.ping_interval_secs > timeouts.ping_idle_secs {
+            return Err(anyhow!(
+                "timeouts.ping_interval_secs ({}) must not exceed timeouts.ping_idle_secs ({})",
+                timeouts.ping_interval_secs,
+                timeouts.ping_idle_secs
+            ));
+        }
+        // 至少要留出一次心跳的时间
+        if timeouts.session_idle_secs <= timeouts.ping_idle_secs + timeouts.ping_interval_secs {
+            return Err(anyhow!(
+                "timeouts.session_idle_secs ({}) must exceed timeouts.ping_idle_secs + timeouts.ping_interval_secs ({})",
+                timeouts.session_idle_secs,
+                timeouts.ping_idle_secs + timeouts.ping_interval_secs
+            ));
+        }
+
+        let max_frame_size = self.limits.max_frame_size;
+        if max_frame_size == 0 || max_frame_size > MAX_FRAME_SIZE_LIMIT {
+            return Err(anyhow!(
+                "limits.max_frame_size ({max_frame_size}) must be between 1 and {MAX_FRAME_SIZE_LIMIT}"
+            ));
+        }
+
+        if self.limits.send_queue_capacity == 0 {
+            return Err(anyhow!("limits.send_queue_capacity must be greater than 0"));
+        }
+
+        self.log.filter()?;
+
+        Ok(())
+    }
+
+    /// 每个会话发送队列的配置
+    pub fn queue_config(&self) -> QueueConfig {
+        QueueConfig {
+            capacity: self.limits.send_queue_capacity,
+            overflow_timeout: Duration::from_secs(self.timeouts.send_queue_overflow_secs),
+        }
+    }
+}
+
+impl TimeoutsConfig {
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_secs(self.ping_interval_secs)
+    }
+
+    pub fn ping_idle(&self) -> Duration {
+        Duration::from_secs(self.ping_idle_secs)
+    }
+
+    pub fn session_idle(&self) -> Duration {
+        Duration::from_secs(self.session_idle_secs)
+    }
+
+    pub fn register(&self) -> Duration {
+        Duration::from_secs(self.register_secs)
+    }
+
+    pub fn media_key_ack(&self) -> Duration {
+        Duration::from_secs(self.media_key_ack_secs)
+    }
+
+    pub fn udp_idle(&self) -> Duration {
+        Duration::from_secs(self.udp_idle_secs)
+    }
+
+    pub fn tls_handshake(&self) -> Duration {
+        Duration::from_secs(self.tls_handshake_secs)
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_secs)
+    }
+}
+
+/// 解析命令行监听参数, 格式为 scheme://addr
+///
+/// scheme: tcp, tls(tcp+tls), kcp, kcps(kcp+tls), udp, ws, wss(ws+tls)
+impl FromStr for ListenerConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let (scheme, addr) = url
+            .split_once("://")
+            .ok_or_else(|| anyhow!("invalid listen url ({url}), expected scheme://addr"))?;
+        let (protocol, tls) = match scheme {
+            "tcp" => (Protocol::Tcp, false),
+            "tls" => (Protocol::Tcp, true),
+            "kcp" => (Protocol::Kcp, false),
+            "kcps" => (Protocol::Kcp, true),
+            "udp" => (Protocol::Udp, false),
+            "ws" => (Protocol::Ws, false),
+            "wss" => (Protocol::Ws, true),
+            _ => {
+                return Err(anyhow!(
+                    "unknown listen scheme ({scheme}), expected one of tcp, tls, kcp, kcps, udp, ws, wss"
+                ))
+            }
+        };
+        Ok(Self {
+            protocol,
+            addr: addr.to_string(),
+            tls,
+        })
+    }
+}
+
+impl ListenerConfig {
+    /// 监听地址的 scheme, 与命令行格式一致
+    pub fn scheme(&self) -> &'static str {
+        match (self.protocol, self.tls) {
+            (Protocol::Tcp, false) => "tcp",
+            (Protocol::Tcp, true) => "tls",
+            (Protocol::Kcp, false) => "kcp",
+            (Protocol::Kcp, true) => "kcps",
+            (Protocol::Udp, _) => "udp",
+            (Protocol::Ws, false) => "ws",
+            (Protocol::Ws, true) => "wss",
+        }
+    }
+}
+
+impl std::fmt::Display for ListenerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}", self.scheme(), self.addr)
+    }
+}
+
+fn check_file_exists(name: &str, path: &str) -> anyhow::Result<()> {
+    if Path::new(path).is_file() {
+        Ok(())
+    } else {
+        Err(anyhow!("{name}: file not found ({path})"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// 只配置授权码, 其余使用默认值
+    const MINIMAL: &str = "[auth]\nauthorization_code = \"123456\"\n";
+
+    fn parse(content: &str) -> anyhow::Result<Config> {
+        let config: Config = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate_error(content: &str) -> String {
+        parse(&format!("{content}\n{MINIMAL}"))
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn example_file_parses() {
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        assert_eq!(config.listeners.len(), 1);
+        assert_eq!(config.listeners[0].protocol, Protocol::Tcp);
+        // 引用的访问控制文件随仓库提供, 测试在包目录下运行
+        let path = config.auth.access_control_file.unwrap();
+        assert_eq!(path, "access_control.example.toml");
+        crate::access_control::AccessControl::load(&path).unwrap();
+    }
+
+    #[test]
+    fn file_values_override_defaults() {
+        let config = parse(&format!(
+            "{MINIMAL}
+            [[listeners]]
+            protocol = \"kcp\"
+            addr = \"127.0.0.1:9000\"
+
+            [[listeners]]
+            protocol = \"ws\"
+            addr = \"127.0.0.1:9000\"
+
+            [timeouts]
+            register_secs = 3
+
+            [limits]
+            send_queue_capacity = 16
+            "
+        ))
+        .unwrap();
+
+        let listeners: Vec<String> = config.listeners.iter().map(|x| x.to_string()).collect();
+        assert_eq!(listeners, ["kcp://127.0.0.1:9000", "ws://127.0.0.1:9000"]);
+        assert_eq!(config.timeouts.register(), Duration::from_secs(3));
+        assert_eq!(
+            config.timeouts.ping_idle_secs,
+            TimeoutsConfig::default().ping_idle_secs
+        );
+        assert_eq!(config.queue_config().capacity, 16);
+        assert_eq!(
+            config.auth.authorization_code.as_ref().map(Secret::expose),
+            Some("123456")
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for content in [
+            "listen_addr = \"0.0.0.0:8000\"",
+            "[timeouts]\nping_secs = 1",
+            "[auth]\nauthorization = \"x\"",
+            "[[listeners]]\nprotocol = \"tcp\"\naddr = \"0.0.0.0:8000\"\nport = 1",
+        ] {
+            let err = toml::from_str::<Config>(content).unwrap_err();
+            assert!(
+                err.to_string().contains("unknown field"),
+                "{content}: {err}"
+            );
+        }
+        assert!(toml::from_str::<Config>(
+            "[[listeners]]\nprotocol = \"sctp\"\naddr = \"0.0.0.0:1\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let tcp = |addr: &str| format!("[[listeners]]\nprotocol = \"tcp\"\naddr = \"{addr}\"\n");
+        let cases = [
+            ("listeners = []".to_string(), "at least one listener is required"),
+            (
+                tcp("localhost"),
+                "invalid listener addr (localhost): invalid socket address syntax",
+            ),
+            (
+                "[[listeners]]\nprotocol = \"udp\"\naddr = \"0.0.0.0:1\"\ntls = true".to_string(),
+                "udp://0.0.0.0:1: tls is not supported over udp",
+            ),
+            (
+                "[[listeners]]\nprotocol = \"tcp\"\naddr = \"0.0.0.0:1\"\ntls = true".to_string(),
+                "tls://0.0.0.0:1: tls listener requires a [tls] section",
+            ),
+            (
+                tcp("0.0.0.0:1") + &tcp("0.0.0.0:1"),
+                "tcp://0.0.0.0:1: address already used by another listener",
+            ),
+            (
+                "[http]\nlisten_addr = \"nowhere\"".to_string(),
+                "invalid http.listen_addr (nowhere): invalid socket address syntax",
+            ),
+            (
+                "[metrics]\nlisten_addr = \"nowhere\"".to_string(),
+                "invalid metrics.listen_addr (nowhere): invalid socket address syntax",
+            ),
+            (
+                "[http]\nlisten_addr = \"127.0.0.1:8080\"\n\
+                 [metrics]\nlisten_addr = \"127.0.0.1:8080\""
+                    .to_string(),
+                "metrics.listen_addr: address already used by another listener",
+            ),
+            (
+                "[tls]\ncertificate = \"/nonexistent/cert.pem\"\nkey = \"key.pem\"".to_string(),
+                "tls.certificate: file not found (/nonexistent/cert.pem)",
+            ),
+            (
+                "[timeouts]\nregister_secs = 0".to_string(),
+                "timeouts.register_secs must be greater than 0",
+            ),
+            (
+                "[timeouts]\nping_interval_secs = 20".to_string(),
+                "timeouts.ping_interval_secs (20) must not exceed timeouts.ping_idle_secs (10)",
+            ),
+            (
+                "[timeouts]\nsession_idle_secs = 12".to_string(),
+                "timeouts.session_idle_secs (12) must exceed timeouts.ping_idle_secs + timeouts.ping_interval_secs (12)",
+            ),
+            (
+                "[limits]\nmax_frame_size = 0".to_string(),
+                "limits.max_frame_size (0) must be between 1 and 67108864",
+            ),
+            (
+                "[limits]\nsend_queue_capacity = 0".to_string(),
+                "limits.send_queue_capacity must be greater than 0",
+            ),
+        ];
+        for (content, error) in cases {
+            assert_eq!(validate_error(&content), error, "{content}");
+        }
+
+        assert!(validate_error("[log]\nlevel = \"info,rmc_server=loud\"").starts_with("log.level:"));
+    }
+
+    #[test]
+    fn auth_is_required() {
+        let err = parse("").unwrap_err().to_string();
+        assert!(err
+            .starts_with("either auth.access_control_file or auth.authorization_code is required"));
+        assert_eq!(
+            parse("[auth]\nauthorization_code = \"\"")
+                .unwrap_err()
+                .to_string(),
+            "auth.authorization_code must not be empty"
+        );
+        assert_eq!(
+            parse("[auth]\naccess_control_file = \"/nonexistent/acl.toml\"")
+                .unwrap_err()
+                .to_string(),
+            "auth.access_control_file: file not found (/nonexistent/acl.toml)"
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let path = std::env::temp_dir().join(format!("rmc-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[[listeners]]
+            protocol = \"kcp\"
+            addr = \"127.0.0.1:9100\"
+
+            [auth]
+            authorization_code = \"from-file\"
+
+            [limits]
+            max_frame_size = 4096
+            send_queue_capacity = 8
+
+            [log]
+            level = \"warn\"
+            ",
+        )
+        .unwrap();
+
+        let opts = Opts::parse_from([
+            "rmc-server",
+            "--config",
+            path.to_str().unwrap(),
+            "--listen",
+            "ws://127.0.0.1:9101",
+            "--listen-addr",
+            "127.0.0.1:9102",
+            "--authorization-code",
+            "from-cli",
+            "--max-frame-size",
+            "8192",
+            "--log-format",
+            "json",
+        ]);
+        let config = Config::load(&opts);
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        // 命令行的监听替换配置文件中的全部监听
+        let listeners: Vec<String> = config.listeners.iter().map(|x| x.to_string()).collect();
+        assert_eq!(listeners, ["ws://127.0.0.1:9101", "tcp://127.0.0.1:9102"]);
+        assert_eq!(
+            config.auth.authorization_code.as_ref().map(Secret::expose),
+            Some("from-cli")
+        );
+        assert_eq!(config.limits.max_frame_size, 8192);
+        assert_eq!(config.log.format, LogFormat::Json);
+        // 命令行未指定的保留配置文件中的值
+        assert_eq!(config.limits.send_queue_capacity, 8);
+        assert_eq!(config.log.level, "warn");
+    }
+}
@@ -0,0 +1,404 @@
+use crate::access_control::Credential;
+use crate::metrics;
+use crate::net::writer_queue;
+use crate::player::{
+    deliver_media_command, deliver_media_key_event, list_devices, media_command_response,
+    media_key_event_response,
+};
+use crate::{GLOBAL_ACCESS_CONTROL, GLOBAL_CONTEXT, GLOBAL_METRICS};
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::info;
+use rmc_proto::{
+    ListDevicesResponse, MediaCommand, SendControlMediaKeyEventRequest,
+    SendControlMediaKeyEventResponse, SendMediaCommandRequest, SendMediaCommandResponse,
+    ACTION_DOWN, ACTION_UP, ERROR_DEVICE_OFFLINE, ERROR_NOT_AUTHENTICATED, ERROR_NO_PERMISSION,
+};
+use std::future::Future;
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+/// HTTP控制接口, 认证方式为 `Authorization: Bearer <凭据密钥>`, 认证失败计入 `rmc_auth_failures_total`
+///
+/// - `GET /health` 健康检查和发送队列统计, 无需认证
+/// - `GET /metrics` Prometheus 格式的运行指标, 无需认证
+/// - `GET /devices` 凭据可访问的在线设备
+/// - `POST /devices/{token}/keys` 发送按键, 请求体 `{"code": 85}`, 省略 action 时发送一次完整的按下和抬起
+/// - `POST /devices/{token}/commands` 发送媒体控制指令, 请求体 `{"type": "next"}`
+pub fn router() -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/devices", get(get_devices))
+        .route("/devices/:token/keys", post(post_key))
+        .route("/devices/:token/commands", post(post_command))
+        .merge(metrics::router())
+}
+
+/// 在 `addr` 上提供 `router` 的接口, 控制接口和单独的指标监听共用
+pub async fn run_server<A: ToSocketAddrs>(
+    addr: A,
+    router: Router,
+    shutdown_condition: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown_condition)
+        .await?;
+    info!("HTTP Server shutdown finish");
+    Ok(())
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct HealthResponse {
+    pub ok: bool,
+    /// 当前连接的会话数量
+    pub sessions: usize,
+    /// 所有会话发送队列中等待写入的消息数
+    pub queued_messages: usize,
+    /// 单个会话发送队列的最大深度
+    pub max_queue_depth: usize,
+    /// 发送队列满时丢弃的状态通知累计数量
+    pub dropped_messages: u64,
+    /// 因发送队列持续超限被断开的会话累计数量
+    pub overflow_disconnects: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PostKeyRequest {
+    pub code: u32,
+    #[serde(default)]
+    pub action: Option<u32>,
+}
+
+async fn health() -> Json<HealthResponse> {
+    let depths: Vec<usize> = GLOBAL_CONTEXT
+        .players
+        .lock()
+        .await
+        .values()
+        .map(|player| player.queue_depth())
+        .collect();
+    Json(HealthResponse {
+        ok: true,
+        sessions: depths.len(),
+        queued_messages: depths.iter().sum(),
+        max_queue_depth: depths.iter().copied().max().unwrap_or_default(),
+        dropped_messages: writer_queue::dropped_messages(),
+        overflow_disconnects: writer_queue::overflow_disconnects(),
+    })
+}
+
+async fn get_devices(headers: HeaderMap) -> (StatusCode, Json<ListDevicesResponse>) {
+    let Some(credential) = bearer_credential(&headers) else {
+        return (
+            unauthorized(),
+            Json(ListDevicesResponse {
+                ok: false,
+                error: ERROR_NOT_AUTHENTICATED.to_string(),
+                devices: vec![],
+            }),
+        );
+    };
+
+    (
+        StatusCode::OK,
+        Json(ListDevicesResponse {
+            ok: true,
+            error: "".to_string(),
+            devices: list_devices(credential).await,
+        }),
+    )
+}
+
+async fn post_key(
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    Json(request): Json<PostKeyRequest>,
+) -> (StatusCode, Json<SendControlMediaKeyEventResponse>) {
+    let credential = bearer_credential(&headers);
+    let actions = match request.action {
+        Some(action) => vec![action],
+        None => vec![ACTION_DOWN, ACTION_UP],
+    };
+
+    // 按下失败时不再发送抬起, 返回第一个事件的投递结果
+    let mut response = None;
+    for action in actions {
+        let result = deliver_media_key_event(
+            credential,
+            SendControlMediaKeyEventRequest {
+                action,
+                code: request.code,
+                token: token.clone(),
+            },
+            None,
+        )
+        .await;
+
+        let current = match result {
+            Ok(delivery) => media_key_event_response(&delivery),
+            Err(error) => SendControlMediaKeyEventResponse {
+                ok: false,
+                error: error.to_string(),
+                delivered: 0,
+                request_id: 0,
+            },
+        };
+        let ok = current.ok;
+        response.get_or_insert(current);
+        if !ok {
+            break;
+        }
+    }
+
+    let response = response.expect("at least one action");
+    (error_status(&response.error), Json(response))
+}
+
+async fn post_command(
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    Json(command): Json<MediaCommand>,
+) -> (StatusCode, Json<SendMediaCommandResponse>) {
+    let result = deliver_media_command(
+        bearer_credential(&headers),
+        SendMediaCommandRequest { token, command },
+        None,
+    )
+    .await;
+
+    let response = match result {
+        Ok(delivery) => media_command_response(&delivery),
+        Err(error) => SendMediaCommandResponse {
+            ok: false,
+            error: error.to_string(),
+            delivered: 0,
+            request_id: 0,
+        },
+    };
+    (error_status(&response.error), Json(response))
+}
+
+fn error_status(error: &str) -> StatusCode {
+    match error {
+        "" => StatusCode::OK,
+        ERROR_NOT_AUTHENTICATED => unauthorized(),
+        ERROR_NO_PERMISSION => StatusCode::FORBIDDEN,
+        ERROR_DEVICE_OFFLINE => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+/// 返回401并计入认证失败
+fn unauthorized() -> StatusCode {
+    GLOBAL_METRICS.auth_failures.inc();
+    StatusCode::UNAUTHORIZED
+}
+
+/// 从 Authorization 头中取出 Bearer 密钥并查找对应凭据
+fn bearer_credential(headers: &HeaderMap) -> Option<&'static Credential> {
+    let secret = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?
+        .trim();
+    GLOBAL_ACCESS_CONTROL.get()?.find_by_secret(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{connect_device, init_access_control, read_packet, SECRET};
+    use rmc_proto::{
+        Packet, ERROR_INVALID_COMMAND, ERROR_INVALID_KEY_CODE, ERROR_UNSUPPORTED_COMMAND,
+        KEYCODE_MEDIA_NEXT,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn start_http_server() -> std::net::SocketAddr {
+        init_access_control();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router()).await });
+        addr
+    }
+
+    /// 发送一个 HTTP/1.1 请求, 返回状态码和响应体
+    async fn request(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        secret: Option<&str>,
+        body: &str,
+    ) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let authorization = secret
+            .map(|secret| format!("Authorization: Bearer {secret}\r\n"))
+            .unwrap_or_default();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n{authorization}\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn health_does_not_require_auth() {
+        let addr = start_http_server().await;
+        let (status, body) = request(addr, "GET", "/health", None, "").await;
+        assert_eq!(status, 200);
+        let health: HealthResponse = serde_json::from_str(&body).unwrap();
+        assert!(health.ok);
+    }
+
+    #[tokio::test]
+    async fn metrics_are_exposed_as_text() {
+        let addr = start_http_server().await;
+        let (status, body) = request(addr, "GET", "/metrics", None, "").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("# TYPE rmc_auth_failures_total counter"));
+        assert!(body.contains("rmc_ping_rtt_seconds_bucket{le=\"+Inf\"}"));
+    }
+
+    #[tokio::test]
+    async fn wrong_bearer_is_rejected() {
+        let addr = start_http_server().await;
+        let auth_failures = GLOBAL_METRICS.auth_failures.get();
+        let (status, _) = request(addr, "GET", "/devices", Some("wrong"), "").await;
+        assert_eq!(status, 401);
+
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/devices/http-any/keys",
+            None,
+            r#"{"code": 85}"#,
+        )
+        .await;
+        assert_eq!(status, 401);
+        let response: SendControlMediaKeyEventResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.error, ERROR_NOT_AUTHENTICATED);
+        // 其他测试可能同时认证失败
+        assert!(GLOBAL_METRICS.auth_failures.get() >= auth_failures + 2);
+    }
+
+    #[tokio::test]
+    async fn key_is_routed_to_registered_device() {
+        let addr = start_http_server().await;
+
+        let (status, _) = request(
+            addr,
+            "POST",
+            "/devices/http-phone/keys",
+            Some(SECRET),
+            r#"{"code": 85}"#,
+        )
+        .await;
+        assert_eq!(status, 404);
+
+        let mut device = connect_device("http-phone").await;
+
+        let (status, body) = request(addr, "GET", "/devices", Some(SECRET), "").await;
+        assert_eq!(status, 200);
+        let devices: ListDevicesResponse = serde_json::from_str(&body).unwrap();
+        assert!(devices
+            .devices
+            .iter()
+            .any(|device| device.token == "http-phone"));
+
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/devices/http-phone/keys",
+            Some(SECRET),
+            r#"{"code": 85}"#,
+        )
+        .await;
+        assert_eq!(status, 200);
+        let response: SendControlMediaKeyEventResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.delivered, 1);
+
+        // 省略 action 时依次收到按下和抬起
+        let down = read_packet!(&mut device, Packet::PushMediaKeyEvent);
+        let up = read_packet!(&mut device, Packet::PushMediaKeyEvent);
+        assert_eq!((down.action, down.code), (ACTION_DOWN, 85));
+        assert_eq!((up.action, up.code), (ACTION_UP, 85));
+    }
+
+    #[tokio::test]
+    async fn command_is_translated_for_legacy_device() {
+        let addr = start_http_server().await;
+        let mut device = connect_device("http-legacy").await;
+
+        // 旧设备没有发送 Hello, 收到等价的按键事件
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/devices/http-legacy/commands",
+            Some(SECRET),
+            r#"{"type": "next"}"#,
+        )
+        .await;
+        assert_eq!(status, 200, "{body}");
+        let down = read_packet!(&mut device, Packet::PushMediaKeyEvent);
+        let up = read_packet!(&mut device, Packet::PushMediaKeyEvent);
+        assert_eq!((down.action, down.code), (ACTION_DOWN, KEYCODE_MEDIA_NEXT));
+        assert_eq!((up.action, up.code), (ACTION_UP, KEYCODE_MEDIA_NEXT));
+
+        // 没有等价按键的指令无法发送给旧设备
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/devices/http-legacy/commands",
+            Some(SECRET),
+            r#"{"type": "set_volume", "percent": 30}"#,
+        )
+        .await;
+        assert_eq!(status, 400);
+        let response: SendMediaCommandResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.error, ERROR_UNSUPPORTED_COMMAND);
+
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/devices/http-legacy/commands",
+            Some(SECRET),
+            r#"{"type": "set_volume", "percent": 300}"#,
+        )
+        .await;
+        assert_eq!(status, 400);
+        let response: SendMediaCommandResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.error, ERROR_INVALID_COMMAND);
+    }
+
+    #[tokio::test]
+    async fn non_media_key_code_is_rejected() {
+        let addr = start_http_server().await;
+        // KEYCODE_POWER
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/devices/http-any/keys",
+            Some(SECRET),
+            r#"{"code": 26}"#,
+        )
+        .await;
+        assert_eq!(status, 400);
+        let response: SendControlMediaKeyEventResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(response.error, ERROR_INVALID_KEY_CODE);
+    }
+}
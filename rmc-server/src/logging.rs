@@ -0,0 +1,105 @@
+use anyhow::anyhow;
+use std::net::SocketAddr;
+use tracing::field::Empty;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+
+/// 日志输出格式
+#[derive(serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// 每行一条可读文本
+    #[default]
+    Text,
+    /// 每行一个JSON对象
+    Json,
+}
+
+/// 解析日志级别过滤, 格式同 `RUST_LOG`: `info,rmc_server::net=debug,tungstenite=warn`
+pub fn parse_filter(directives: &str) -> anyhow::Result<EnvFilter> {
+    Ok(EnvFilter::builder().parse(directives)?)
+}
+
+/// 初始化全局日志并接收 `log` 宏的输出, 只能调用一次
+pub fn init(filter: EnvFilter, format: LogFormat) -> anyhow::Result<()> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_span_list(false)
+            .try_init(),
+    }
+    .map_err(|err| anyhow!("failed to initialize logging: {err}"))
+}
+
+/// 会话的日志span, 会话任务内输出的日志都带有会话id和地址
+///
+/// `role` 和 `token` 在注册后由 [`Span::record`] 填写, 会话派生的后台任务用 `in_current_span` 沿用
+pub fn session_span(session_id: u32, addr: SocketAddr) -> Span {
+    tracing::info_span!("session", session_id, %addr, role = Empty, token = Empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing::Instrument;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn invalid_filter_is_rejected() {
+        assert!(parse_filter("warn,rmc_server=info,rmc_server::net=debug").is_ok());
+        assert!(parse_filter("info,rmc_server=loud").is_err());
+    }
+
+    #[tokio::test]
+    async fn json_lines_carry_session_fields() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(parse_filter("info").unwrap())
+            .with_writer(move || writer.clone())
+            .json()
+            .flatten_event(true)
+            .with_span_list(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let addr: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        async {
+            Span::current().record("role", "device");
+            // 派生的任务沿用会话字段
+            tokio::spawn(async { tracing::info!("registered") }.in_current_span())
+                .await
+                .unwrap();
+        }
+        .instrument(session_span(7, addr))
+        .await;
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["message"], "registered");
+        assert_eq!(value["span"]["session_id"], 7);
+        assert_eq!(value["span"]["addr"], "127.0.0.1:9000");
+        assert_eq!(value["span"]["role"], "device");
+        assert!(value["span"].get("token").is_none());
+    }
+}
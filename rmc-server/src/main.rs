@@ -1,50 +1,285 @@
+mod access_control;
+mod artwork;
+mod auth;
+mod codec;
+mod config;
+mod http_api;
+mod logging;
+mod metrics;
 pub mod net;
 mod peer;
 mod player;
-mod proto;
+#[cfg(test)]
+mod test_support;
 
-use crate::net::session_delegate::SessionDelegate;
-use crate::net::tcp_server;
+use crate::access_control::AccessControl;
+use crate::artwork::ArtworkCache;
+use crate::config::{Config, ListenerConfig, Protocol};
+use crate::logging::LogFormat;
+use crate::metrics::Metrics;
+use crate::net::session_delegate::{CreateSessionDelegateCallback, SessionDelegate};
+use crate::net::{kcp_server, tcp_server, udp_server, ws_server};
 use crate::peer::Peer;
-use crate::player::Player;
+use crate::player::{PendingAck, PendingVolume, Player};
 use clap::Parser;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use log::{debug, info, warn};
+use once_cell::sync::{Lazy, OnceCell};
+use rmc_proto::Secret;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::select;
 use tokio::signal;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinSet;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Opts {
-    /// The address to listen on
-    #[arg(long, default_value = "0.0.0.0:8000")]
-    pub listen_addr: String,
+    /// Configuration file (TOML), command line arguments take precedence over it
+    #[arg(long, short)]
+    pub config: Option<String>,
 
-    /// Authorization code
-    #[arg(long, default_value = "abc123")]
-    pub authorization_code: String,
+    /// Listeners as scheme://addr, may be repeated. scheme: tcp, tls, kcp, kcps, udp, ws, wss
+    #[arg(long)]
+    pub listen: Vec<String>,
+
+    /// Plain TCP address to listen on, same as --listen tcp://ADDR [default: 0.0.0.0:8000]
+    #[arg(long)]
+    pub listen_addr: Option<String>,
+
+    /// Authorization code, grants full control of every device
+    #[arg(long)]
+    pub authorization_code: Option<Secret>,
+
+    /// Access control file (TOML), maps controller credentials to device tokens
+    #[arg(long)]
+    pub access_control_file: Option<String>,
+
+    /// Address of the HTTP control API, disabled if not set
+    #[arg(long)]
+    pub http_listen_addr: Option<String>,
+
+    /// Address serving only GET /metrics, which is otherwise available on the HTTP control API
+    #[arg(long)]
+    pub metrics_listen_addr: Option<String>,
+
+    /// TLS certificate file (PEM)
+    #[arg(long, requires = "tls_key")]
+    pub tls_certificate: Option<String>,
+
+    /// TLS private key file (PEM)
+    #[arg(long, requires = "tls_certificate")]
+    pub tls_key: Option<String>,
+
+    /// Maximum size of a single message in bytes [default: 2097152]
+    #[arg(long)]
+    pub max_frame_size: Option<usize>,
+
+    /// Log filter in RUST_LOG syntax, e.g. info,rmc_server::net=debug [default: info]
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// Log output format [default: text]
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+}
+
+pub static GLOBAL_OPTS: Lazy<Opts> = Lazy::new(Opts::parse);
+
+static GLOBAL_CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// 全局配置, 未加载时(如单元测试)使用默认配置
+pub fn config() -> &'static Config {
+    GLOBAL_CONFIG.get_or_init(Config::default)
 }
 
-pub static GLOBAL_OPTS: Lazy<Opts> = Lazy::new(|| Opts::parse());
+pub static GLOBAL_ACCESS_CONTROL: OnceCell<AccessControl> = OnceCell::new();
+
+/// 设备注册前是否须先认证: 配置开启, 或配置了多个凭据
+pub fn device_auth_required() -> bool {
+    config().auth.require_device_auth
+        || GLOBAL_ACCESS_CONTROL
+            .get()
+            .is_some_and(AccessControl::requires_device_auth)
+}
+
+pub static GLOBAL_METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
 
 pub struct GlobalContext {
     players: Mutex<HashMap<u32, Arc<Player>>>,
+    /// 会话id -> 控制端订阅的设备token
+    subscriptions: Mutex<HashMap<u32, HashSet<String>>>,
+    /// token -> 注册在该token下的设备会话id
+    devices: Mutex<HashMap<String, HashSet<u32>>>,
+    /// request_id -> 等待设备确认的按键事件
+    pending_acks: Mutex<HashMap<u64, PendingAck>>,
+    /// 设备上传的封面
+    artworks: Mutex<ArtworkCache>,
+    /// hash -> 等待该封面上传完成的控制端会话id
+    artwork_waiters: Mutex<HashMap<String, HashSet<u32>>>,
+    /// token -> 合并中的音量调整, 存在即表示该设备处于合并窗口内
+    volume_changes: Mutex<HashMap<String, PendingVolume>>,
 }
 
 pub static GLOBAL_CONTEXT: Lazy<GlobalContext> = Lazy::new(|| GlobalContext {
     players: Mutex::new(HashMap::new()),
+    subscriptions: Mutex::new(HashMap::new()),
+    devices: Mutex::new(HashMap::new()),
+    pending_acks: Mutex::new(HashMap::new()),
+    artworks: Mutex::new(ArtworkCache::new(config().limits.artwork_cache_size)),
+    artwork_waiters: Mutex::new(HashMap::new()),
+    volume_changes: Mutex::new(HashMap::new()),
 });
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     Lazy::force(&GLOBAL_OPTS);
 
-    tcp_server::Builder::new(Box::new(|| -> Box<dyn SessionDelegate> {
-        Box::new(Peer::new())
-    }))
-    .build(GLOBAL_OPTS.listen_addr.as_str(), signal::ctrl_c())
-    .await?;
+    let _ = GLOBAL_CONFIG.set(Config::load(&GLOBAL_OPTS)?);
+    let config = config();
+    logging::init(config.log.filter()?, config.log.format)?;
+    debug!("config: {:?}", config);
+
+    let access_control = match (
+        &config.auth.access_control_file,
+        &config.auth.authorization_code,
+    ) {
+        (Some(path), _) => AccessControl::load(path)?,
+        (None, Some(authorization_code)) => {
+            AccessControl::with_authorization_code(authorization_code.clone())
+        }
+        (None, None) => unreachable!("checked by Config::validate"),
+    };
+    let _ = GLOBAL_ACCESS_CONTROL.set(access_control);
+    if device_auth_required() {
+        info!("devices must authenticate with a credential that can access their token before registering");
+    } else {
+        warn!("INSECURE: devices register without authentication, anyone who knows a token can register under it, receive its key events and report fake state; set auth.require_device_auth = true");
+    }
+
+    // 所有监听共用一个退出信号
+    let (shutdown_tx, _) = broadcast::channel::<()>(1);
+    let mut listeners = JoinSet::new();
+    for listener in &config.listeners {
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        listeners.spawn(async move {
+            let shutdown = async move {
+                let _ = shutdown_rx.recv().await;
+            };
+            run_listener(listener, shutdown)
+                .await
+                .map_err(|err| anyhow::anyhow!("{listener}: {err}"))
+        });
+    }
+
+    if let Some(http_listen_addr) = &config.http.listen_addr {
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        listeners.spawn(async move {
+            let shutdown = async move {
+                let _ = shutdown_rx.recv().await;
+            };
+            http_api::run_server(http_listen_addr.as_str(), http_api::router(), shutdown)
+                .await
+                .map_err(|err| anyhow::anyhow!("http://{http_listen_addr}: {err}"))
+        });
+    }
+
+    if let Some(metrics_listen_addr) = &config.metrics.listen_addr {
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        listeners.spawn(async move {
+            let shutdown = async move {
+                let _ = shutdown_rx.recv().await;
+            };
+            http_api::run_server(metrics_listen_addr.as_str(), metrics::router(), shutdown)
+                .await
+                .map_err(|err| anyhow::anyhow!("http://{metrics_listen_addr}: {err}"))
+        });
+    }
+
+    let mut result = Ok(());
+    loop {
+        select! {
+            _ = signal::ctrl_c() => {
+                let _ = shutdown_tx.send(());
+            }
+            res = listeners.join_next() => match res {
+                Some(res) => {
+                    // 任意监听异常退出时停止所有监听
+                    if let Err(err) = res? {
+                        if result.is_ok() {
+                            result = Err(err);
+                        }
+                        let _ = shutdown_tx.send(());
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    result
+}
+
+/// [`transport`] 监听的协议, 用于按协议统计会话
+fn create_peer(transport: &'static str) -> CreateSessionDelegateCallback {
+    Box::new(move || -> Box<dyn SessionDelegate> { Box::new(Peer::new(transport)) })
+}
+
+async fn run_listener(
+    listener: &ListenerConfig,
+    shutdown: impl std::future::Future,
+) -> anyhow::Result<()> {
+    let config = config();
+    let timeouts = &config.timeouts;
+    let tls = config.tls.as_ref().filter(|_| listener.tls);
+    let queue_config = config.queue_config();
+    info!("listening on {listener}");
 
+    match listener.protocol {
+        Protocol::Tcp => {
+            let mut builder = tcp_server::Builder::new(create_peer(listener.scheme()))
+                .set_tls_handshake_timeout(timeouts.tls_handshake())
+                .set_shutdown_timeout(timeouts.shutdown_grace())
+                .set_queue_config(queue_config);
+            if let Some(tls) = tls {
+                builder = builder.set_tls_configuration(tls.certificate.as_str(), tls.key.as_str());
+            }
+            builder.build(listener.addr.as_str(), shutdown).await?;
+        }
+        Protocol::Kcp => {
+            let mut builder = kcp_server::Builder::new(create_peer(listener.scheme()))
+                .set_tls_handshake_timeout(timeouts.tls_handshake())
+                .set_shutdown_timeout(timeouts.shutdown_grace())
+                .set_queue_config(queue_config);
+            if let Some(tls) = tls {
+                builder = builder.set_tls_configuration(tls.certificate.as_str(), tls.key.as_str());
+            }
+            builder.build(listener.addr.as_str(), shutdown).await?;
+        }
+        Protocol::Ws => {
+            let mut builder = ws_server::Builder::new(create_peer(listener.scheme()))
+                .set_handshake_timeout(timeouts.tls_handshake())
+                .set_shutdown_timeout(timeouts.shutdown_grace())
+                .set_max_message_size(config.limits.max_frame_size)
+                .set_queue_config(queue_config);
+            if let Some(tls) = tls {
+                builder = builder.set_tls_configuration(tls.certificate.as_str(), tls.key.as_str());
+            }
+            builder.build(listener.addr.as_str(), shutdown).await?;
+        }
+        Protocol::Udp => {
+            let socket = UdpSocket::bind(listener.addr.as_str()).await?;
+            udp_server::run_server(
+                socket,
+                create_peer(listener.scheme()),
+                shutdown,
+                timeouts.udp_idle(),
+                timeouts.shutdown_grace(),
+                queue_config,
+            )
+            .await;
+        }
+    }
     Ok(())
 }
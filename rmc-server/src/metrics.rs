@@ -0,0 +1,259 @@
+use crate::net::{self, writer_queue};
+use crate::GLOBAL_CONTEXT;
+use axum::http::header;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use rmc_proto::DeviceRole;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// 服务器运行指标, 由 `GET /metrics` 以 Prometheus 文本格式输出
+pub struct Metrics {
+    registry: Registry,
+    /// 传输协议 -> 当前会话数
+    pub sessions: IntGaugeVec,
+    /// 消息名 -> 收到的消息数
+    pub messages_received: IntCounterVec,
+    /// 消息名 -> 发送的消息数
+    pub messages_sent: IntCounterVec,
+    /// 协议认证和HTTP接口 Bearer 认证失败的次数
+    pub auth_failures: IntCounter,
+    /// 无法分帧或解码的消息数
+    pub decode_errors: IntCounter,
+    /// 每次按键事件或媒体指令投递到的设备数
+    pub key_event_fanout: Histogram,
+    /// 服务器测得的心跳往返时间(秒)
+    pub ping_rtt: Histogram,
+    /// 以下指标由其他模块统计, 在输出时更新
+    snapshot: Snapshot,
+}
+
+/// 输出时从会话列表和网络层读取的指标
+struct Snapshot {
+    /// 多个请求同时输出时, 避免更新交错
+    lock: Mutex<()>,
+    sessions_by_role: IntGaugeVec,
+    bytes_received: IntCounter,
+    bytes_sent: IntCounter,
+    queue_depth: IntGauge,
+    max_queue_depth: IntGauge,
+    dropped_messages: IntCounter,
+    overflow_disconnects: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        Self {
+            sessions: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("rmc_sessions", "Active sessions per transport"),
+                    &["transport"],
+                ),
+            ),
+            messages_received: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("rmc_messages_received_total", "Messages received by name"),
+                    &["name"],
+                ),
+            ),
+            messages_sent: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("rmc_messages_sent_total", "Messages sent by name"),
+                    &["name"],
+                ),
+            ),
+            auth_failures: register(
+                &registry,
+                IntCounter::new("rmc_auth_failures_total", "Failed authentication attempts"),
+            ),
+            decode_errors: register(
+                &registry,
+                IntCounter::new(
+                    "rmc_frame_decode_errors_total",
+                    "Frames that could not be decoded",
+                ),
+            ),
+            key_event_fanout: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "rmc_key_event_fanout",
+                        "Devices reached per key event or media command",
+                    )
+                    .buckets(vec![0.0, 1.0, 2.0, 4.0, 8.0, 16.0]),
+                ),
+            ),
+            ping_rtt: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "rmc_ping_rtt_seconds",
+                        "Ping round-trip time measured by the server",
+                    )
+                    .buckets(vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+                ),
+            ),
+            snapshot: Snapshot {
+                lock: Mutex::new(()),
+                sessions_by_role: register(
+                    &registry,
+                    IntGaugeVec::new(
+                        Opts::new("rmc_sessions_by_role", "Active sessions per role"),
+                        &["role"],
+                    ),
+                ),
+                bytes_received: register(
+                    &registry,
+                    IntCounter::new(
+                        "rmc_bytes_received_total",
+                        "Bytes received from all sessions",
+                    ),
+                ),
+                bytes_sent: register(
+                    &registry,
+                    IntCounter::new("rmc_bytes_sent_total", "Bytes sent to all sessions"),
+                ),
+                queue_depth: register(
+                    &registry,
+                    IntGauge::new(
+                        "rmc_send_queue_depth",
+                        "Messages waiting in all send queues",
+                    ),
+                ),
+                max_queue_depth: register(
+                    &registry,
+                    IntGauge::new(
+                        "rmc_send_queue_depth_max",
+                        "Deepest send queue of a single session",
+                    ),
+                ),
+                dropped_messages: register(
+                    &registry,
+                    IntCounter::new(
+                        "rmc_send_queue_dropped_total",
+                        "State notifications dropped from full send queues",
+                    ),
+                ),
+                overflow_disconnects: register(
+                    &registry,
+                    IntCounter::new(
+                        "rmc_send_queue_overflow_disconnects_total",
+                        "Sessions closed because their send queue stayed over capacity",
+                    ),
+                ),
+            },
+            registry,
+        }
+    }
+
+    /// 输出所有指标, 会话角色和发送队列深度在此时统计
+    pub async fn render(&self) -> String {
+        let mut roles: BTreeMap<&str, i64> = BTreeMap::new();
+        let mut queue_depth = 0;
+        let mut max_queue_depth = 0;
+        let players: Vec<_> = GLOBAL_CONTEXT
+            .players
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect();
+        for player in players {
+            let role = match player.role().await {
+                Some(DeviceRole::Device) => "device",
+                Some(DeviceRole::Controller) => "controller",
+                None => "unregistered",
+            };
+            *roles.entry(role).or_default() += 1;
+            let depth = player.queue_depth();
+            queue_depth += depth;
+            max_queue_depth = max_queue_depth.max(depth);
+        }
+
+        let snapshot = &self.snapshot;
+        let _guard = snapshot.lock.lock().unwrap();
+        // 已断开的角色不再输出
+        snapshot.sessions_by_role.reset();
+        for (role, count) in roles {
+            snapshot
+                .sessions_by_role
+                .with_label_values(&[role])
+                .set(count);
+        }
+        snapshot.queue_depth.set(queue_depth as i64);
+        snapshot.max_queue_depth.set(max_queue_depth as i64);
+        for (counter, value) in [
+            (&snapshot.bytes_received, net::bytes_received()),
+            (&snapshot.bytes_sent, net::bytes_sent()),
+            (&snapshot.dropped_messages, writer_queue::dropped_messages()),
+            (
+                &snapshot.overflow_disconnects,
+                writer_queue::overflow_disconnects(),
+            ),
+        ] {
+            counter.reset();
+            counter.inc_by(value);
+        }
+
+        let mut out = Vec::new();
+        // 指标名和标签都是合法的, 编码不会失败
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut out);
+        String::from_utf8(out).unwrap_or_default()
+    }
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &Registry,
+    metric: prometheus::Result<T>,
+) -> T {
+    let metric = metric.expect("invalid metric");
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("duplicate metric");
+    metric
+}
+
+/// 只提供 `GET /metrics` 的路由, 可单独监听, 也合并在HTTP控制接口中
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(metrics))
+}
+
+async fn metrics() -> ([(header::HeaderName, &'static str); 1], String) {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        crate::GLOBAL_METRICS.render().await,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn metrics_are_rendered_as_text() {
+        let metrics = Metrics::new();
+        metrics.sessions.with_label_values(&["tcp"]).inc();
+        metrics.sessions.with_label_values(&["tcp"]).inc();
+        metrics.sessions.with_label_values(&["ws"]).inc();
+        metrics.sessions.with_label_values(&["tcp"]).dec();
+        metrics.ping_rtt.observe(0.02);
+        metrics.ping_rtt.observe(3.0);
+
+        let out = metrics.render().await;
+        assert!(out.contains("# TYPE rmc_sessions gauge"));
+        assert!(out.contains("rmc_sessions{transport=\"tcp\"} 1\n"));
+        assert!(out.contains("rmc_sessions{transport=\"ws\"} 1\n"));
+        assert!(out.contains("rmc_ping_rtt_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(out.contains("rmc_ping_rtt_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("rmc_ping_rtt_seconds_count 2\n"));
+        assert!(out.contains("# TYPE rmc_bytes_sent_total counter"));
+    }
+}
@@ -1,20 +1,21 @@
 use crate::net::session_delegate::CreateSessionDelegateCallback;
+use crate::net::writer_queue::QueueConfig;
 use crate::net::{net_session, tls};
 use log::{debug, error};
 use log::{info, trace};
 use std::future::Future;
-use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::ToSocketAddrs;
 use tokio::select;
 use tokio::sync::{broadcast, mpsc};
 use tokio_kcp::{KcpConfig, KcpListener};
-use tokio_rustls::rustls::ServerConfig;
 use tokio_rustls::TlsAcceptor;
 
 struct Server {
     notify_shutdown: broadcast::Sender<()>,
     shutdown_complete_tx: mpsc::Sender<()>,
+    tls_handshake_timeout: Duration,
+    queue_config: QueueConfig,
 }
 
 impl Server {
@@ -25,17 +26,7 @@ impl Server {
         tls_configuration: Option<tls::TlsConfiguration>,
     ) -> anyhow::Result<()> {
         let tls_acceptor: Option<TlsAcceptor> = match tls_configuration {
-            Some(tls_configuration) => {
-                let certs = super::tls::load_certs(&tls_configuration.certificate)?;
-                let keys = super::tls::load_private_key(&tls_configuration.key)?;
-
-                let server_config = ServerConfig::builder()
-                    .with_safe_defaults()
-                    .with_no_client_auth()
-                    .with_single_cert(certs, keys)?;
-
-                Some(TlsAcceptor::from(Arc::new(server_config)))
-            }
+            Some(tls_configuration) => Some(tls::new_acceptor(&tls_configuration)?),
             None => None,
         };
 
@@ -46,13 +37,15 @@ impl Server {
             let delegate = on_create_session_delegate_callback();
             let shutdown = self.notify_shutdown.subscribe();
             let shutdown_complete = self.shutdown_complete_tx.clone();
+            let tls_handshake_timeout = self.tls_handshake_timeout;
+            let queue_config = self.queue_config;
 
             // 新连接单独起一个异步任务处理
             tokio::spawn(async move {
                 trace!("KCP Server new connection: {}", addr);
 
                 if let Some(tls_acceptor) = tls_acceptor {
-                    match tls::try_tls(stream, tls_acceptor).await {
+                    match tls::try_tls(stream, tls_acceptor, tls_handshake_timeout).await {
                         Ok(stream) => {
                             net_session::run(
                                 net_session::create_session_id(),
@@ -60,6 +53,7 @@ impl Server {
                                 delegate,
                                 shutdown,
                                 stream,
+                                queue_config,
                             )
                             .await;
                         }
@@ -74,6 +68,7 @@ impl Server {
                         delegate,
                         shutdown,
                         stream,
+                        queue_config,
                     )
                     .await;
                 }
@@ -90,6 +85,9 @@ pub struct Builder {
     create_session_delegate_callback: CreateSessionDelegateCallback,
     kcp_config: KcpConfig,
     tls_configuration: Option<tls::TlsConfiguration>,
+    tls_handshake_timeout: Duration,
+    shutdown_timeout: Duration,
+    queue_config: QueueConfig,
 }
 
 impl Builder {
@@ -98,6 +96,9 @@ impl Builder {
             create_session_delegate_callback,
             kcp_config: KcpConfig::default(),
             tls_configuration: None,
+            tls_handshake_timeout: super::DEFAULT_TLS_HANDSHAKE_TIMEOUT,
+            shutdown_timeout: super::DEFAULT_SHUTDOWN_TIMEOUT,
+            queue_config: QueueConfig::default(),
         }
     }
 
@@ -114,6 +115,23 @@ impl Builder {
         self
     }
 
+    pub fn set_tls_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.tls_handshake_timeout = timeout;
+        self
+    }
+
+    /// 优雅退出等待时间, 超时后强制退出
+    pub fn set_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// 每个会话发送队列的容量和超限处理
+    pub fn set_queue_config(mut self, queue_config: QueueConfig) -> Self {
+        self.queue_config = queue_config;
+        self
+    }
+
     pub async fn build_with_listener(
         self,
         listener: KcpListener,
@@ -125,6 +143,8 @@ impl Builder {
         let server = Server {
             notify_shutdown,
             shutdown_complete_tx,
+            tls_handshake_timeout: self.tls_handshake_timeout,
+            queue_config: self.queue_config,
         };
 
         select! {
@@ -142,6 +162,7 @@ impl Builder {
         let Server {
             notify_shutdown,
             shutdown_complete_tx,
+            ..
         } = server;
 
         // 销毁notify_shutdown 是为了触发 net_session run函数中shutdown.recv()返回
@@ -155,7 +176,7 @@ impl Builder {
         };
 
         // 设置超时时间，无法优雅退出则强制退出
-        if let Err(_) = tokio::time::timeout(Duration::from_secs(600), wait_task).await {
+        if let Err(_) = tokio::time::timeout(self.shutdown_timeout, wait_task).await {
             error!("KCP Server exit timeout, forced exit");
         }
 
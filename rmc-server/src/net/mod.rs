@@ -1,15 +1,48 @@
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
 pub mod kcp_server;
 pub mod net_session;
 pub mod session_delegate;
+pub mod stream_server;
 pub mod tcp_server;
 pub mod tls;
 pub mod udp_server;
 pub mod udp_session;
+pub mod writer_queue;
+pub mod ws_server;
+pub mod ws_session;
+
+/// 默认TLS握手超时
+pub const DEFAULT_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+/// 默认UDP会话空闲超时
+pub const DEFAULT_UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// 默认优雅退出等待时间, 超时后强制退出
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// 所有会话累计收到的字节数
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+/// 所有会话累计发送的字节数
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+
+pub fn bytes_received() -> u64 {
+    BYTES_RECEIVED.load(Ordering::Relaxed)
+}
+
+pub fn bytes_sent() -> u64 {
+    BYTES_SENT.load(Ordering::Relaxed)
+}
+
+pub(crate) fn add_bytes_received(len: usize) {
+    BYTES_RECEIVED.fetch_add(len as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn add_bytes_sent(len: usize) {
+    BYTES_SENT.fetch_add(len as u64, Ordering::Relaxed);
+}
 
 pub type SendMessageFuncType =
     Box<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
@@ -1,5 +1,7 @@
+use crate::logging;
 use crate::net::session_delegate::SessionDelegate;
-use crate::net::WriterMessage;
+use crate::net::writer_queue::{self, Framing, QueueConfig, WriterReceiver};
+use crate::net::{self, WriterMessage};
 use anyhow::anyhow;
 use bytes::BytesMut;
 use log::{error, info};
@@ -10,9 +12,9 @@ use tokio::io::{
 };
 use tokio::select;
 use tokio::sync::broadcast;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use tokio::task::yield_now;
 use tokio::time::sleep;
+use tracing::Instrument;
 
 static SESSION_COUNTER: AtomicU32 = AtomicU32::new(0);
 pub fn create_session_id() -> u32 {
@@ -26,6 +28,8 @@ pub fn create_session_id() -> u32 {
 
 /// run
 ///
+/// 会话内输出的日志带有会话id和地址, 见 [`logging::session_span`]
+///
 /// [`session_id`] 会话id
 ///
 /// [`addr`] 地址
@@ -35,45 +39,57 @@ pub fn create_session_id() -> u32 {
 /// [`shutdown`] 监听退出消息
 ///
 /// [`stream`]
+///
+/// [`queue_config`] 发送队列的容量和超限处理
 pub async fn run<S>(
     session_id: u32,
     addr: SocketAddr,
     mut delegate: Box<dyn SessionDelegate>,
     mut shutdown: broadcast::Receiver<()>,
     stream: S,
+    queue_config: QueueConfig,
 ) where
     S: AsyncRead + AsyncWrite + Send + 'static,
 {
-    let (reader, writer) = tokio::io::split(stream);
-    let (delegate_sender, delegate_receiver) = unbounded_channel::<WriterMessage>();
+    async move {
+        let (reader, writer) = tokio::io::split(stream);
+        let (delegate_sender, delegate_receiver) =
+            writer_queue::channel(queue_config, Framing::LengthPrefixed);
+        let queue = delegate_sender.clone();
 
-    if let Err(err) = delegate
-        .on_session_start(session_id, &addr, delegate_sender)
-        .await
-    {
-        error!("[{addr}] on_session_start error:{err}");
-        return;
-    }
+        if let Err(err) = delegate
+            .on_session_start(session_id, &addr, delegate_sender)
+            .await
+        {
+            error!("[{addr}] on_session_start error:{err}");
+            return;
+        }
 
-    select! {
-        err = poll_read(addr, &mut delegate, reader) => {
-            if let Err(err) = err {
-                info!("poll read error: {}", err.to_string());
+        select! {
+            err = poll_read(addr, &mut delegate, reader) => {
+                if let Err(err) = err {
+                    info!("poll read error: {}", err.to_string());
+                }
+            }
+            _ = poll_write(addr, delegate_receiver, writer) => {}
+            reason = queue.aborted() => {
+                info!("[{addr}] {reason}, closing session");
             }
+            _ = shutdown.recv() => {}
         }
-        _ = poll_write(addr, delegate_receiver, writer) => {}
-        _ = shutdown.recv() => {}
-    }
 
-    if let Err(err) = delegate.on_session_close().await {
-        error!("[{addr}] on_session_close error:{err}");
+        if let Err(err) = delegate.on_session_close().await {
+            error!("[{addr}] on_session_close error:{err}");
+        }
     }
+    .instrument(logging::session_span(session_id, addr))
+    .await
 }
 
 /// 循环写入数据
 async fn poll_write<S>(
     addr: SocketAddr,
-    mut delegate_receiver: UnboundedReceiver<WriterMessage>,
+    mut delegate_receiver: WriterReceiver,
     writer: WriteHalf<S>,
 ) where
     S: AsyncRead + AsyncWrite + Send + 'static,
@@ -97,6 +113,7 @@ async fn poll_write<S>(
                     error!("[{addr}] error when write_all {:?}", error);
                     break;
                 }
+                net::add_bytes_sent(data.len());
 
                 if flush {
                     if let Err(error) = writer.flush().await {
@@ -117,6 +134,7 @@ async fn poll_write<S>(
                     error!("[{addr}] error when write_all {:?}", error);
                     break;
                 }
+                net::add_bytes_sent(data.len());
                 if let Err(error) = writer.flush().await {
                     error!("[{addr}] error when flushing {:?}", error);
                 }
@@ -145,10 +163,12 @@ where
     let mut buffer = BytesMut::with_capacity(4096);
 
     loop {
-        if reader.read_buf(&mut buffer).await? == 0 {
+        let len = reader.read_buf(&mut buffer).await?;
+        if len == 0 {
             // 客户端主动断开
             return Err(anyhow!("[{addr}] socket closed."));
         }
+        net::add_bytes_received(len);
 
         // 循环解包
         loop {
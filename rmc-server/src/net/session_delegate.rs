@@ -1,8 +1,7 @@
-use crate::net::WriterMessage;
+use crate::net::writer_queue::WriterSender;
 use async_trait::async_trait;
 use bytes::BytesMut;
 use std::net::SocketAddr;
-use tokio::sync::mpsc::UnboundedSender;
 
 #[async_trait]
 pub trait SessionDelegate
@@ -15,12 +14,12 @@ where
     ///
     /// [`addr`] 对方地址
     ///
-    /// [`tx`] 主动发送消息通道发送端
+    /// [`tx`] 主动发送消息的有界队列发送端
     async fn on_session_start(
         &mut self,
         session_id: u32,
         addr: &SocketAddr,
-        tx: UnboundedSender<WriterMessage>,
+        tx: WriterSender,
     ) -> anyhow::Result<()>;
 
     /// 会话关闭
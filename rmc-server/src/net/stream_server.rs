@@ -0,0 +1,151 @@
+use crate::net::tls;
+use log::{debug, error, info, trace};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::sync::{broadcast, mpsc};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::either::Either;
+
+/// 明文或TLS连接
+pub type MaybeTlsStream = Either<TcpStream, tokio_rustls::TlsStream<TcpStream>>;
+
+pub type StreamInitCallbackType = Arc<
+    dyn Fn(TcpStream) -> Pin<Box<dyn Future<Output = anyhow::Result<TcpStream>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// 处理一个已完成TLS握手的连接, 在单独的任务中运行直到会话结束
+///
+/// 参数依次为连接、对方地址和退出通知
+pub type ConnectionHandler = Arc<
+    dyn Fn(
+            MaybeTlsStream,
+            SocketAddr,
+            broadcast::Receiver<()>,
+        ) -> Pin<Box<dyn Future<Output = ()> + Send>>
+        + Send
+        + Sync,
+>;
+
+/// 基于TCP的监听(tcp、ws)共用的接收连接、TLS握手和优雅退出流程
+pub struct StreamServer {
+    /// 用于日志, 如 "TCP Server"
+    pub name: &'static str,
+    pub tls_configuration: Option<tls::TlsConfiguration>,
+    pub tls_handshake_timeout: Duration,
+    pub shutdown_timeout: Duration,
+    pub stream_init_callback: Option<StreamInitCallbackType>,
+}
+
+impl StreamServer {
+    pub async fn run(
+        self,
+        listener: TcpListener,
+        shutdown_condition: impl Future,
+        handler: ConnectionHandler,
+    ) -> anyhow::Result<()> {
+        let name = self.name;
+        let (notify_shutdown, _) = broadcast::channel::<()>(1);
+        let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
+
+        select! {
+            res = self.accept_loop(listener, &notify_shutdown, &shutdown_complete_tx, handler) => {
+                if let Err(err) = res {
+                    error!("{name} error: {}", err);
+                }
+            },
+            _ = shutdown_condition => {
+                info!("{name} shutting down");
+            }
+        }
+
+        // 销毁notify_shutdown 是为了触发会话 run 函数中shutdown.recv()返回
+        drop(notify_shutdown);
+        // 此处必须将 shutdown_complete_tx 并销毁，否则会一直卡在shutdown_complete_rx.recv().await
+        drop(shutdown_complete_tx);
+
+        // 等待服务器优雅退出任务
+        let wait_task = async {
+            let _ = shutdown_complete_rx.recv().await;
+        };
+
+        // 设置超时时间，无法优雅退出则强制退出
+        if tokio::time::timeout(self.shutdown_timeout, wait_task)
+            .await
+            .is_err()
+        {
+            error!("{name} exit timeout, forced exit");
+        }
+
+        info!("{name} shutdown finish");
+
+        Ok(())
+    }
+
+    async fn accept_loop(
+        &self,
+        listener: TcpListener,
+        notify_shutdown: &broadcast::Sender<()>,
+        shutdown_complete_tx: &mpsc::Sender<()>,
+        handler: ConnectionHandler,
+    ) -> anyhow::Result<()> {
+        let name = self.name;
+        let tls_acceptor: Option<TlsAcceptor> = match &self.tls_configuration {
+            Some(tls_configuration) => Some(tls::new_acceptor(tls_configuration)?),
+            None => None,
+        };
+
+        loop {
+            let (mut stream, addr) = listener.accept().await?;
+
+            if let Some(ref stream_init_callback) = self.stream_init_callback {
+                match stream_init_callback(stream).await {
+                    Ok(s) => {
+                        stream = s;
+                    }
+                    Err(error) => {
+                        error!("{name} on_stream_init error:{error}");
+                        continue;
+                    }
+                }
+            }
+
+            let tls_acceptor = tls_acceptor.clone();
+            let handler = handler.clone();
+            let shutdown = notify_shutdown.subscribe();
+            let shutdown_complete = shutdown_complete_tx.clone();
+            let tls_handshake_timeout = self.tls_handshake_timeout;
+
+            // 新连接单独起一个异步任务处理
+            tokio::spawn(async move {
+                trace!("{name} new connection: {}", addr);
+
+                let stream = match tls_acceptor {
+                    Some(tls_acceptor) => {
+                        match tls::try_tls(stream, tls_acceptor, tls_handshake_timeout).await {
+                            Ok(stream) => Some(Either::Right(stream)),
+                            Err(err) => {
+                                debug!("{name} tls error: {err}");
+                                None
+                            }
+                        }
+                    }
+                    None => Some(Either::Left(stream)),
+                };
+                if let Some(stream) = stream {
+                    handler(stream, addr, shutdown).await;
+                }
+
+                trace!("{name} disconnect: {}", addr);
+                // 反向通知此会话结束
+                drop(shutdown_complete);
+            });
+        }
+    }
+}
@@ -1,114 +1,21 @@
 use crate::net::session_delegate::CreateSessionDelegateCallback;
+use crate::net::stream_server::StreamServer;
+use crate::net::writer_queue::QueueConfig;
 use crate::net::{net_session, tls};
-use log::{debug, error};
-use log::{info, trace};
 use std::future::Future;
-use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio::select;
-use tokio::sync::{broadcast, mpsc};
-use tokio_rustls::rustls::ServerConfig;
-use tokio_rustls::TlsAcceptor;
+use tokio::net::{TcpListener, ToSocketAddrs};
 
-pub type StreamInitCallbackType = Arc<
-    dyn Fn(TcpStream) -> Pin<Box<dyn Future<Output = anyhow::Result<TcpStream>> + Send>>
-        + Send
-        + Sync,
->;
-
-struct Server {
-    notify_shutdown: broadcast::Sender<()>,
-    shutdown_complete_tx: mpsc::Sender<()>,
-}
-
-impl Server {
-    async fn start_server(
-        &self,
-        listener: TcpListener,
-        on_create_session_delegate_callback: CreateSessionDelegateCallback,
-        on_stream_init_callback: Option<StreamInitCallbackType>,
-        tls_configuration: Option<tls::TlsConfiguration>,
-    ) -> anyhow::Result<()> {
-        let tls_acceptor: Option<TlsAcceptor> = match tls_configuration {
-            Some(tls_configuration) => {
-                let certs = super::tls::load_certs(&tls_configuration.certificate)?;
-                let keys = super::tls::load_private_key(&tls_configuration.key)?;
-
-                let server_config = ServerConfig::builder()
-                    .with_safe_defaults()
-                    .with_no_client_auth()
-                    .with_single_cert(certs, keys)?;
-
-                Some(TlsAcceptor::from(Arc::new(server_config)))
-            }
-            None => None,
-        };
-
-        loop {
-            let (mut stream, addr) = listener.accept().await?;
-
-            if let Some(ref on_stream_init_callback) = on_stream_init_callback {
-                match on_stream_init_callback(stream).await {
-                    Ok(s) => {
-                        stream = s;
-                    }
-                    Err(error) => {
-                        error!("TCP Server on_stream_init error:{}", error.to_string());
-                        continue;
-                    }
-                }
-            }
-
-            let tls_acceptor = tls_acceptor.clone();
-            let delegate = on_create_session_delegate_callback();
-            let shutdown = self.notify_shutdown.subscribe();
-            let shutdown_complete = self.shutdown_complete_tx.clone();
-
-            // 新连接单独起一个异步任务处理
-            tokio::spawn(async move {
-                trace!("TCP Server new connection: {}", addr);
-
-                if let Some(tls_acceptor) = tls_acceptor {
-                    match tls::try_tls(stream, tls_acceptor).await {
-                        Ok(stream) => {
-                            net_session::run(
-                                net_session::create_session_id(),
-                                addr,
-                                delegate,
-                                shutdown,
-                                stream,
-                            )
-                            .await;
-                        }
-                        Err(err) => {
-                            debug!("TCP Server tls error: {err}");
-                        }
-                    }
-                } else {
-                    net_session::run(
-                        net_session::create_session_id(),
-                        addr,
-                        delegate,
-                        shutdown,
-                        stream,
-                    )
-                    .await;
-                }
-
-                trace!("TCP Server disconnect: {}", addr);
-                // 反向通知此会话结束
-                drop(shutdown_complete);
-            });
-        }
-    }
-}
+pub use crate::net::stream_server::StreamInitCallbackType;
 
 pub struct Builder {
     create_session_delegate_callback: CreateSessionDelegateCallback,
     tls_configuration: Option<tls::TlsConfiguration>,
     steam_init_callback: Option<StreamInitCallbackType>,
+    tls_handshake_timeout: Duration,
+    shutdown_timeout: Duration,
+    queue_config: QueueConfig,
 }
 
 impl Builder {
@@ -117,6 +24,9 @@ impl Builder {
             create_session_delegate_callback,
             tls_configuration: None,
             steam_init_callback: None,
+            tls_handshake_timeout: super::DEFAULT_TLS_HANDSHAKE_TIMEOUT,
+            shutdown_timeout: super::DEFAULT_SHUTDOWN_TIMEOUT,
+            queue_config: QueueConfig::default(),
         }
     }
 
@@ -136,54 +46,54 @@ impl Builder {
         self
     }
 
+    pub fn set_tls_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.tls_handshake_timeout = timeout;
+        self
+    }
+
+    /// 优雅退出等待时间, 超时后强制退出
+    pub fn set_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// 每个会话发送队列的容量和超限处理
+    pub fn set_queue_config(mut self, queue_config: QueueConfig) -> Self {
+        self.queue_config = queue_config;
+        self
+    }
+
     pub async fn build_with_listener(
         self,
         listener: TcpListener,
         shutdown_condition: impl Future,
     ) -> anyhow::Result<()> {
-        let (notify_shutdown, _) = broadcast::channel::<()>(1);
-        let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
-
-        let server = Server {
-            notify_shutdown,
-            shutdown_complete_tx,
-        };
-
-        select! {
-            res = server.start_server(listener, self.create_session_delegate_callback, self.steam_init_callback, self.tls_configuration) => {
-                if let Err(err) = res {
-                    error!("TCP Server error: {}", err);
-                }
-            },
-            _ = shutdown_condition => {
-                info!("TCP Server shutting down");
-            }
-        }
-
-        // 解构server中的变量
-        let Server {
-            notify_shutdown,
-            shutdown_complete_tx,
-        } = server;
-
-        // 销毁notify_shutdown 是为了触发 net_session run函数中shutdown.recv()返回
-        drop(notify_shutdown);
-        // 此处必须将 shutdown_complete_tx 并销毁，否则会一直卡在shutdown_complete_rx.recv().await
-        drop(shutdown_complete_tx);
-
-        // 等待服务器优雅退出任务
-        let wait_task = async {
-            let _ = shutdown_complete_rx.recv().await;
+        let server = StreamServer {
+            name: "TCP Server",
+            tls_configuration: self.tls_configuration,
+            tls_handshake_timeout: self.tls_handshake_timeout,
+            shutdown_timeout: self.shutdown_timeout,
+            stream_init_callback: self.steam_init_callback,
         };
 
-        // 设置超时时间，无法优雅退出则强制退出
-        if let Err(_) = tokio::time::timeout(Duration::from_secs(600), wait_task).await {
-            error!("TCP Server exit timeout, forced exit");
-        }
-
-        info!("TCP Server shutdown finish");
-
-        Ok(())
+        let create_session_delegate_callback = self.create_session_delegate_callback;
+        let queue_config = self.queue_config;
+        server
+            .run(
+                listener,
+                shutdown_condition,
+                Arc::new(move |stream, addr, shutdown| {
+                    Box::pin(net_session::run(
+                        net_session::create_session_id(),
+                        addr,
+                        create_session_delegate_callback(),
+                        shutdown,
+                        stream,
+                        queue_config,
+                    ))
+                }),
+            )
+            .await
     }
 
     pub async fn build<A: ToSocketAddrs>(
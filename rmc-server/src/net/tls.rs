@@ -1,9 +1,10 @@
 use anyhow::anyhow;
 use std::fs::File;
 use std::io::BufReader;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_rustls::rustls::{Certificate, PrivateKey};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
 use tokio_rustls::TlsAcceptor;
 
 pub struct TlsConfiguration {
@@ -11,6 +12,19 @@ pub struct TlsConfiguration {
     pub key: String,
 }
 
+/// 加载证书和私钥, 创建TLS接收器
+pub fn new_acceptor(tls_configuration: &TlsConfiguration) -> anyhow::Result<TlsAcceptor> {
+    let certs = load_certs(&tls_configuration.certificate)?;
+    let keys = load_private_key(&tls_configuration.key)?;
+
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, keys)?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
 pub fn load_certs(path: &str) -> anyhow::Result<Vec<Certificate>> {
     let cert_file = File::open(path)?;
     let mut reader = BufReader::new(cert_file);
@@ -34,20 +48,15 @@ pub fn load_private_key(path: &str) -> anyhow::Result<PrivateKey> {
     };
 }
 
-const TIMEOUT_TLS: u64 = 15;
-
 // ref https://github.com/netskillzgh/rollo/blob/master/rollo/src/server/world_socket_mgr.rs#L183
 pub async fn try_tls<IO>(
     stream: IO,
     tls_acceptor: TlsAcceptor,
+    handshake_timeout: Duration,
 ) -> anyhow::Result<tokio_rustls::TlsStream<IO>>
 where
     IO: AsyncRead + AsyncWrite + Unpin,
 {
-    let stream = tokio::time::timeout(
-        Duration::from_secs(TIMEOUT_TLS),
-        tls_acceptor.accept(stream),
-    )
-    .await??;
+    let stream = tokio::time::timeout(handshake_timeout, tls_acceptor.accept(stream)).await??;
     Ok(tokio_rustls::TlsStream::Server(stream))
 }
@@ -1,5 +1,6 @@
 use crate::net::session_delegate::CreateSessionDelegateCallback;
-use crate::net::udp_session;
+use crate::net::writer_queue::QueueConfig;
+use crate::net::{net_session, udp_session};
 use log::{error, info, trace};
 use std::collections::HashMap;
 use std::future::Future;
@@ -11,17 +12,24 @@ use tokio::select;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::{broadcast, mpsc, Mutex};
 
+/// [`idle_timeout`] 会话空闲超时
+///
+/// [`shutdown_timeout`] 优雅退出等待时间，超时后强制退出
+///
+/// [`queue_config`] 每个会话发送队列的容量和超限处理
 pub async fn run_server(
     socket: UdpSocket,
     on_create_session_delegate_callback: CreateSessionDelegateCallback,
     shutdown: impl Future,
+    idle_timeout: Duration,
+    shutdown_timeout: Duration,
+    queue_config: QueueConfig,
 ) {
     let (notify_shutdown, receiver_shutdown) = broadcast::channel::<()>(1);
     let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
 
     // 循环读取中...
     let recv_task = async {
-        let mut session_id_seed = 0;
         let hashmap: Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Vec<u8>>>>> =
             Arc::new(Mutex::new(HashMap::new()));
         let mut buf = [0; 65535]; // 最大允许的UDP数据包大小
@@ -38,9 +46,8 @@ pub async fn run_server(
 
             let contains_addr = hashmap.lock().await.contains_key(&addr);
             if !contains_addr {
-                // 新的会话id
-                session_id_seed += 1;
-                let session_id = session_id_seed;
+                // 新的会话id, 与其他传输共用计数器, 避免全局上下文中会话id冲突
+                let session_id = net_session::create_session_id();
 
                 let delegate = on_create_session_delegate_callback();
 
@@ -69,6 +76,8 @@ pub async fn run_server(
                         Some(udp_recv_receiver),
                         shutdown,
                         socket_cloned,
+                        idle_timeout,
+                        queue_config,
                     )
                     .await;
                     hashmap_cloned.lock().await.remove(&addr);
@@ -102,9 +111,78 @@ pub async fn run_server(
     };
 
     // 设置超时时间，无法优雅退出则强制退出
-    if let Err(_) = tokio::time::timeout(Duration::from_secs(600), wait_task).await {
+    if let Err(_) = tokio::time::timeout(shutdown_timeout, wait_task).await {
         error!("UDP Server exit timeout, forced exit");
     }
 
     info!("UDP Server shutdown finish");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::session_delegate::SessionDelegate;
+    use crate::peer::Peer;
+    use rmc_proto::{
+        AuthChallengeNtf, Encoding, HelloResponse, Message, Packet, Ping, Pong, Protocol,
+        FEATURE_ARTWORK,
+    };
+
+    async fn next_message(socket: &UdpSocket, name: &str) -> String {
+        let mut buf = [0; 65535];
+        let amt = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let message: Message = serde_json::from_slice(&buf[..amt]).unwrap();
+        assert_eq!(message.name, name);
+        message.data
+    }
+
+    #[tokio::test]
+    async fn datagrams_carry_unprefixed_messages() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(run_server(
+            socket,
+            Box::new(|| -> Box<dyn SessionDelegate> { Box::new(Peer::new("udp")) }),
+            std::future::pending::<()>(),
+            Duration::from_secs(10),
+            Duration::from_secs(1),
+            QueueConfig::default(),
+        ));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(addr).await.unwrap();
+
+        // 第一个数据报创建会话, 每个数据报是一个完整消息
+        let ping = Message {
+            name: "Ping".to_string(),
+            data: serde_json::to_string(&Ping { time: 42 }).unwrap(),
+        };
+        client
+            .send(&serde_json::to_vec(&ping).unwrap())
+            .await
+            .unwrap();
+
+        // 下发的挑战和响应同样不带长度前缀
+        let data = next_message(&client, "AuthChallengeNtf").await;
+        let challenge: AuthChallengeNtf = serde_json::from_str(&data).unwrap();
+        assert!(!challenge.nonce.is_empty());
+
+        let data = next_message(&client, "Pong").await;
+        let pong: Pong = serde_json::from_str(&data).unwrap();
+        assert_eq!(pong.time, 42);
+
+        // 封面分片可能超过数据报上限, 协商时去掉
+        let hello = Packet::from(Protocol::hello())
+            .encode(Encoding::Json)
+            .unwrap();
+        client.send(&hello).await.unwrap();
+        let data = next_message(&client, "HelloResponse").await;
+        let response: HelloResponse = serde_json::from_str(&data).unwrap();
+        assert!(response.ok, "{}", response.error);
+        assert!(!response.features.is_empty());
+        assert!(!response.features.iter().any(|x| x == FEATURE_ARTWORK));
+    }
+}
@@ -1,15 +1,18 @@
+use crate::logging;
 use crate::net::session_delegate::SessionDelegate;
-use crate::net::WriterMessage;
-use log::error;
+use crate::net::writer_queue::{self, Framing, QueueConfig, WriterReceiver};
+use crate::net::{self, WriterMessage};
+use log::{error, info};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tokio::select;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::{broadcast, RwLock};
 use tokio::task::yield_now;
 use tokio::time::sleep;
 use tokio::time::{Duration, Instant};
+use tracing::Instrument;
 
 async fn poll_read_from_unbounded_receiver(
     addr: SocketAddr,
@@ -23,6 +26,7 @@ async fn poll_read_from_unbounded_receiver(
             let mut instant_write = last_active_time.write().await;
             *instant_write = Instant::now();
         }
+        net::add_bytes_received(data.len());
         if let Err(err) = delegate.on_recv_frame(data).await {
             error!("[{addr}] on_recv_frame error: {err}");
             break;
@@ -52,6 +56,7 @@ async fn poll_read(
 
         let (amt, peer_addr) = result.unwrap();
 
+        net::add_bytes_received(amt);
         let received_data = Vec::from(&buf[..amt]);
         if let Err(err) = delegate.on_recv_frame_from(received_data, peer_addr).await {
             error!("[{addr}] on_recv_frame error: {err}");
@@ -62,7 +67,7 @@ async fn poll_read(
 
 async fn poll_write(
     addr: SocketAddr,
-    mut delegate_receiver: UnboundedReceiver<WriterMessage>,
+    mut delegate_receiver: WriterReceiver,
     socket: Arc<UdpSocket>,
     last_active_time: Arc<RwLock<Instant>>,
 ) {
@@ -89,6 +94,7 @@ async fn poll_write(
                     error!("[{addr}] Error when udp socket send_to {:?}", error);
                     break;
                 }
+                net::add_bytes_sent(data.len());
             }
             WriterMessage::SendTo(data, target_addr) => {
                 if data.is_empty() {
@@ -100,6 +106,7 @@ async fn poll_write(
                     error!("[{addr}] Error when udp socket send_to {:?}", error);
                     break;
                 }
+                net::add_bytes_sent(data.len());
             }
             WriterMessage::SendAndThen(data, callback) => {
                 if data.is_empty() {
@@ -112,6 +119,7 @@ async fn poll_write(
                     error!("[{addr}] Error when udp socket send_to {:?}", error);
                     break;
                 }
+                net::add_bytes_sent(data.len());
                 callback().await;
             }
             WriterMessage::Flush => {}
@@ -121,8 +129,7 @@ async fn poll_write(
     delegate_receiver.close();
 }
 
-async fn poll_timeout(last_active_time: Arc<RwLock<Instant>>) {
-    let timeout = Duration::from_secs(10);
+async fn poll_timeout(last_active_time: Arc<RwLock<Instant>>, timeout: Duration) {
     loop {
         sleep(Duration::from_secs(1)).await;
         if last_active_time.read().await.elapsed() > timeout {
@@ -133,6 +140,8 @@ async fn poll_timeout(last_active_time: Arc<RwLock<Instant>>) {
 
 /// run
 ///
+/// 会话内输出的日志带有会话id和地址, 见 [`logging::session_span`]
+///
 /// [`session_id`] 会话id
 ///
 /// [`addr`] UDP发送端地址
@@ -144,6 +153,11 @@ async fn poll_timeout(last_active_time: Arc<RwLock<Instant>>) {
 /// [`shutdown`] 监听退出消息
 ///
 /// [`socket`] UdpSocket对象，用于写入udp数据
+///
+/// [`idle_timeout`] 空闲超时，超时未收发数据则关闭会话
+///
+/// [`queue_config`] 发送队列的容量和超限处理
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     session_id: u32,
     addr: SocketAddr,
@@ -151,36 +165,49 @@ pub async fn run(
     udp_recv_receiver: Option<UnboundedReceiver<Vec<u8>>>,
     mut shutdown: broadcast::Receiver<()>,
     socket: Arc<UdpSocket>,
+    idle_timeout: Duration,
+    queue_config: QueueConfig,
 ) {
-    let (delegate_sender, delegate_receiver) = unbounded_channel::<WriterMessage>();
-
-    if let Err(err) = delegate
-        .on_session_start(session_id, &addr, delegate_sender)
-        .await
-    {
-        error!("on_session_start error:{err}");
-        return;
-    }
+    async move {
+        let (delegate_sender, delegate_receiver) = writer_queue::channel(queue_config, Framing::Message);
+        let queue = delegate_sender.clone();
+
+        if let Err(err) = delegate
+            .on_session_start(session_id, &addr, delegate_sender)
+            .await
+        {
+            error!("on_session_start error:{err}");
+            return;
+        }
 
-    let last_active_time = Arc::new(RwLock::new(Instant::now()));
+        let last_active_time = Arc::new(RwLock::new(Instant::now()));
 
-    if let Some(udp_recv_receiver) = udp_recv_receiver {
-        select! {
-            _= poll_read_from_unbounded_receiver(addr, &mut delegate, udp_recv_receiver, last_active_time.clone()) => {},
-            _= poll_write(addr, delegate_receiver, socket, last_active_time.clone()) => {},
-            _= poll_timeout(last_active_time) => {},
-            _ = shutdown.recv() => {}
-        }
-    } else {
-        select! {
-            _= poll_read(addr, &mut delegate, socket.clone(), last_active_time.clone()) => {},
-            _= poll_write(addr, delegate_receiver, socket, last_active_time.clone()) => {},
-            _= poll_timeout(last_active_time) => {},
-            _ = shutdown.recv() => {}
+        if let Some(udp_recv_receiver) = udp_recv_receiver {
+            select! {
+                _= poll_read_from_unbounded_receiver(addr, &mut delegate, udp_recv_receiver, last_active_time.clone()) => {},
+                _= poll_write(addr, delegate_receiver, socket, last_active_time.clone()) => {},
+                _= poll_timeout(last_active_time, idle_timeout) => {},
+                reason = queue.aborted() => {
+                    info!("[{addr}] {reason}, closing session");
+                }
+                _ = shutdown.recv() => {}
+            }
+        } else {
+            select! {
+                _= poll_read(addr, &mut delegate, socket.clone(), last_active_time.clone()) => {},
+                _= poll_write(addr, delegate_receiver, socket, last_active_time.clone()) => {},
+                _= poll_timeout(last_active_time, idle_timeout) => {},
+                reason = queue.aborted() => {
+                    info!("[{addr}] {reason}, closing session");
+                }
+                _ = shutdown.recv() => {}
+            }
         }
-    }
 
-    if let Err(err) = delegate.on_session_close().await {
-        error!("[{addr}] on_session_close error:{err}");
+        if let Err(err) = delegate.on_session_close().await {
+            error!("[{addr}] on_session_close error:{err}");
+        }
     }
+    .instrument(logging::session_span(session_id, addr))
+    .await
 }
@@ -0,0 +1,398 @@
+use crate::net::WriterMessage;
+use anyhow::anyhow;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+
+/// 默认每个会话发送队列的容量(消息数)
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+/// 默认发送队列持续超出容量多久后断开会话
+pub const DEFAULT_QUEUE_OVERFLOW_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 所有会话累计丢弃的消息数
+static DROPPED_MESSAGES: AtomicU64 = AtomicU64::new(0);
+/// 因发送队列持续超限被断开的会话数
+static OVERFLOW_DISCONNECTS: AtomicU64 = AtomicU64::new(0);
+
+pub fn dropped_messages() -> u64 {
+    DROPPED_MESSAGES.load(Ordering::Relaxed)
+}
+
+pub fn overflow_disconnects() -> u64 {
+    OVERFLOW_DISCONNECTS.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueueConfig {
+    /// 队列容量, 超出后开始丢弃可丢弃的消息
+    pub capacity: usize,
+    /// 持续超出容量的最长时间, 超时后断开会话
+    pub overflow_timeout: Duration,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow_timeout: DEFAULT_QUEUE_OVERFLOW_TIMEOUT,
+        }
+    }
+}
+
+/// 会话传输的分帧方式, 决定发送的数据是否需要长度前缀
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Framing {
+    /// 字节流(tcp、kcp), 每个消息前加4字节大端长度前缀
+    LengthPrefixed,
+    /// 传输自带分帧(ws消息、udp数据报), 每个 [`WriterMessage::Send`] 就是一个完整消息
+    Message,
+}
+
+/// 队列已满时对新消息的处理方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QueuePolicy {
+    /// 从不丢弃, 如按键事件、请求的响应
+    Reliable,
+    /// 丢弃队列中最早的可丢弃消息, 如状态通知, 之后的通知会覆盖旧状态
+    DropOldest,
+}
+
+struct State {
+    messages: VecDeque<(WriterMessage, QueuePolicy)>,
+    /// 开始超出容量的时间
+    overflow_since: Option<Instant>,
+    closed: bool,
+    /// 被强制关闭的原因, 如持续超限、空闲超时
+    aborted: Option<String>,
+}
+
+struct Shared {
+    config: QueueConfig,
+    framing: Framing,
+    state: Mutex<State>,
+    /// 有新消息或队列已关闭
+    readable: Notify,
+    /// 开始超限或队列被强制关闭
+    aborted: Notify,
+}
+
+/// 会话发送队列的发送端, 替代无界通道
+#[derive(Clone)]
+pub struct WriterSender {
+    shared: Arc<Shared>,
+}
+
+/// 会话发送队列的接收端, 由写循环持有
+pub struct WriterReceiver {
+    shared: Arc<Shared>,
+}
+
+/// 创建有界发送队列
+pub fn channel(config: QueueConfig, framing: Framing) -> (WriterSender, WriterReceiver) {
+    let shared = Arc::new(Shared {
+        config,
+        framing,
+        state: Mutex::new(State {
+            messages: VecDeque::new(),
+            overflow_since: None,
+            closed: false,
+            aborted: None,
+        }),
+        readable: Notify::new(),
+        aborted: Notify::new(),
+    });
+    (
+        WriterSender {
+            shared: shared.clone(),
+        },
+        WriterReceiver { shared },
+    )
+}
+
+impl WriterSender {
+    /// 发送不可丢弃的消息
+    pub fn send(&self, message: WriterMessage) -> anyhow::Result<()> {
+        self.send_with_policy(message, QueuePolicy::Reliable)
+    }
+
+    pub fn send_with_policy(
+        &self,
+        message: WriterMessage,
+        policy: QueuePolicy,
+    ) -> anyhow::Result<()> {
+        let QueueConfig {
+            capacity,
+            overflow_timeout,
+        } = self.shared.config;
+
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(anyhow!("writer queue closed"));
+        }
+
+        if policy == QueuePolicy::DropOldest && state.messages.len() >= capacity {
+            let oldest = state
+                .messages
+                .iter()
+                .position(|(_, policy)| *policy == QueuePolicy::DropOldest);
+            // 没有更早的可丢弃消息时丢弃新消息
+            match oldest {
+                Some(index) => {
+                    state.messages.remove(index);
+                    state.messages.push_back((message, policy));
+                }
+                None => drop(message),
+            }
+            DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+        } else {
+            state.messages.push_back((message, policy));
+        }
+
+        if state.messages.len() > capacity && state.overflow_since.is_none() {
+            state.overflow_since = Some(Instant::now());
+            // 唤醒 aborted() 开始计时
+            self.shared.aborted.notify_one();
+        }
+        if self.shared.overflow_expired(&mut state, overflow_timeout) {
+            return Err(anyhow!("writer queue overflow"));
+        }
+        drop(state);
+
+        self.shared.readable.notify_one();
+        Ok(())
+    }
+
+    /// 写入前需要对消息做的分帧
+    pub fn framing(&self) -> Framing {
+        self.shared.framing
+    }
+
+    /// 队列中等待写入的消息数
+    pub fn depth(&self) -> usize {
+        self.shared.state.lock().unwrap().messages.len()
+    }
+
+    /// 强制关闭队列, 丢弃未写入的消息, 会话的 [`aborted`] 随即返回 [`reason`]
+    ///
+    /// 用于对方无响应等写端可能阻塞的情况, 不经过写循环直接断开会话
+    pub fn abort(&self, reason: &str) -> anyhow::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(anyhow!("writer queue closed"));
+        }
+        self.shared.abort_locked(&mut state, reason);
+        Ok(())
+    }
+
+    /// 队列被强制关闭时返回关闭原因, 用于中断阻塞中的写入
+    ///
+    /// 超限期间没有新消息入队时也会按时关闭, 需要在会话的 select! 中一直等待
+    pub async fn aborted(&self) -> String {
+        loop {
+            let notified = self.shared.aborted.notified();
+            let deadline = {
+                let mut state = self.shared.state.lock().unwrap();
+                let overflow_timeout = self.shared.config.overflow_timeout;
+                self.shared.overflow_expired(&mut state, overflow_timeout);
+                if let Some(reason) = &state.aborted {
+                    return reason.clone();
+                }
+                state.overflow_since.map(|since| since + overflow_timeout)
+            };
+            match deadline {
+                Some(deadline) => {
+                    select! {
+                        _ = notified => {}
+                        _ = sleep_until(deadline) => {}
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+}
+
+impl Shared {
+    /// 持续超限超过 [`overflow_timeout`] 时关闭队列并返回 true
+    fn overflow_expired(&self, state: &mut State, overflow_timeout: Duration) -> bool {
+        let expired = state
+            .overflow_since
+            .is_some_and(|since| since.elapsed() >= overflow_timeout);
+        if !expired || state.closed {
+            return state.aborted.is_some();
+        }
+        OVERFLOW_DISCONNECTS.fetch_add(1, Ordering::Relaxed);
+        self.abort_locked(state, "send queue overflow");
+        true
+    }
+
+    fn abort_locked(&self, state: &mut State, reason: &str) {
+        state.closed = true;
+        state.aborted = Some(reason.to_string());
+        state.messages.clear();
+        self.readable.notify_one();
+        self.aborted.notify_one();
+    }
+}
+
+impl WriterReceiver {
+    /// 取出下一条消息, 队列关闭后返回 None
+    pub async fn recv(&mut self) -> Option<WriterMessage> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some((message, _)) = state.messages.pop_front() {
+                    if state.messages.len() <= self.shared.config.capacity {
+                        state.overflow_since = None;
+                    }
+                    return Some(message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.shared.readable.notified().await;
+        }
+    }
+
+    /// 关闭队列, 之后的发送返回错误
+    pub fn close(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+    }
+}
+
+impl Drop for WriterReceiver {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(byte: u8) -> WriterMessage {
+        WriterMessage::Send(vec![byte], true)
+    }
+
+    async fn recv_byte(receiver: &mut WriterReceiver) -> u8 {
+        match receiver.recv().await {
+            Some(WriterMessage::Send(data, _)) => data[0],
+            _ => panic!("expected Send"),
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_oldest_state_message() {
+        let (sender, mut receiver) = channel(
+            QueueConfig {
+                capacity: 3,
+                overflow_timeout: Duration::from_secs(60),
+            },
+            Framing::LengthPrefixed,
+        );
+        sender
+            .send_with_policy(message(1), QueuePolicy::DropOldest)
+            .unwrap();
+        sender.send(message(2)).unwrap();
+        sender
+            .send_with_policy(message(3), QueuePolicy::DropOldest)
+            .unwrap();
+        sender
+            .send_with_policy(message(4), QueuePolicy::DropOldest)
+            .unwrap();
+
+        assert_eq!(sender.depth(), 3);
+        assert_eq!(recv_byte(&mut receiver).await, 2);
+        assert_eq!(recv_byte(&mut receiver).await, 3);
+        assert_eq!(recv_byte(&mut receiver).await, 4);
+    }
+
+    #[tokio::test]
+    async fn reliable_messages_are_never_dropped() {
+        let (sender, mut receiver) = channel(
+            QueueConfig {
+                capacity: 2,
+                overflow_timeout: Duration::from_secs(60),
+            },
+            Framing::LengthPrefixed,
+        );
+        for byte in 0..4 {
+            sender.send(message(byte)).unwrap();
+        }
+        // 只有可靠消息时丢弃新的状态消息
+        sender
+            .send_with_policy(message(9), QueuePolicy::DropOldest)
+            .unwrap();
+
+        assert_eq!(sender.depth(), 4);
+        for byte in 0..4 {
+            assert_eq!(recv_byte(&mut receiver).await, byte);
+        }
+    }
+
+    #[tokio::test]
+    async fn queue_staying_over_capacity_is_closed() {
+        let (sender, mut receiver) = channel(
+            QueueConfig {
+                capacity: 1,
+                overflow_timeout: Duration::ZERO,
+            },
+            Framing::LengthPrefixed,
+        );
+        sender.send(message(1)).unwrap();
+        assert!(sender.send(message(2)).is_err());
+
+        assert_eq!(sender.aborted().await, "send queue overflow");
+        assert!(receiver.recv().await.is_none());
+        assert!(sender.send(message(3)).is_err());
+    }
+
+    #[tokio::test]
+    async fn abort_closes_queue_with_reason() {
+        let (sender, mut receiver) = channel(QueueConfig::default(), Framing::LengthPrefixed);
+        sender.send(message(1)).unwrap();
+        sender.abort("idle timeout").unwrap();
+
+        assert_eq!(sender.aborted().await, "idle timeout");
+        assert!(receiver.recv().await.is_none());
+        assert!(sender.abort("again").is_err());
+    }
+
+    #[tokio::test]
+    async fn overflow_is_closed_without_further_sends() {
+        let config = QueueConfig {
+            capacity: 1,
+            overflow_timeout: Duration::from_millis(50),
+        };
+        let (sender, mut receiver) = channel(config, Framing::LengthPrefixed);
+        sender.send(message(1)).unwrap();
+        sender.send(message(2)).unwrap();
+
+        // 超限后不再有消息入队, 由计时器关闭
+        tokio::time::timeout(Duration::from_secs(5), sender.aborted())
+            .await
+            .unwrap();
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn draining_below_capacity_stops_overflow_timer() {
+        let config = QueueConfig {
+            capacity: 1,
+            overflow_timeout: Duration::from_millis(50),
+        };
+        let (sender, mut receiver) = channel(config, Framing::LengthPrefixed);
+        sender.send(message(1)).unwrap();
+        sender.send(message(2)).unwrap();
+        assert_eq!(recv_byte(&mut receiver).await, 1);
+
+        let aborted = tokio::time::timeout(Duration::from_millis(200), sender.aborted()).await;
+        assert!(aborted.is_err());
+        assert_eq!(recv_byte(&mut receiver).await, 2);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::net::session_delegate::{CreateSessionDelegateCallback, SessionDelegate};
+use crate::net::stream_server::StreamServer;
+use crate::net::writer_queue::QueueConfig;
+use crate::net::{net_session, tls, ws_session};
+use log::debug;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+
+/// 完成WebSocket握手后运行会话
+async fn run_session<S>(
+    stream: S,
+    addr: SocketAddr,
+    delegate: Box<dyn SessionDelegate>,
+    shutdown: broadcast::Receiver<()>,
+    handshake_timeout: Duration,
+    websocket_config: WebSocketConfig,
+    queue_config: QueueConfig,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let handshake = tokio_tungstenite::accept_async_with_config(stream, Some(websocket_config));
+    match tokio::time::timeout(handshake_timeout, handshake).await {
+        Ok(Ok(stream)) => {
+            ws_session::run(
+                net_session::create_session_id(),
+                addr,
+                delegate,
+                shutdown,
+                stream,
+                queue_config,
+            )
+            .await;
+        }
+        Ok(Err(err)) => {
+            debug!("WS Server handshake error: {err}");
+        }
+        Err(_) => {
+            debug!("WS Server handshake timeout: {addr}");
+        }
+    }
+}
+
+pub struct Builder {
+    create_session_delegate_callback: CreateSessionDelegateCallback,
+    tls_configuration: Option<tls::TlsConfiguration>,
+    handshake_timeout: Duration,
+    shutdown_timeout: Duration,
+    max_message_size: Option<usize>,
+    queue_config: QueueConfig,
+}
+
+impl Builder {
+    pub fn new(create_session_delegate_callback: CreateSessionDelegateCallback) -> Self {
+        Self {
+            create_session_delegate_callback,
+            tls_configuration: None,
+            handshake_timeout: super::DEFAULT_TLS_HANDSHAKE_TIMEOUT,
+            shutdown_timeout: super::DEFAULT_SHUTDOWN_TIMEOUT,
+            max_message_size: None,
+            queue_config: QueueConfig::default(),
+        }
+    }
+
+    pub fn set_tls_configuration<A: ToString>(mut self, certificate: A, key: A) -> Self {
+        self.tls_configuration = Some(tls::TlsConfiguration {
+            certificate: certificate.to_string(),
+            key: key.to_string(),
+        });
+        self
+    }
+
+    /// TLS握手和WebSocket升级的超时时间
+    pub fn set_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// 优雅退出等待时间, 超时后强制退出
+    pub fn set_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// 单个WebSocket消息最大字节数
+    pub fn set_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+
+    /// 每个会话发送队列的容量和超限处理
+    pub fn set_queue_config(mut self, queue_config: QueueConfig) -> Self {
+        self.queue_config = queue_config;
+        self
+    }
+
+    pub async fn build_with_listener(
+        self,
+        listener: TcpListener,
+        shutdown_condition: impl Future,
+    ) -> anyhow::Result<()> {
+        let mut websocket_config = WebSocketConfig::default();
+        if self.max_message_size.is_some() {
+            websocket_config.max_message_size = self.max_message_size;
+            websocket_config.max_frame_size = self.max_message_size;
+        }
+
+        let server = StreamServer {
+            name: "WS Server",
+            tls_configuration: self.tls_configuration,
+            tls_handshake_timeout: self.handshake_timeout,
+            shutdown_timeout: self.shutdown_timeout,
+            stream_init_callback: None,
+        };
+
+        let create_session_delegate_callback = self.create_session_delegate_callback;
+        let handshake_timeout = self.handshake_timeout;
+        let queue_config = self.queue_config;
+        server
+            .run(
+                listener,
+                shutdown_condition,
+                Arc::new(move |stream, addr, shutdown| {
+                    Box::pin(run_session(
+                        stream,
+                        addr,
+                        create_session_delegate_callback(),
+                        shutdown,
+                        handshake_timeout,
+                        websocket_config,
+                        queue_config,
+                    ))
+                }),
+            )
+            .await
+    }
+
+    pub async fn build<A: ToSocketAddrs>(
+        self,
+        addr: A,
+        shutdown_condition: impl Future,
+    ) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(&addr).await?;
+        self.build_with_listener(listener, shutdown_condition).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::Peer;
+    use futures_util::{SinkExt, StreamExt};
+    use rmc_proto::{AuthChallengeNtf, Message, Ping, Pong};
+    use tokio_tungstenite::tungstenite;
+
+    async fn next_message<S>(stream: &mut S, name: &str) -> String
+    where
+        S: futures_util::Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Unpin,
+    {
+        let frame = stream.next().await.unwrap().unwrap();
+        let message: Message = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert_eq!(message.name, name);
+        message.data
+    }
+
+    #[tokio::test]
+    async fn text_frames_carry_message_envelope() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Builder::new(Box::new(|| -> Box<dyn SessionDelegate> {
+                Box::new(Peer::new("ws"))
+            }))
+            .build_with_listener(listener, std::future::pending::<()>()),
+        );
+
+        let (mut stream, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+            .await
+            .unwrap();
+
+        // 连接后立即下发挑战, 不带长度前缀
+        let data = next_message(&mut stream, "AuthChallengeNtf").await;
+        let challenge: AuthChallengeNtf = serde_json::from_str(&data).unwrap();
+        assert!(!challenge.nonce.is_empty());
+
+        let ping = Message {
+            name: "Ping".to_string(),
+            data: serde_json::to_string(&Ping { time: 42 }).unwrap(),
+        };
+        stream
+            .send(tungstenite::Message::Text(
+                serde_json::to_string(&ping).unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        let data = next_message(&mut stream, "Pong").await;
+        let pong: Pong = serde_json::from_str(&data).unwrap();
+        assert_eq!(pong.time, 42);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::logging;
+use crate::net::session_delegate::SessionDelegate;
+use crate::net::writer_queue::{self, Framing, QueueConfig, WriterReceiver};
+use crate::net::{self, WriterMessage};
+use anyhow::anyhow;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info};
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::Instrument;
+
+/// run
+///
+/// 会话内输出的日志带有会话id和地址, 见 [`logging::session_span`]
+///
+/// WebSocket自带分帧, 收到的每个文本/二进制帧直接作为一个完整消息交给 [`SessionDelegate::on_recv_frame`]
+///
+/// 发送队列使用 [`Framing::Message`], 每个消息不带长度前缀, 单独发送一个文本帧(非UTF-8时为二进制帧)
+///
+/// [`session_id`] 会话id
+///
+/// [`addr`] 地址
+///
+/// [`delegate`] 会话代理
+///
+/// [`shutdown`] 监听退出消息
+///
+/// [`stream`] 已完成握手的WebSocket流
+///
+/// [`queue_config`] 发送队列的容量和超限处理
+pub async fn run<S>(
+    session_id: u32,
+    addr: SocketAddr,
+    mut delegate: Box<dyn SessionDelegate>,
+    mut shutdown: broadcast::Receiver<()>,
+    stream: WebSocketStream<S>,
+    queue_config: QueueConfig,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    async move {
+        let (writer, reader) = stream.split();
+        let (delegate_sender, delegate_receiver) =
+            writer_queue::channel(queue_config, Framing::Message);
+        let queue = delegate_sender.clone();
+
+        if let Err(err) = delegate
+            .on_session_start(session_id, &addr, delegate_sender)
+            .await
+        {
+            error!("[{addr}] on_session_start error:{err}");
+            return;
+        }
+
+        select! {
+            err = poll_read(addr, &mut delegate, reader) => {
+                if let Err(err) = err {
+                    info!("poll read error: {}", err);
+                }
+            }
+            _ = poll_write(addr, delegate_receiver, writer) => {}
+            reason = queue.aborted() => {
+                info!("[{addr}] {reason}, closing session");
+            }
+            _ = shutdown.recv() => {}
+        }
+
+        if let Err(err) = delegate.on_session_close().await {
+            error!("[{addr}] on_session_close error:{err}");
+        }
+    }
+    .instrument(logging::session_span(session_id, addr))
+    .await
+}
+
+/// 循环写入数据
+async fn poll_write<S>(
+    addr: SocketAddr,
+    mut delegate_receiver: WriterReceiver,
+    mut writer: SplitSink<WebSocketStream<S>, Message>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    while let Some(message) = delegate_receiver.recv().await {
+        match message {
+            WriterMessage::Close => break,
+            WriterMessage::CloseDelayed(duration) => {
+                sleep(duration).await;
+                break;
+            }
+            WriterMessage::Send(data, flush) => {
+                if let Err(error) = write_message(&mut writer, data).await {
+                    error!("[{addr}] error when write frame {:?}", error);
+                    break;
+                }
+                if flush {
+                    if let Err(error) = writer.flush().await {
+                        error!("[{addr}] error when flushing {:?}", error);
+                    }
+                }
+            }
+            WriterMessage::SendTo(..) => {
+                panic!("not support");
+            }
+            WriterMessage::SendAndThen(data, callback) => {
+                if let Err(error) = write_message(&mut writer, data).await {
+                    error!("[{addr}] error when write frame {:?}", error);
+                    break;
+                }
+                if let Err(error) = writer.flush().await {
+                    error!("[{addr}] error when flushing {:?}", error);
+                }
+                callback().await;
+            }
+            WriterMessage::Flush => {
+                if let Err(error) = writer.flush().await {
+                    error!("[{addr}] error when flushing {:?}", error);
+                }
+            }
+        }
+    }
+
+    let _ = writer.send(Message::Close(None)).await;
+    delegate_receiver.close();
+}
+
+/// 将一个完整消息写入(不刷新)
+async fn write_message<S>(
+    writer: &mut SplitSink<WebSocketStream<S>, Message>,
+    data: Vec<u8>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    net::add_bytes_sent(data.len());
+    let message = match String::from_utf8(data) {
+        Ok(text) => Message::Text(text),
+        Err(err) => Message::Binary(err.into_bytes()),
+    };
+    writer.feed(message).await?;
+    Ok(())
+}
+
+/// 循环读取数据
+async fn poll_read<S>(
+    addr: SocketAddr,
+    delegate: &mut Box<dyn SessionDelegate>,
+    mut reader: SplitStream<WebSocketStream<S>>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    while let Some(message) = reader.next().await {
+        match message? {
+            Message::Text(text) => {
+                net::add_bytes_received(text.len());
+                delegate.on_recv_frame(text.into_bytes()).await?
+            }
+            Message::Binary(data) => {
+                net::add_bytes_received(data.len());
+                delegate.on_recv_frame(data).await?
+            }
+            Message::Close(_) => break,
+            // Ping/Pong由tungstenite自动处理
+            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+        }
+    }
+
+    // 客户端主动断开
+    Err(anyhow!("[{addr}] websocket closed."))
+}
@@ -1,19 +1,21 @@
 use crate::net::session_delegate::SessionDelegate;
-use crate::net::WriterMessage;
+use crate::net::writer_queue::WriterSender;
 use crate::player::Player;
-use crate::proto::Message;
-use crate::GLOBAL_CONTEXT;
-use anyhow::anyhow;
+use crate::{config, GLOBAL_CONTEXT, GLOBAL_METRICS};
 use async_trait::async_trait;
-use byteorder::{BigEndian, ByteOrder};
 use bytes::BytesMut;
+use log::{debug, warn};
+use rmc_proto::codec::FrameCodec;
+use rmc_proto::Packet;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::codec::Decoder;
 
 pub struct Peer {
     player: Option<Arc<Player>>,
     session_id: u32,
+    /// 会话所属监听的协议
+    transport: &'static str,
 }
 
 #[async_trait]
@@ -21,10 +23,10 @@ impl SessionDelegate for Peer {
     async fn on_session_start(
         &mut self,
         session_id: u32,
-        _addr: &SocketAddr,
-        tx: UnboundedSender<WriterMessage>,
+        addr: &SocketAddr,
+        tx: WriterSender,
     ) -> anyhow::Result<()> {
-        let player = Arc::new(Player::new(session_id, tx));
+        let player = Arc::new(Player::new(session_id, *addr, self.transport, tx));
         GLOBAL_CONTEXT
             .players
             .lock()
@@ -32,13 +34,27 @@ impl SessionDelegate for Peer {
             .insert(session_id, player.clone());
         self.player = Some(player);
         self.session_id = session_id;
+        GLOBAL_METRICS
+            .sessions
+            .with_label_values(&[self.transport])
+            .inc();
         Ok(())
     }
 
     // 会话关闭回调
     async fn on_session_close(&mut self) -> anyhow::Result<()> {
-        self.player.take().unwrap().on_disconnect_session().await?;
+        GLOBAL_METRICS
+            .sessions
+            .with_label_values(&[self.transport])
+            .dec();
         GLOBAL_CONTEXT.players.lock().await.remove(&self.session_id);
+        GLOBAL_CONTEXT
+            .subscriptions
+            .lock()
+            .await
+            .remove(&self.session_id);
+        // 先从会话表移除, 下线通知不会再发给自己
+        self.player.take().unwrap().on_disconnect_session().await?;
         Ok(())
     }
 
@@ -50,46 +66,37 @@ impl SessionDelegate for Peer {
         &mut self,
         buffer: &mut BytesMut,
     ) -> anyhow::Result<Option<Vec<u8>>> {
-        // 数据小于4字节,继续读取数据
-        if buffer.len() < 4 {
-            return Ok(None);
+        let result = FrameCodec::new(config().limits.max_frame_size).decode(buffer);
+        if result.is_err() {
+            GLOBAL_METRICS.decode_errors.inc();
         }
-
-        // 读取包长度
-        let buf = buffer.get(0..4).unwrap();
-        let len = BigEndian::read_u32(buf) as usize;
-
-        // 超出最大限制
-        if len <= 0 || len >= 1024 * 1024 * 2 {
-            return Err(anyhow!("Message too long"));
-        }
-
-        // 数据不够,继续读取数据
-        if buffer.len() < 4 + len {
-            return Ok(None);
-        }
-
-        // 拆出这个包的数据
-        let frame = buffer.split_to(4 + len).split_off(4).to_vec();
-
-        Ok(Some(frame))
+        result
     }
 
     // 收到一个完整的消息包
     async fn on_recv_frame(&mut self, bytes: Vec<u8>) -> anyhow::Result<()> {
-        let str = String::from_utf8(bytes)?;
-        println!("recv: {}", str);
-        let message: Message = serde_json::from_str(&str)?;
+        let packet = match Packet::decode(&bytes) {
+            Ok(packet) => packet,
+            Err(err) => {
+                GLOBAL_METRICS.decode_errors.inc();
+                return Err(err);
+            }
+        };
+        debug!("recv: {} ({} bytes)", packet.name(), bytes.len());
+        // 未知消息名由对端决定, 合并统计避免标签无限增长
+        let name = match packet {
+            Packet::Unknown(_) => "Unknown",
+            _ => packet.name(),
+        };
+        GLOBAL_METRICS
+            .messages_received
+            .with_label_values(&[name])
+            .inc();
 
-        let result = self
-            .player
-            .as_ref()
-            .unwrap()
-            .on_recv_message(&message)
-            .await;
+        let result = self.player.as_ref().unwrap().on_recv_message(packet).await;
 
         if let Err(ref err) = result {
-            println!("on_recv_message err: {}", err.to_string());
+            warn!("on_recv_message err: {err}");
         }
 
         result
@@ -97,10 +104,11 @@ impl SessionDelegate for Peer {
 }
 
 impl Peer {
-    pub fn new() -> Self {
+    pub fn new(transport: &'static str) -> Self {
         Peer {
             player: None,
             session_id: 0,
+            transport,
         }
     }
 }
@@ -1,97 +1,225 @@
+use crate::access_control::Credential;
+use crate::auth;
+use crate::codec::SessionEncoding;
+use crate::net::writer_queue::{Framing, QueuePolicy, WriterSender};
 use crate::net::WriterMessage;
-use crate::proto::{
-    Message, Ping, Pong, PushMediaKeyEvent, SendControlMediaKeyEventRequest,
-    SendControlMediaKeyEventResponse,
+use crate::{config, device_auth_required, GLOBAL_ACCESS_CONTROL, GLOBAL_CONTEXT, GLOBAL_METRICS};
+use anyhow::anyhow;
+use log::{debug, info, warn};
+use rmc_proto::codec::encode_frame;
+use rmc_proto::{
+    artwork_chunks, is_valid_key_event, ArtworkAssembler, ArtworkChunk, ArtworkRequest,
+    ArtworkResponse, AuthChallengeNtf, AuthRequest, AuthResponse, DeviceInfo, DeviceOfflineNtf,
+    DeviceOnlineNtf, DeviceRole, Encoding, Hello, HelloResponse, ListDevicesRequest,
+    ListDevicesResponse, MediaKeyEventAck, MediaKeyEventResult, NowPlayingNtf, Packet, Ping, Pong,
+    Protocol, PushMediaCommand, PushMediaKeyEvent, RegisterDeviceRequest, RegisterDeviceResponse,
+    RttEstimator, SendControlMediaKeyEventRequest, SendControlMediaKeyEventResponse,
+    SendMediaCommandRequest, SendMediaCommandResponse, SetVolumeRequest, SetVolumeResponse,
+    SubscribeRequest, SubscribeResponse, UnsubscribeRequest, UnsubscribeResponse, VolumeChange,
+    VolumeStateNtf, ACTION_DOWN, ACTION_UP, ERROR_ARTWORK_NOT_FOUND, ERROR_DEVICE_OFFLINE,
+    ERROR_INVALID_COMMAND, ERROR_INVALID_KEY_CODE, ERROR_NOT_AUTHENTICATED, ERROR_NO_PERMISSION,
+    ERROR_UNSUPPORTED_COMMAND, FEATURE_ARTWORK, FEATURE_DEVICE_PRESENCE, FEATURE_MEDIA_COMMAND,
+    FEATURE_MEDIA_KEY_ACK, FEATURE_NOW_PLAYING, FEATURE_VOLUME_STATE, MAX_VOLUME_STEPS,
+    PROTOCOL_VERSION, SUBSCRIBE_ALL,
 };
-use crate::{GLOBAL_CONTEXT, GLOBAL_OPTS};
-use byteorder::BigEndian;
-use serde::Serialize;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tokio::task::JoinHandle;
 use tokio::time::{sleep, Instant};
+use tracing::{Instrument, Span};
+
+static REQUEST_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+/// 合并音量调整的时间窗口
+const VOLUME_COALESCE_INTERVAL: Duration = Duration::from_millis(150);
+
+/// 等待设备确认的按键事件
+pub struct PendingAck {
+    /// 发起请求的控制端会话
+    controller_session_id: u32,
+    token: String,
+    /// 尚未确认的设备数量
+    remaining: u32,
+}
 
 pub struct Player {
-    pub tx: UnboundedSender<WriterMessage>,
+    pub tx: WriterSender,
     ping_task: JoinHandle<()>,
+    register_timeout_task: JoinHandle<()>,
     session_id: u32,
+    addr: SocketAddr,
+    /// 会话所属监听的协议
+    transport: &'static str,
+    /// 连接时间(毫秒时间戳)
+    connect_time: u64,
     last_active_time: Arc<RwLock<Instant>>,
+    /// 根据心跳计算的往返时间和抖动
+    rtt: Arc<RwLock<RttEstimator>>,
+    registration: Arc<RwLock<Option<RegisterDeviceRequest>>>,
+    /// 本会话的认证挑战, 每个挑战只能使用一次
+    nonce: RwLock<Option<String>>,
+    /// 认证通过的凭据
+    credential: RwLock<Option<&'static Credential>>,
+    /// 发送消息使用的编码, 注册时协商
+    encoding: Arc<SessionEncoding>,
+    /// Hello 协商的协议版本和功能, 未发送 Hello 的旧客户端为版本1
+    protocol: RwLock<Protocol>,
+    /// 设备最近一次上报的正在播放信息
+    now_playing: RwLock<Option<NowPlayingNtf>>,
+    /// 设备最近一次上报的音量
+    volume: RwLock<Option<VolumeStateNtf>>,
+    /// 已向设备请求、尚未上传完成的封面
+    requested_artwork: RwLock<String>,
+    artwork_upload: Mutex<ArtworkAssembler>,
 }
 
 impl Player {
-    pub fn new(session_id: u32, tx: UnboundedSender<WriterMessage>) -> Self {
+    pub fn new(
+        session_id: u32,
+        addr: SocketAddr,
+        transport: &'static str,
+        tx: WriterSender,
+    ) -> Self {
         let last_active_time = Arc::new(RwLock::new(Instant::now()));
+        let registration = Arc::new(RwLock::new(None));
+
+        let encoding = Arc::new(SessionEncoding::default());
+
+        let rtt = Arc::new(RwLock::new(RttEstimator::default()));
+        let timeouts = &config().timeouts;
+        let ping_task = tokio::spawn(
+            keepalive(
+                tx.clone(),
+                encoding.clone(),
+                last_active_time.clone(),
+                rtt.clone(),
+                timeouts.ping_interval(),
+                timeouts.ping_idle(),
+                timeouts.session_idle(),
+            )
+            .in_current_span(),
+        );
 
         let tx_cloned = tx.clone();
-        let last_active_time_cloned = last_active_time.clone();
+        let registration_cloned = registration.clone();
+        let register_timeout = timeouts.register();
+        let register_timeout_task = tokio::spawn(
+            async move {
+                // 会话必须在此时间内完成注册, 否则断开
+                sleep(register_timeout).await;
+                if registration_cloned.read().await.is_none() {
+                    info!("register timeout, closing session");
+                    let _ = send_message(
+                        &tx_cloned,
+                        Encoding::Json,
+                        RegisterDeviceResponse {
+                            ok: false,
+                            error: "register timeout".to_string(),
+                            encoding: "".to_string(),
+                        },
+                    );
+                    let _ = tx_cloned.send(WriterMessage::Close);
+                }
+            }
+            .in_current_span(),
+        );
+
+        // 会话开始即下发认证挑战
+        let nonce = auth::new_nonce();
+        let _ = send_message(
+            &tx,
+            Encoding::Json,
+            AuthChallengeNtf {
+                nonce: nonce.clone(),
+            },
+        );
 
         Self {
             tx,
-            ping_task: tokio::spawn(async move {
-                loop {
-                    sleep(Duration::from_secs(2)).await;
-                    if last_active_time_cloned.read().await.elapsed() < Duration::from_secs(10) {
-                        continue;
-                    }
-                    let _ = send_message(&tx_cloned, &Ping { time: now_millis() });
-                }
-            }),
+            ping_task,
+            register_timeout_task,
             session_id,
+            addr,
+            transport,
+            connect_time: now_millis(),
             last_active_time,
+            rtt,
+            registration,
+            nonce: RwLock::new(Some(nonce)),
+            credential: RwLock::new(None),
+            encoding,
+            protocol: RwLock::new(Protocol::legacy()),
+            now_playing: RwLock::new(None),
+            volume: RwLock::new(None),
+            requested_artwork: RwLock::new(String::new()),
+            artwork_upload: Mutex::new(ArtworkAssembler::default()),
         }
     }
 
-    pub async fn on_recv_message(&self, message: &Message) -> anyhow::Result<()> {
+    pub async fn on_recv_message(&self, packet: Packet) -> anyhow::Result<()> {
         if self.last_active_time.read().await.elapsed() >= Duration::from_secs(1) {
             let mut instant_write = self.last_active_time.write().await;
             *instant_write = Instant::now();
         }
 
-        match message.name.as_str() {
-            "Ping" => {
-                let ping: Ping = serde_json::from_str(&message.data)?;
-                send_message(&self.tx, &Pong { time: ping.time })?;
-            }
-            "Pong" => {}
-            "SendControlMediaKeyEventRequest" => {
-                let request: SendControlMediaKeyEventRequest = serde_json::from_str(&message.data)?;
-
-                if GLOBAL_OPTS.authorization_code == request.authorization_code {
-                    let push = PushMediaKeyEvent {
-                        action: request.action,
-                        code: request.code,
-                        token: request.token,
-                    };
-
-                    for (_, (_, player)) in GLOBAL_CONTEXT
-                        .players
-                        .lock()
-                        .await
-                        .iter()
-                        .filter(|(session_id, _)| **session_id != self.session_id)
-                        .enumerate()
-                    {
-                        send_message(&player.tx, &push)?;
-                    }
+        let packet = match packet {
+            Packet::Ping(ping) => return self.send(Pong { time: ping.time }),
+            Packet::Pong(pong) => {
+                self.on_pong(pong).await;
+                return Ok(());
+            }
+            Packet::Hello(hello) => return self.on_hello(hello).await,
+            Packet::RegisterDeviceRequest(request) => {
+                return self.on_register_device_request(request).await
+            }
+            Packet::AuthRequest(request) => return self.on_auth_request(request).await,
+            packet => packet,
+        };
 
-                    send_message(
-                        &self.tx,
-                        &SendControlMediaKeyEventResponse {
-                            ok: true,
-                            error: "".to_string(),
-                        },
-                    )?;
-                } else {
-                    send_message(
-                        &self.tx,
-                        &SendControlMediaKeyEventResponse {
-                            ok: false,
-                            error: "no permission".to_string(),
-                        },
-                    )?;
-                }
+        // 除心跳、注册和认证外, 未注册的会话不允许发送任何消息
+        let role = match self.role().await {
+            Some(role) => role,
+            None => return Err(anyhow!("session {} is not registered", self.session_id)),
+        };
+
+        match packet {
+            Packet::SendControlMediaKeyEventRequest(request) => {
+                self.on_send_control_media_key_event_request(role, request)
+                    .await?;
+            }
+            Packet::SendMediaCommandRequest(request) => {
+                self.on_send_media_command_request(role, request).await?;
+            }
+            Packet::MediaKeyEventAck(ack) => {
+                self.on_media_key_event_ack(role, ack).await?;
+            }
+            Packet::ListDevicesRequest(request) => {
+                self.on_list_devices_request(role, request).await?;
+            }
+            Packet::SubscribeRequest(request) => {
+                self.on_subscribe_request(role, request).await?;
+            }
+            Packet::UnsubscribeRequest(request) => {
+                self.on_unsubscribe_request(role, request).await?;
+            }
+            Packet::NowPlayingNtf(now_playing) => {
+                self.on_now_playing_ntf(role, now_playing).await?;
+            }
+            Packet::SetVolumeRequest(request) => {
+                self.on_set_volume_request(role, request).await?;
+            }
+            Packet::VolumeStateNtf(volume) => {
+                self.on_volume_state_ntf(role, volume).await?;
+            }
+            Packet::ArtworkRequest(request) => {
+                self.on_artwork_request(role, request).await?;
+            }
+            Packet::ArtworkChunk(chunk) => {
+                self.on_artwork_chunk(role, chunk).await;
             }
             _ => {}
         }
@@ -99,18 +227,1247 @@ impl Player {
         Ok(())
     }
 
+    /// Pong 带回服务器发送 Ping 时的时间, 差值即往返时间
+    async fn on_pong(&self, pong: Pong) {
+        let now = now_millis();
+        if pong.time > now {
+            return;
+        }
+        let rtt = Duration::from_millis(now - pong.time);
+        self.rtt.write().await.update(rtt);
+        GLOBAL_METRICS.ping_rtt.observe(rtt.as_secs_f64());
+    }
+
     pub async fn on_disconnect_session(&self) -> anyhow::Result<()> {
         self.ping_task.abort();
+        self.register_timeout_task.abort();
+        self.unregister_token().await;
+
+        // 未上传完成的封面不会再收到
+        let requested_artwork = std::mem::take(&mut *self.requested_artwork.write().await);
+        if !requested_artwork.is_empty() {
+            notify_artwork_waiters(&requested_artwork, None).await;
+        }
+
+        if let Some(device) = self.device_info().await {
+            notify_subscribers(
+                &device.token,
+                FEATURE_DEVICE_PRESENCE,
+                DeviceOfflineNtf {
+                    session_id: device.session_id,
+                    token: device.token.clone(),
+                }
+                .into(),
+            )
+            .await;
+        }
         Ok(())
     }
+
+    /// 发送队列中等待写入的消息数
+    pub fn queue_depth(&self) -> usize {
+        self.tx.depth()
+    }
+
+    /// 对端是否支持某个可选功能
+    pub async fn supports(&self, feature: &str) -> bool {
+        self.protocol.read().await.supports(feature)
+    }
+
+    /// 会话角色, 未注册时为 None
+    pub async fn role(&self) -> Option<DeviceRole> {
+        self.registration.read().await.as_ref().map(|x| x.role)
+    }
+
+    /// 设备注册的token, 非设备会话为 None
+    async fn device_token(&self, role: DeviceRole) -> Option<String> {
+        if role != DeviceRole::Device {
+            return None;
+        }
+        self.registration
+            .read()
+            .await
+            .as_ref()
+            .map(|x| x.token.clone())
+    }
+
+    /// 订阅的设备中凭据可访问的设备缓存的状态(正在播放、音量), 只包含本会话支持的功能
+    async fn cached_device_states(
+        &self,
+        credential: &Credential,
+        tokens: &[String],
+    ) -> Vec<Packet> {
+        let now_playing = self.supports(FEATURE_NOW_PLAYING).await;
+        let volume = self.supports(FEATURE_VOLUME_STATE).await;
+        let players: Vec<Arc<Player>> = GLOBAL_CONTEXT
+            .players
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect();
+
+        let mut result = Vec::new();
+        for player in players {
+            let Some(token) = player.device_token(DeviceRole::Device).await else {
+                continue;
+            };
+            if !credential.can_access(&token) || !subscribes(tokens.iter(), &token) {
+                continue;
+            }
+            if let Some(state) = player
+                .now_playing
+                .read()
+                .await
+                .clone()
+                .filter(|_| now_playing)
+            {
+                result.push(state.into());
+            }
+            if let Some(state) = player.volume.read().await.clone().filter(|_| volume) {
+                result.push(state.into());
+            }
+        }
+        result
+    }
+
+    /// 认证通过的控制端凭据
+    async fn controller_credential(&self, role: DeviceRole) -> Option<&'static Credential> {
+        if role != DeviceRole::Controller {
+            return None;
+        }
+        *self.credential.read().await
+    }
+
+    /// 设备信息, 仅已注册的设备会话有
+    pub async fn device_info(&self) -> Option<DeviceInfo> {
+        let registration = self.registration.read().await;
+        let registration = registration.as_ref()?;
+        if registration.role != DeviceRole::Device {
+            return None;
+        }
+
+        let idle = self.last_active_time.read().await.elapsed().as_millis() as u64;
+        let rtt = *self.rtt.read().await;
+        Some(DeviceInfo {
+            session_id: self.session_id,
+            name: registration.device_name.clone(),
+            token: registration.token.clone(),
+            addr: self.addr.to_string(),
+            connect_time: self.connect_time,
+            last_active_time: now_millis().saturating_sub(idle),
+            rtt_ms: rtt.srtt_ms(),
+            jitter_ms: rtt.jitter_ms(),
+        })
+    }
+
+    async fn on_hello(&self, hello: Hello) -> anyhow::Result<()> {
+        let result = if self.registration.read().await.is_some() {
+            Err("hello must be sent before registration".to_string())
+        } else {
+            Protocol::negotiate(&hello)
+        };
+
+        match result {
+            Ok(mut protocol) => {
+                // 封面分片可能超过UDP数据报的大小上限, 且没有重传, udp会话不传输封面
+                if self.transport == "udp" {
+                    protocol.features.remove(FEATURE_ARTWORK);
+                }
+                let response = HelloResponse {
+                    ok: true,
+                    error: "".to_string(),
+                    protocol_version: protocol.version,
+                    features: protocol.features.iter().cloned().collect(),
+                };
+                *self.protocol.write().await = protocol;
+                self.send(response)
+            }
+            Err(error) => {
+                self.send(HelloResponse {
+                    ok: false,
+                    error,
+                    protocol_version: PROTOCOL_VERSION,
+                    features: vec![],
+                })?;
+                // 留出时间让对端收到错误原因
+                self.tx
+                    .send(WriterMessage::CloseDelayed(Duration::from_secs(1)))?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn on_register_device_request(
+        &self,
+        request: RegisterDeviceRequest,
+    ) -> anyhow::Result<()> {
+        let error = if self.registration.read().await.is_some() {
+            "already registered"
+        } else if request.role == DeviceRole::Device && request.token.is_empty() {
+            "empty token"
+        } else if request.role == DeviceRole::Device {
+            check_device_registration(
+                device_auth_required(),
+                *self.credential.read().await,
+                &request.token,
+            )
+        } else {
+            ""
+        };
+
+        if !error.is_empty() {
+            return self.send(RegisterDeviceResponse {
+                ok: false,
+                error: error.to_string(),
+                encoding: "".to_string(),
+            });
+        }
+
+        // 只有设备才进入 token 索引, 控制端不接收按键事件
+        if request.role == DeviceRole::Device {
+            GLOBAL_CONTEXT
+                .devices
+                .lock()
+                .await
+                .entry(request.token.clone())
+                .or_default()
+                .insert(self.session_id);
+        }
+        let encoding = Encoding::negotiate(&request.encodings);
+        let span = Span::current();
+        span.record(
+            "role",
+            match request.role {
+                DeviceRole::Device => "device",
+                DeviceRole::Controller => "controller",
+            },
+        );
+        if !request.token.is_empty() {
+            span.record("token", request.token.as_str());
+        }
+        info!(
+            "registered as {:?}, encoding {}",
+            request.role,
+            encoding.name()
+        );
+        *self.registration.write().await = Some(request);
+        self.register_timeout_task.abort();
+
+        // 响应本身仍使用 JSON, 之后的消息切换到协商的编码
+        self.send(RegisterDeviceResponse {
+            ok: true,
+            error: "".to_string(),
+            encoding: encoding.name().to_string(),
+        })?;
+        self.encoding.set(encoding);
+
+        if let Some(device) = self.device_info().await {
+            notify_subscribers(
+                &device.token.clone(),
+                FEATURE_DEVICE_PRESENCE,
+                DeviceOnlineNtf { device }.into(),
+            )
+            .await;
+        }
+        Ok(())
+    }
+
+    async fn on_auth_request(&self, request: AuthRequest) -> anyhow::Result<()> {
+        let nonce = self.nonce.write().await.take();
+        let credential = GLOBAL_ACCESS_CONTROL
+            .get()
+            .and_then(|access_control| access_control.find_by_name(&request.name));
+
+        let error = match (nonce, credential) {
+            (None, _) => "no pending challenge",
+            (Some(nonce), Some(credential))
+                if auth::verify_proof(
+                    credential.secret.expose(),
+                    &nonce,
+                    request.proof.expose(),
+                ) =>
+            {
+                *self.credential.write().await = Some(credential);
+                ""
+            }
+            _ => "authentication failed",
+        };
+        if error.is_empty() {
+            info!("authenticated as {}", request.name);
+        } else {
+            GLOBAL_METRICS.auth_failures.inc();
+            warn!("authentication as {} failed: {error}", request.name);
+        }
+
+        self.send(AuthResponse {
+            ok: error.is_empty(),
+            error: error.to_string(),
+        })
+    }
+
+    async fn on_list_devices_request(
+        &self,
+        role: DeviceRole,
+        _request: ListDevicesRequest,
+    ) -> anyhow::Result<()> {
+        let Some(credential) = self.controller_credential(role).await else {
+            return self.send(ListDevicesResponse {
+                ok: false,
+                error: ERROR_NOT_AUTHENTICATED.to_string(),
+                devices: vec![],
+            });
+        };
+
+        let devices = list_devices(credential).await;
+        self.send(ListDevicesResponse {
+            ok: true,
+            error: "".to_string(),
+            devices,
+        })
+    }
+
+    async fn on_subscribe_request(
+        &self,
+        role: DeviceRole,
+        request: SubscribeRequest,
+    ) -> anyhow::Result<()> {
+        let Some(credential) = self.controller_credential(role).await else {
+            return self.send(SubscribeResponse {
+                ok: false,
+                error: ERROR_NOT_AUTHENTICATED.to_string(),
+                tokens: vec![],
+            });
+        };
+
+        // 有一个token无权访问时整个请求失败
+        let allowed = request
+            .tokens
+            .iter()
+            .all(|token| token == SUBSCRIBE_ALL || credential.can_access(token));
+        let tokens = {
+            let mut subscriptions = GLOBAL_CONTEXT.subscriptions.lock().await;
+            let subscribed = subscriptions.entry(self.session_id).or_default();
+            if allowed {
+                subscribed.extend(request.tokens.iter().cloned());
+            }
+            sorted(subscribed)
+        };
+
+        self.send(SubscribeResponse {
+            ok: allowed,
+            error: if allowed { "" } else { ERROR_NO_PERMISSION }.to_string(),
+            tokens,
+        })?;
+
+        // 立即补发已缓存的设备状态, 不必等设备下一次上报
+        if allowed {
+            for packet in self.cached_device_states(credential, &request.tokens).await {
+                self.send(packet)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_unsubscribe_request(
+        &self,
+        role: DeviceRole,
+        request: UnsubscribeRequest,
+    ) -> anyhow::Result<()> {
+        if self.controller_credential(role).await.is_none() {
+            return self.send(UnsubscribeResponse {
+                ok: false,
+                error: ERROR_NOT_AUTHENTICATED.to_string(),
+                tokens: vec![],
+            });
+        }
+
+        let tokens = {
+            let mut subscriptions = GLOBAL_CONTEXT.subscriptions.lock().await;
+            match subscriptions.get_mut(&self.session_id) {
+                Some(subscribed) => {
+                    for token in request.tokens.iter() {
+                        subscribed.remove(token);
+                    }
+                    sorted(subscribed)
+                }
+                None => vec![],
+            }
+        };
+        self.send(UnsubscribeResponse {
+            ok: true,
+            error: "".to_string(),
+            tokens,
+        })
+    }
+
+    /// 设备上报正在播放信息
+    async fn on_now_playing_ntf(
+        &self,
+        role: DeviceRole,
+        mut now_playing: NowPlayingNtf,
+    ) -> anyhow::Result<()> {
+        let Some(token) = self.device_token(role).await else {
+            return Ok(());
+        };
+
+        // token 以注册信息为准, 时间以服务器时钟为准
+        now_playing.token = token.clone();
+        now_playing.updated_at = now_millis();
+        *self.now_playing.write().await = Some(now_playing.clone());
+
+        // 提前获取封面, 控制端请求时可以直接从缓存发送
+        let hash = &now_playing.artwork_hash;
+        if !hash.is_empty() && !GLOBAL_CONTEXT.artworks.lock().await.contains(hash) {
+            self.request_artwork(hash).await?;
+        }
+
+        notify_subscribers(&token, FEATURE_NOW_PLAYING, now_playing.into()).await;
+        Ok(())
+    }
+
+    /// 设备上报音量
+    async fn on_volume_state_ntf(
+        &self,
+        role: DeviceRole,
+        mut volume: VolumeStateNtf,
+    ) -> anyhow::Result<()> {
+        let Some(token) = self.device_token(role).await else {
+            return Ok(());
+        };
+
+        volume.token = token.clone();
+        volume.percent = volume.percent.min(100);
+        volume.updated_at = now_millis();
+        *self.volume.write().await = Some(volume.clone());
+
+        notify_subscribers(&token, FEATURE_VOLUME_STATE, volume.into()).await;
+        Ok(())
+    }
+
+    async fn on_set_volume_request(
+        &self,
+        role: DeviceRole,
+        request: SetVolumeRequest,
+    ) -> anyhow::Result<()> {
+        let credential = self.controller_credential(role).await;
+        let error = match set_volume(credential, request).await {
+            Ok(()) => "",
+            Err(error) => error,
+        };
+        self.send(SetVolumeResponse {
+            ok: error.is_empty(),
+            error: error.to_string(),
+        })
+    }
+
+    /// 控制端请求封面, 缓存中没有时向正在播放该封面的设备请求, 上传完成后再发送
+    async fn on_artwork_request(
+        &self,
+        role: DeviceRole,
+        request: ArtworkRequest,
+    ) -> anyhow::Result<()> {
+        let not_found = |error: &str| ArtworkResponse {
+            ok: false,
+            error: error.to_string(),
+            hash: request.hash.clone(),
+            total_size: 0,
+        };
+        let Some(credential) = self.controller_credential(role).await else {
+            return self.send(not_found(ERROR_NOT_AUTHENTICATED));
+        };
+        if !self.supports(FEATURE_ARTWORK).await {
+            return self.send(not_found(ERROR_UNSUPPORTED_COMMAND));
+        }
+
+        let cached = GLOBAL_CONTEXT.artworks.lock().await.get(&request.hash);
+        if let Some(data) = cached {
+            return send_artwork(self, &request.hash, &data);
+        }
+
+        let Some(device) = artwork_owner(credential, &request.hash).await else {
+            return self.send(not_found(ERROR_ARTWORK_NOT_FOUND));
+        };
+        GLOBAL_CONTEXT
+            .artwork_waiters
+            .lock()
+            .await
+            .entry(request.hash.clone())
+            .or_default()
+            .insert(self.session_id);
+        device.request_artwork(&request.hash).await
+    }
+
+    /// 设备上传的封面分片, 只接收服务器请求过的封面
+    async fn on_artwork_chunk(&self, role: DeviceRole, chunk: ArtworkChunk) {
+        if role != DeviceRole::Device || *self.requested_artwork.read().await != chunk.hash {
+            return;
+        }
+
+        let hash = chunk.hash.clone();
+        let result = self.artwork_upload.lock().await.push(chunk);
+        let data = match result {
+            Ok(None) => return,
+            Ok(Some((_, data))) => {
+                let data = Arc::new(data);
+                GLOBAL_CONTEXT
+                    .artworks
+                    .lock()
+                    .await
+                    .insert(hash.clone(), data.clone());
+                Some(data)
+            }
+            Err(err) => {
+                warn!("artwork upload error: {err}");
+                None
+            }
+        };
+
+        self.requested_artwork.write().await.clear();
+        notify_artwork_waiters(&hash, data.as_deref().map(|x| x.as_slice())).await;
+    }
+
+    /// 向设备请求封面, 正在上传的封面不重复请求
+    async fn request_artwork(&self, hash: &str) -> anyhow::Result<()> {
+        if !self.supports(FEATURE_ARTWORK).await {
+            return Ok(());
+        }
+        let mut requested_artwork = self.requested_artwork.write().await;
+        if *requested_artwork == hash {
+            return Ok(());
+        }
+        *requested_artwork = hash.to_string();
+        self.send(ArtworkRequest {
+            hash: hash.to_string(),
+        })
+    }
+
+    async fn on_send_control_media_key_event_request(
+        &self,
+        role: DeviceRole,
+        request: SendControlMediaKeyEventRequest,
+    ) -> anyhow::Result<()> {
+        let credential = self.controller_credential(role).await;
+        let delivery =
+            match deliver_media_key_event(credential, request, Some(self.session_id)).await {
+                Ok(result) => result,
+                Err(error) => {
+                    return self.send(SendControlMediaKeyEventResponse {
+                        ok: false,
+                        error: error.to_string(),
+                        delivered: 0,
+                        request_id: 0,
+                    });
+                }
+            };
+
+        self.wait_media_key_event_ack(&delivery).await;
+        self.send(media_key_event_response(&delivery))
+    }
+
+    async fn on_send_media_command_request(
+        &self,
+        role: DeviceRole,
+        request: SendMediaCommandRequest,
+    ) -> anyhow::Result<()> {
+        let credential = self.controller_credential(role).await;
+        let delivery = match deliver_media_command(credential, request, Some(self.session_id)).await
+        {
+            Ok(result) => result,
+            Err(error) => {
+                return self.send(SendMediaCommandResponse {
+                    ok: false,
+                    error: error.to_string(),
+                    delivered: 0,
+                    request_id: 0,
+                });
+            }
+        };
+
+        self.wait_media_key_event_ack(&delivery).await;
+        self.send(media_command_response(&delivery))
+    }
+
+    /// 等待确认的按键事件超时后通知控制端, 确认记录在投递前已登记
+    ///
+    /// 不支持确认的旧设备不参与等待, 全部为旧设备时不会收到最终结果
+    async fn wait_media_key_event_ack(&self, delivery: &Delivery) {
+        if delivery.awaiting_ack == 0 {
+            return;
+        }
+
+        tokio::spawn(
+            expire_media_key_event_ack(
+                delivery.request_id,
+                delivery.token.clone(),
+                self.tx.clone(),
+                self.encoding.get(),
+                config().timeouts.media_key_ack(),
+            )
+            .in_current_span(),
+        );
+    }
+
+    /// 设备确认按键事件, 转发给发起请求的控制端
+    async fn on_media_key_event_ack(
+        &self,
+        role: DeviceRole,
+        mut ack: MediaKeyEventAck,
+    ) -> anyhow::Result<()> {
+        if role != DeviceRole::Device {
+            return Ok(());
+        }
+
+        let token = match self.registration.read().await.as_ref() {
+            Some(registration) => registration.token.clone(),
+            None => return Ok(()),
+        };
+
+        let controller_session_id = {
+            let mut pending_acks = GLOBAL_CONTEXT.pending_acks.lock().await;
+            let Some(pending) = pending_acks.get_mut(&ack.request_id) else {
+                // 已超时或未知的请求
+                return Ok(());
+            };
+            // 只能确认发给自己token的按键事件
+            if pending.token != token {
+                return Ok(());
+            }
+
+            let controller_session_id = pending.controller_session_id;
+            pending.remaining = pending.remaining.saturating_sub(1);
+            if pending.remaining == 0 {
+                pending_acks.remove(&ack.request_id);
+            }
+            controller_session_id
+        };
+
+        ack.token = token;
+        if let Some(player) = GLOBAL_CONTEXT
+            .players
+            .lock()
+            .await
+            .get(&controller_session_id)
+        {
+            player.send(ack)?;
+        }
+        Ok(())
+    }
+
+    /// 使用本会话协商的编码发送消息
+    pub fn send(&self, packet: impl Into<Packet>) -> anyhow::Result<()> {
+        send_message(&self.tx, self.encoding.get(), packet)
+    }
+
+    /// 从 token 索引中移除本会话
+    async fn unregister_token(&self) {
+        let registration = self.registration.read().await;
+        let Some(registration) = registration.as_ref() else {
+            return;
+        };
+        if registration.role != DeviceRole::Device {
+            return;
+        }
+
+        let mut devices = GLOBAL_CONTEXT.devices.lock().await;
+        if let Some(session_ids) = devices.get_mut(&registration.token) {
+            session_ids.remove(&self.session_id);
+            if session_ids.is_empty() {
+                devices.remove(&registration.token);
+            }
+        }
+    }
+}
+
+/// 凭据可访问的在线设备, 按会话id排序; 凭据看不到的token置空
+pub async fn list_devices(credential: &Credential) -> Vec<DeviceInfo> {
+    let players: Vec<Arc<Player>> = GLOBAL_CONTEXT
+        .players
+        .lock()
+        .await
+        .values()
+        .cloned()
+        .collect();
+
+    let mut devices = Vec::new();
+    for player in players {
+        if let Some(mut device) = player.device_info().await {
+            if credential.can_access(&device.token) {
+                if !credential.can_see_token(&device.token) {
+                    device.token.clear();
+                }
+                devices.push(device);
+            }
+        }
+    }
+    devices.sort_by_key(|device| device.session_id);
+    devices
+}
+
+/// 按键事件或媒体控制指令的投递结果
+pub struct Delivery {
+    pub request_id: u64,
+    pub token: String,
+    /// 接收设备确认的控制端会话, HTTP接口没有
+    ack_to: Option<u32>,
+    /// 投递前登记的等待确认数量
+    expected_acks: u32,
+    /// 成功投递的设备数量
+    pub delivered: u32,
+    /// 其中支持 MediaKeyEventAck 的设备数量
+    pub awaiting_ack: u32,
+    /// 在线但无法执行该指令的设备数量
+    pub unsupported: u32,
+}
+
+impl Delivery {
+    fn new(token: String, ack_to: Option<u32>) -> Self {
+        Self {
+            request_id: next_request_id(),
+            token,
+            ack_to,
+            expected_acks: 0,
+            delivered: 0,
+            awaiting_ack: 0,
+            unsupported: 0,
+        }
+    }
+
+    /// 投递失败时的错误码, 没有设备收到时为设备离线或设备都不支持该指令
+    fn error(&self) -> &'static str {
+        if self.delivered > 0 {
+            ""
+        } else if self.unsupported > 0 {
+            ERROR_UNSUPPORTED_COMMAND
+        } else {
+            ERROR_DEVICE_OFFLINE
+        }
+    }
+
+    async fn delivered_to(&mut self, player: &Player) {
+        self.delivered += 1;
+        if player.supports(FEATURE_MEDIA_KEY_ACK).await {
+            self.awaiting_ack += 1;
+        }
+    }
+
+    /// 投递前登记等待确认的请求, 设备的确认可能在投递结束前就到达
+    ///
+    /// 先按所有支持确认的设备计数, 投递后由 [`Delivery::settle_pending_ack`] 修正
+    async fn register_pending_ack(&mut self, players: &[Arc<Player>]) {
+        let Some(controller_session_id) = self.ack_to else {
+            return;
+        };
+        for player in players {
+            if player.supports(FEATURE_MEDIA_KEY_ACK).await {
+                self.expected_acks += 1;
+            }
+        }
+        if self.expected_acks == 0 {
+            return;
+        }
+        GLOBAL_CONTEXT.pending_acks.lock().await.insert(
+            self.request_id,
+            PendingAck {
+                controller_session_id,
+                token: self.token.clone(),
+                remaining: self.expected_acks,
+            },
+        );
+    }
+
+    /// 扣除未投递成功的设备, 没有需要等待的确认时移除登记
+    async fn settle_pending_ack(&self) {
+        if self.expected_acks == 0 {
+            return;
+        }
+        let undelivered = self.expected_acks.saturating_sub(self.awaiting_ack);
+        let mut pending_acks = GLOBAL_CONTEXT.pending_acks.lock().await;
+        if let Some(pending) = pending_acks.get_mut(&self.request_id) {
+            pending.remaining = pending.remaining.saturating_sub(undelivered);
+            if pending.remaining == 0 {
+                pending_acks.remove(&self.request_id);
+            }
+        }
+    }
 }
 
-fn type_name_of<T>() -> &'static str {
-    let full_type_name = std::any::type_name::<T>();
-    full_type_name
-        .rsplit("::")
-        .next()
-        .unwrap_or_else(|| full_type_name)
+/// 定期发送心跳, 空闲超过 [`session_idle`] 时中断会话
+async fn keepalive(
+    tx: WriterSender,
+    encoding: Arc<SessionEncoding>,
+    last_active_time: Arc<RwLock<Instant>>,
+    rtt: Arc<RwLock<RttEstimator>>,
+    ping_interval: Duration,
+    ping_idle: Duration,
+    session_idle: Duration,
+) {
+    let mut last_ping_time = Instant::now();
+    loop {
+        sleep(ping_interval).await;
+        let idle = last_active_time.read().await.elapsed();
+        // 活跃的会话也定期发送心跳, 保证往返时间是最近测量的
+        if idle < ping_idle && last_ping_time.elapsed() < ping_idle {
+            continue;
+        }
+        // 半开连接的写端可能一直阻塞, 直接中断会话而不是排队等待关闭
+        if idle > session_idle {
+            info!(
+                "idle for {:?} (last rtt: {:?}), closing",
+                idle,
+                rtt.read().await.srtt()
+            );
+            if let Err(err) = tx.abort("idle timeout") {
+                warn!("abort idle session error: {err}");
+            }
+            break;
+        }
+        last_ping_time = Instant::now();
+        if let Err(err) = send_message(&tx, encoding.get(), Ping { time: now_millis() }) {
+            warn!("send ping error: {err}");
+        }
+    }
+}
+
+/// ack_timeout 后仍未确认的按键事件以 TimedOut 通知控制端
+async fn expire_media_key_event_ack(
+    request_id: u64,
+    token: String,
+    controller_tx: WriterSender,
+    encoding: Encoding,
+    ack_timeout: Duration,
+) {
+    sleep(ack_timeout).await;
+    let pending = GLOBAL_CONTEXT.pending_acks.lock().await.remove(&request_id);
+    if pending.is_some() {
+        debug!("media key event {request_id} timed out waiting for ack");
+        let _ = send_message(
+            &controller_tx,
+            encoding,
+            MediaKeyEventAck {
+                request_id,
+                token,
+                result: MediaKeyEventResult::TimedOut,
+                error: "".to_string(),
+            },
+        );
+    }
+}
+
+/// 开启 `auth.require_device_auth` 或配置了多个凭据时,
+/// 设备须先以可访问该token且有完全控制权限的凭据认证才能注册
+///
+/// 返回错误码, 允许注册时为空
+fn check_device_registration(
+    require_device_auth: bool,
+    credential: Option<&Credential>,
+    token: &str,
+) -> &'static str {
+    match credential {
+        _ if !require_device_auth => "",
+        None => ERROR_NOT_AUTHENTICATED,
+        Some(credential) if !credential.can_register(token) => ERROR_NO_PERMISSION,
+        Some(_) => "",
+    }
+}
+
+fn next_request_id() -> u64 {
+    REQUEST_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// 注册在该token下的设备会话
+async fn token_players(token: &str) -> Vec<Arc<Player>> {
+    let session_ids = GLOBAL_CONTEXT
+        .devices
+        .lock()
+        .await
+        .get(token)
+        .cloned()
+        .unwrap_or_default();
+
+    let players = GLOBAL_CONTEXT.players.lock().await;
+    session_ids
+        .iter()
+        .filter_map(|session_id| players.get(session_id).cloned())
+        .collect()
+}
+
+/// 校验凭据后把按键事件投递给注册在该token下的所有设备, 控制端会话和HTTP接口共用
+///
+/// 设备的确认转发给 [`ack_to`] 会话, 未通过校验时返回错误码
+pub async fn deliver_media_key_event(
+    credential: Option<&Credential>,
+    request: SendControlMediaKeyEventRequest,
+    ack_to: Option<u32>,
+) -> Result<Delivery, &'static str> {
+    match credential {
+        None => return Err(ERROR_NOT_AUTHENTICATED),
+        Some(_) if !is_valid_key_event(request.action, request.code) => {
+            return Err(ERROR_INVALID_KEY_CODE)
+        }
+        Some(credential) if !credential.can_send_key(&request.token, request.code) => {
+            return Err(ERROR_NO_PERMISSION)
+        }
+        Some(_) => {}
+    }
+
+    let mut delivery = Delivery::new(request.token, ack_to);
+    let push = PushMediaKeyEvent {
+        action: request.action,
+        code: request.code,
+        token: delivery.token.clone(),
+        request_id: delivery.request_id,
+    };
+
+    let players = token_players(&delivery.token).await;
+    delivery.register_pending_ack(&players).await;
+    for player in players {
+        // 单个设备的通道已关闭不影响投递给其他设备
+        if player.send(push.clone()).is_ok() {
+            delivery.delivered_to(&player).await;
+        }
+    }
+    delivery.settle_pending_ack().await;
+    GLOBAL_METRICS
+        .key_event_fanout
+        .observe(delivery.delivered as f64);
+
+    Ok(delivery)
+}
+
+/// 校验凭据和参数后把媒体控制指令投递给注册在该token下的所有设备
+///
+/// 不支持 PushMediaCommand 的旧设备收到等价按键的按下和抬起, 没有等价按键的指令不发送
+pub async fn deliver_media_command(
+    credential: Option<&Credential>,
+    request: SendMediaCommandRequest,
+    ack_to: Option<u32>,
+) -> Result<Delivery, &'static str> {
+    match credential {
+        None => return Err(ERROR_NOT_AUTHENTICATED),
+        Some(_) if !request.command.is_valid() => return Err(ERROR_INVALID_COMMAND),
+        Some(credential) if !credential.can_send_command(&request.token, &request.command) => {
+            return Err(ERROR_NO_PERMISSION)
+        }
+        Some(_) => {}
+    }
+
+    let mut delivery = Delivery::new(request.token, ack_to);
+    let push = PushMediaCommand {
+        token: delivery.token.clone(),
+        request_id: delivery.request_id,
+        command: request.command,
+    };
+
+    let players = token_players(&delivery.token).await;
+    delivery.register_pending_ack(&players).await;
+    for player in players {
+        match push_media_command(&player, push.clone()).await {
+            Some(true) => delivery.delivered_to(&player).await,
+            Some(false) => {}
+            None => delivery.unsupported += 1,
+        }
+    }
+    delivery.settle_pending_ack().await;
+    GLOBAL_METRICS
+        .key_event_fanout
+        .observe(delivery.delivered as f64);
+
+    Ok(delivery)
+}
+
+/// 向单个设备发送媒体控制指令, 不支持 PushMediaCommand 的旧设备收到等价按键的按下和抬起
+///
+/// 返回是否发送成功, 旧设备没有等价按键时返回 None
+async fn push_media_command(player: &Player, push: PushMediaCommand) -> Option<bool> {
+    if player.supports(FEATURE_MEDIA_COMMAND).await {
+        return Some(player.send(push).is_ok());
+    }
+
+    // 只有按下事件带本次请求id, 设备确认一次即可
+    let down = PushMediaKeyEvent {
+        action: ACTION_DOWN,
+        code: push.command.key_code()?,
+        token: push.token,
+        request_id: push.request_id,
+    };
+    let up = PushMediaKeyEvent {
+        action: ACTION_UP,
+        request_id: next_request_id(),
+        ..down.clone()
+    };
+    Some(player.send(down).is_ok() && player.send(up).is_ok())
+}
+
+/// 同一设备一段时间内的音量调整, 合并后发送
+#[derive(Default)]
+pub struct PendingVolume {
+    percent: Option<u32>,
+    steps: i32,
+}
+
+impl PendingVolume {
+    fn merge(&mut self, change: VolumeChange) {
+        match change {
+            // 绝对音量覆盖之前的所有调整
+            VolumeChange::Absolute { percent } => {
+                self.percent = Some(percent);
+                self.steps = 0;
+            }
+            VolumeChange::Relative { steps } => {
+                self.steps = (self.steps + steps).clamp(-MAX_VOLUME_STEPS, MAX_VOLUME_STEPS);
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.percent.is_none() && self.steps == 0
+    }
+
+    /// 先设置绝对音量, 再按档位调整
+    fn changes(&self) -> impl Iterator<Item = VolumeChange> {
+        let absolute = self
+            .percent
+            .map(|percent| VolumeChange::Absolute { percent });
+        let relative = VolumeChange::Relative { steps: self.steps };
+        let steps = self.steps.unsigned_abs() as usize;
+        absolute
+            .into_iter()
+            .chain(std::iter::repeat_n(relative, steps))
+    }
+}
+
+/// 校验凭据和参数后调整设备音量, 控制端会话和HTTP接口共用
+///
+/// 第一次调整立即发送, 之后每 VOLUME_COALESCE_INTERVAL 最多发送一次合并后的调整,
+/// 拖动滑块时不会向设备发送大量指令。没有设备能执行时返回设备离线或不支持该指令
+pub async fn set_volume(
+    credential: Option<&Credential>,
+    request: SetVolumeRequest,
+) -> Result<(), &'static str> {
+    match credential {
+        None => return Err(ERROR_NOT_AUTHENTICATED),
+        Some(_) if !request.change.is_valid() => return Err(ERROR_INVALID_COMMAND),
+        Some(credential)
+            if !credential.can_send_command(&request.token, &request.change.command()) =>
+        {
+            return Err(ERROR_NO_PERMISSION)
+        }
+        Some(_) => {}
+    }
+    // 合并的调整稍后才发送, 先确认有设备能执行
+    let players = token_players(&request.token).await;
+    if players.is_empty() {
+        return Err(ERROR_DEVICE_OFFLINE);
+    }
+    let command = request.change.command();
+    let mut supported = false;
+    for player in players.iter() {
+        if command.key_code().is_some() || player.supports(FEATURE_MEDIA_COMMAND).await {
+            supported = true;
+            break;
+        }
+    }
+    if !supported {
+        return Err(ERROR_UNSUPPORTED_COMMAND);
+    }
+
+    let token = request.token;
+    {
+        let mut volume_changes = GLOBAL_CONTEXT.volume_changes.lock().await;
+        if let Some(pending) = volume_changes.get_mut(&token) {
+            pending.merge(request.change);
+            return Ok(());
+        }
+        volume_changes.insert(token.clone(), PendingVolume::default());
+    }
+
+    let mut pending = PendingVolume::default();
+    pending.merge(request.change);
+    let error = push_volume_changes(&token, pending).await.error();
+
+    tokio::spawn(
+        async move {
+            loop {
+                sleep(VOLUME_COALESCE_INTERVAL).await;
+                let pending = {
+                    let mut volume_changes = GLOBAL_CONTEXT.volume_changes.lock().await;
+                    match volume_changes.get_mut(&token) {
+                        Some(pending) if !pending.is_empty() => std::mem::take(pending),
+                        _ => {
+                            volume_changes.remove(&token);
+                            return;
+                        }
+                    }
+                };
+                let delivery = push_volume_changes(&token, pending).await;
+                if !delivery.error().is_empty() {
+                    debug!("volume change not applied: {}", delivery.error());
+                }
+            }
+        }
+        .in_current_span(),
+    );
+    match error {
+        "" => Ok(()),
+        error => Err(error),
+    }
+}
+
+/// 向注册在该token下的所有设备发送合并后的调整
+///
+/// 设备执行了其中至少一项调整且全部发送成功时计为投递, 旧设备只能执行有等价按键的调整
+async fn push_volume_changes(token: &str, pending: PendingVolume) -> Delivery {
+    let mut delivery = Delivery::new(token.to_string(), None);
+    let pushes: Vec<PushMediaCommand> = pending
+        .changes()
+        .map(|change| PushMediaCommand {
+            token: token.to_string(),
+            request_id: next_request_id(),
+            command: change.command(),
+        })
+        .collect();
+
+    for player in token_players(token).await {
+        // None 表示该设备不能执行任何一项调整
+        let mut sent = None;
+        for push in pushes.iter() {
+            if let Some(ok) = push_media_command(&player, push.clone()).await {
+                sent = Some(sent.unwrap_or(true) && ok);
+            }
+        }
+        match sent {
+            Some(true) => delivery.delivered_to(&player).await,
+            Some(false) => {}
+            None => delivery.unsupported += 1,
+        }
+    }
+    delivery
+}
+
+pub fn media_key_event_response(delivery: &Delivery) -> SendControlMediaKeyEventResponse {
+    let error = delivery.error();
+    SendControlMediaKeyEventResponse {
+        ok: error.is_empty(),
+        error: error.to_string(),
+        delivered: delivery.delivered,
+        request_id: delivery.request_id,
+    }
+}
+
+pub fn media_command_response(delivery: &Delivery) -> SendMediaCommandResponse {
+    let error = delivery.error();
+    SendMediaCommandResponse {
+        ok: error.is_empty(),
+        error: error.to_string(),
+        delivered: delivery.delivered,
+        request_id: delivery.request_id,
+    }
+}
+
+/// 凭据可访问且正在播放该封面、能够上传封面的设备
+async fn artwork_owner(credential: &Credential, hash: &str) -> Option<Arc<Player>> {
+    let players: Vec<Arc<Player>> = GLOBAL_CONTEXT
+        .players
+        .lock()
+        .await
+        .values()
+        .cloned()
+        .collect();
+
+    for player in players {
+        let owns = player
+            .now_playing
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|x| x.artwork_hash == hash && credential.can_access(&x.token));
+        if owns && player.supports(FEATURE_ARTWORK).await {
+            return Some(player);
+        }
+    }
+    None
+}
+
+/// 发送 ArtworkResponse 和该封面的所有分片
+fn send_artwork(player: &Player, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+    player.send(ArtworkResponse {
+        ok: true,
+        error: "".to_string(),
+        hash: hash.to_string(),
+        total_size: data.len() as u64,
+    })?;
+    for chunk in artwork_chunks(hash, data) {
+        player.send(chunk)?;
+    }
+    Ok(())
+}
+
+/// 封面上传结束, 把结果发给等待的控制端, 上传失败时 data 为 None
+async fn notify_artwork_waiters(hash: &str, data: Option<&[u8]>) {
+    let Some(session_ids) = GLOBAL_CONTEXT.artwork_waiters.lock().await.remove(hash) else {
+        return;
+    };
+    let players: Vec<Arc<Player>> = {
+        let players = GLOBAL_CONTEXT.players.lock().await;
+        session_ids
+            .iter()
+            .filter_map(|session_id| players.get(session_id).cloned())
+            .collect()
+    };
+
+    for player in players {
+        let _ = match data {
+            Some(data) => send_artwork(&player, hash, data),
+            None => player.send(ArtworkResponse {
+                ok: false,
+                error: ERROR_ARTWORK_NOT_FOUND.to_string(),
+                hash: hash.to_string(),
+                total_size: 0,
+            }),
+        };
+    }
+}
+
+/// 订阅列表是否包含该设备
+fn subscribes<'a>(mut tokens: impl Iterator<Item = &'a String>, token: &str) -> bool {
+    tokens.any(|x| x == SUBSCRIBE_ALL || x == token)
+}
+
+fn sorted(tokens: &HashSet<String>) -> Vec<String> {
+    let mut tokens: Vec<String> = tokens.iter().cloned().collect();
+    tokens.sort();
+    tokens
+}
+
+/// 通知订阅了该设备且能看到该设备token的控制端, 只发给支持 feature 的控制端
+async fn notify_subscribers(token: &str, feature: &str, packet: Packet) {
+    let session_ids: Vec<u32> = GLOBAL_CONTEXT
+        .subscriptions
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, tokens)| subscribes(tokens.iter(), token))
+        .map(|(session_id, _)| *session_id)
+        .collect();
+    let players: Vec<Arc<Player>> = {
+        let players = GLOBAL_CONTEXT.players.lock().await;
+        session_ids
+            .iter()
+            .filter_map(|session_id| players.get(session_id).cloned())
+            .collect()
+    };
+
+    for player in players {
+        if !player.supports(feature).await {
+            continue;
+        }
+        if let Some(credential) = *player.credential.read().await {
+            if credential.can_see_token(token) {
+                let _ = player.send(packet.clone());
+            }
+        }
+    }
 }
 
 fn now_millis() -> u64 {
@@ -121,23 +1478,692 @@ fn now_millis() -> u64 {
     since_the_epoch.as_millis() as u64
 }
 
-fn send_message<U>(tx: &UnboundedSender<WriterMessage>, data: &U) -> anyhow::Result<()>
-where
-    U: Serialize,
-{
-    let message = Message {
-        name: type_name_of::<U>().to_string(),
-        data: serde_json::to_string(data)?,
+/// 状态通知只需要送达最新的一条, 发送队列满时可以丢弃旧的
+fn queue_policy(packet: &Packet) -> QueuePolicy {
+    match packet {
+        Packet::NowPlayingNtf(_) | Packet::VolumeStateNtf(_) | Packet::Ping(_) => {
+            QueuePolicy::DropOldest
+        }
+        _ => QueuePolicy::Reliable,
+    }
+}
+
+fn send_message(
+    tx: &WriterSender,
+    encoding: Encoding,
+    packet: impl Into<Packet>,
+) -> anyhow::Result<()> {
+    let packet = packet.into();
+    let frame = packet.encode(encoding)?;
+    debug!(
+        "send: {} ({} bytes {})",
+        packet.name(),
+        frame.len(),
+        encoding.name()
+    );
+
+    // ws自带分帧, 不需要长度前缀
+    let data = match tx.framing() {
+        Framing::LengthPrefixed => encode_frame(&frame)?,
+        Framing::Message => frame,
     };
+    tx.send_with_policy(WriterMessage::Send(data, true), queue_policy(&packet))?;
+    GLOBAL_METRICS
+        .messages_sent
+        .with_label_values(&[packet.name()])
+        .inc();
 
-    let str = serde_json::to_string(&message)?;
-    println!("send: {}", str);
+    Ok(())
+}
 
-    let mut buf = Vec::with_capacity(str.len() + 4);
-    byteorder::WriteBytesExt::write_u32::<BigEndian>(&mut buf, str.len() as u32)?;
-    buf.extend_from_slice(str.as_bytes());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        connect, connect_controller, connect_device, hello, read_packet, read_until, register,
+        write_packet,
+    };
+    use rmc_proto::{
+        MediaCommand, PlaybackState, ARTWORK_CHUNK_SIZE, ENCODING_MSGPACK, KEYCODE_VOLUME_UP,
+    };
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    /// 其他测试的设备也会上报, 只读取指定设备的
+    async fn read_now_playing(stream: &mut TcpStream, token: &str) -> NowPlayingNtf {
+        read_until(stream, |packet| match packet {
+            Packet::NowPlayingNtf(now_playing) if now_playing.token == token => Some(now_playing),
+            _ => None,
+        })
+        .await
+    }
 
-    tx.send(WriterMessage::Send(buf, true))?;
+    #[tokio::test]
+    async fn unresponsive_peer_is_disconnected() {
+        // 不读取发送队列, 模拟对方不再接收数据、写端一直阻塞
+        let (tx, _rx) =
+            crate::net::writer_queue::channel(Default::default(), Framing::LengthPrefixed);
+        let task = tokio::spawn(keepalive(
+            tx.clone(),
+            Arc::new(SessionEncoding::default()),
+            Arc::new(RwLock::new(Instant::now())),
+            Arc::new(RwLock::new(RttEstimator::default())),
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+            Duration::from_millis(50),
+        ));
 
-    Ok(())
+        let reason = tokio::time::timeout(Duration::from_secs(5), tx.aborted())
+            .await
+            .unwrap();
+        assert_eq!(reason, "idle timeout");
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn key_events_reach_only_devices_with_token() {
+        let mut target = connect_device("routing-phone").await;
+        let mut other = connect_device("routing-other").await;
+        let mut controller = connect_controller(&[]).await;
+
+        write_packet(
+            &mut controller,
+            SendControlMediaKeyEventRequest {
+                action: ACTION_DOWN,
+                code: rmc_proto::KEYCODE_MEDIA_NEXT,
+                token: "routing-phone".to_string(),
+            },
+        )
+        .await;
+        let push = read_packet!(&mut target, Packet::PushMediaKeyEvent);
+        assert_eq!(push.token, "routing-phone");
+        assert_eq!(push.code, rmc_proto::KEYCODE_MEDIA_NEXT);
+
+        // 其他token的设备收不到, Pong 之前没有按键事件
+        write_packet(&mut other, Ping { time: 1 }).await;
+        let received = read_until(&mut other, |packet| match packet {
+            Packet::Pong(_) => Some(false),
+            Packet::PushMediaKeyEvent(_) => Some(true),
+            _ => None,
+        })
+        .await;
+        assert!(!received);
+    }
+
+    #[tokio::test]
+    async fn registration_is_validated() {
+        let mut stream = connect().await;
+        register(&mut stream, DeviceRole::Device, "").await;
+        let response = read_packet!(&mut stream, Packet::RegisterDeviceResponse);
+        assert!(!response.ok);
+        assert_eq!(response.error, "empty token");
+
+        register(&mut stream, DeviceRole::Device, "registration-phone").await;
+        let response = read_packet!(&mut stream, Packet::RegisterDeviceResponse);
+        assert!(response.ok, "{}", response.error);
+        assert_eq!(response.encoding, "json");
+
+        register(&mut stream, DeviceRole::Controller, "").await;
+        let response = read_packet!(&mut stream, Packet::RegisterDeviceResponse);
+        assert!(!response.ok);
+        assert_eq!(response.error, "already registered");
+    }
+
+    #[tokio::test]
+    async fn negotiated_msgpack_applies_after_register_response() {
+        /// 读取一个完整的帧, 返回原始字节
+        async fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+            let len = stream.read_u32().await.unwrap() as usize;
+            let mut frame = vec![0u8; len];
+            stream.read_exact(&mut frame).await.unwrap();
+            frame
+        }
+
+        let mut stream = connect().await;
+        write_packet(
+            &mut stream,
+            RegisterDeviceRequest {
+                role: DeviceRole::Device,
+                token: "msgpack-phone".to_string(),
+                device_name: "loopback".to_string(),
+                client_version: "test".to_string(),
+                capabilities: vec![],
+                encodings: vec![ENCODING_MSGPACK.to_string()],
+            },
+        )
+        .await;
+
+        // 响应本身仍是 JSON
+        let response = loop {
+            let frame = read_frame(&mut stream).await;
+            if let Packet::RegisterDeviceResponse(response) = Packet::decode(&frame).unwrap() {
+                assert_eq!(frame[0], b'{');
+                break response;
+            }
+        };
+        assert!(response.ok, "{}", response.error);
+        assert_eq!(response.encoding, ENCODING_MSGPACK);
+
+        // 之后的消息使用 MessagePack(`[name, payload]`, 首字节为二元素数组标记)
+        write_packet(&mut stream, Ping { time: 42 }).await;
+        let frame = loop {
+            let frame = read_frame(&mut stream).await;
+            if matches!(Packet::decode(&frame).unwrap(), Packet::Pong(ref pong) if pong.time == 42)
+            {
+                break frame;
+            }
+        };
+        assert_eq!(frame[0], 0x92);
+    }
+
+    #[test]
+    fn device_registration_may_require_credential() {
+        let access_control: crate::access_control::AccessControl = toml::from_str(
+            r#"
+            [[credentials]]
+            name = "alice"
+            secret = "alice-secret"
+            tokens = ["alice-phone"]
+            "#,
+        )
+        .unwrap();
+        let alice = access_control.find_by_name("alice");
+
+        assert_eq!(check_device_registration(false, None, "alice-phone"), "");
+        assert_eq!(
+            check_device_registration(true, None, "alice-phone"),
+            ERROR_NOT_AUTHENTICATED
+        );
+        assert_eq!(check_device_registration(true, alice, "alice-phone"), "");
+        assert_eq!(
+            check_device_registration(true, alice, "bob-phone"),
+            ERROR_NO_PERMISSION
+        );
+    }
+
+    #[tokio::test]
+    async fn unregistered_session_is_closed() {
+        let mut stream = connect().await;
+        write_packet(&mut stream, SubscribeRequest { tokens: vec![] }).await;
+
+        // 服务器关闭连接
+        let mut data = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut data))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn response_reports_delivered_devices() {
+        let _phone = connect_device("delivered-phone").await;
+        let _tablet = connect_device("delivered-phone").await;
+        let mut controller = connect_controller(&[]).await;
+
+        for (token, delivered) in [("delivered-phone", 2), ("delivered-offline", 0)] {
+            write_packet(
+                &mut controller,
+                SendControlMediaKeyEventRequest {
+                    action: ACTION_DOWN,
+                    code: rmc_proto::KEYCODE_MEDIA_PLAY_PAUSE,
+                    token: token.to_string(),
+                },
+            )
+            .await;
+            let response = read_packet!(&mut controller, Packet::SendControlMediaKeyEventResponse);
+            assert_eq!(response.delivered, delivered);
+            if delivered > 0 {
+                assert!(response.ok, "{}", response.error);
+            } else {
+                assert!(!response.ok);
+                assert_eq!(response.error, ERROR_DEVICE_OFFLINE);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn device_presence_is_listed_and_notified() {
+        let mut controller = connect_controller(&["presence-phone"]).await;
+        let device = connect_device("presence-phone").await;
+        let online = read_until(&mut controller, |packet| match packet {
+            Packet::DeviceOnlineNtf(x) if x.device.token == "presence-phone" => Some(x.device),
+            _ => None,
+        })
+        .await;
+
+        write_packet(&mut controller, ListDevicesRequest {}).await;
+        let response = read_packet!(&mut controller, Packet::ListDevicesResponse);
+        let listed = response
+            .devices
+            .iter()
+            .find(|x| x.token == "presence-phone")
+            .unwrap();
+        assert_eq!(listed.session_id, online.session_id);
+        assert_eq!(listed.name, "loopback");
+        assert!(listed.connect_time > 0);
+
+        drop(device);
+        let offline = read_until(&mut controller, |packet| match packet {
+            Packet::DeviceOfflineNtf(x) if x.token == "presence-phone" => Some(x),
+            _ => None,
+        })
+        .await;
+        assert_eq!(offline.session_id, online.session_id);
+    }
+
+    #[tokio::test]
+    async fn hello_negotiates_common_features() {
+        let mut stream = connect().await;
+        write_packet(
+            &mut stream,
+            Hello {
+                protocol_version: PROTOCOL_VERSION + 1,
+                min_protocol_version: 1,
+                features: vec![FEATURE_MEDIA_KEY_ACK.to_string(), "teleport".to_string()],
+            },
+        )
+        .await;
+
+        let response = read_packet!(&mut stream, Packet::HelloResponse);
+        assert!(response.ok, "{}", response.error);
+        assert_eq!(response.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(response.features, vec![FEATURE_MEDIA_KEY_ACK.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn incompatible_hello_closes_session() {
+        let mut stream = connect().await;
+        write_packet(
+            &mut stream,
+            Hello {
+                protocol_version: PROTOCOL_VERSION + 2,
+                min_protocol_version: PROTOCOL_VERSION + 1,
+                features: vec![],
+            },
+        )
+        .await;
+
+        let response = read_packet!(&mut stream, Packet::HelloResponse);
+        assert!(!response.ok);
+        assert!(response.error.contains("incompatible protocol version"));
+
+        // 服务器在发送错误原因后断开连接
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf)).await;
+        assert!(read.is_ok());
+    }
+
+    #[tokio::test]
+    async fn now_playing_is_relayed_to_subscribers_and_cached() {
+        let mut controller = connect_controller(&["now-playing-phone"]).await;
+        let mut other = connect_controller(&["another-phone"]).await;
+
+        let mut device = connect().await;
+        hello(&mut device).await;
+        register(&mut device, DeviceRole::Device, "now-playing-phone").await;
+        write_packet(
+            &mut device,
+            NowPlayingNtf {
+                // 设备填写的 token 会被服务器覆盖
+                token: "someone-else".to_string(),
+                title: "Song".to_string(),
+                state: PlaybackState::Playing,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let now_playing = read_now_playing(&mut controller, "now-playing-phone").await;
+        assert_eq!(now_playing.token, "now-playing-phone");
+        assert_eq!(now_playing.title, "Song");
+        assert!(now_playing.updated_at > 0);
+
+        // 没有订阅该设备的控制端收不到, Pong 之前没有任何通知
+        write_packet(&mut other, Ping { time: 1 }).await;
+        let received = read_until(&mut other, |packet| match packet {
+            Packet::Pong(_) => Some(None),
+            Packet::NowPlayingNtf(now_playing) => Some(Some(now_playing)),
+            _ => None,
+        })
+        .await;
+        assert!(received.is_none());
+
+        // 之后订阅的控制端立即收到缓存
+        let mut late = connect_controller(&[SUBSCRIBE_ALL]).await;
+        let cached = read_now_playing(&mut late, "now-playing-phone").await;
+        assert_eq!(cached, now_playing);
+    }
+
+    #[tokio::test]
+    async fn notifications_follow_subscriptions() {
+        let mut subscribed = connect_controller(&["subscription-phone"]).await;
+        let mut unsubscribed = connect_controller(&["subscription-phone"]).await;
+        write_packet(
+            &mut unsubscribed,
+            UnsubscribeRequest {
+                tokens: vec!["subscription-phone".to_string()],
+            },
+        )
+        .await;
+        let response = read_packet!(&mut unsubscribed, Packet::UnsubscribeResponse);
+        assert!(response.ok, "{}", response.error);
+        assert!(response.tokens.is_empty());
+
+        let mut device = connect().await;
+        register(&mut device, DeviceRole::Device, "subscription-phone").await;
+        let online = read_until(&mut subscribed, |packet| match packet {
+            Packet::DeviceOnlineNtf(x) if x.device.token == "subscription-phone" => Some(x),
+            _ => None,
+        })
+        .await;
+        assert_eq!(online.device.name, "loopback");
+
+        // 取消订阅的控制端收不到, Pong 之前没有该设备的通知
+        write_packet(&mut unsubscribed, Ping { time: 1 }).await;
+        let received = read_until(&mut unsubscribed, |packet| match packet {
+            Packet::Pong(_) => Some(false),
+            Packet::DeviceOnlineNtf(x) => Some(x.device.token == "subscription-phone"),
+            _ => None,
+        })
+        .await;
+        assert!(!received);
+
+        // 断开后订阅记录被清理
+        let subscribed_sessions = || async {
+            GLOBAL_CONTEXT
+                .subscriptions
+                .lock()
+                .await
+                .values()
+                .filter(|tokens| tokens.contains("subscription-phone"))
+                .count()
+        };
+        assert_eq!(subscribed_sessions().await, 1);
+        drop(subscribed);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while subscribed_sessions().await > 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    /// 控制端收到请求的响应和设备确认, 两者到达顺序不固定
+    async fn read_key_event_result(
+        stream: &mut TcpStream,
+    ) -> (SendControlMediaKeyEventResponse, MediaKeyEventAck) {
+        let (mut response, mut ack) = (None, None);
+        while response.is_none() || ack.is_none() {
+            match crate::test_support::read_next(stream).await {
+                Packet::SendControlMediaKeyEventResponse(x) => response = Some(x),
+                Packet::MediaKeyEventAck(x) => ack = Some(x),
+                _ => {}
+            }
+        }
+        (response.unwrap(), ack.unwrap())
+    }
+
+    #[tokio::test]
+    async fn media_key_event_acks_reach_controller() {
+        let mut device = connect().await;
+        hello(&mut device).await;
+        register(&mut device, DeviceRole::Device, "ack-phone").await;
+        let response = read_packet!(&mut device, Packet::RegisterDeviceResponse);
+        assert!(response.ok, "{}", response.error);
+        let mut controller = connect_controller(&[]).await;
+
+        // 设备收到后立即确认, 确认可能先于投递结束到达; 超时见 unacknowledged_key_event_times_out
+        for result in [MediaKeyEventResult::Executed, MediaKeyEventResult::Rejected] {
+            write_packet(
+                &mut controller,
+                SendControlMediaKeyEventRequest {
+                    action: ACTION_DOWN,
+                    code: rmc_proto::KEYCODE_MEDIA_PLAY_PAUSE,
+                    token: "ack-phone".to_string(),
+                },
+            )
+            .await;
+            let push = read_packet!(&mut device, Packet::PushMediaKeyEvent);
+            write_packet(
+                &mut device,
+                MediaKeyEventAck {
+                    request_id: push.request_id,
+                    token: "".to_string(),
+                    result,
+                    error: "".to_string(),
+                },
+            )
+            .await;
+
+            let (response, ack) = read_key_event_result(&mut controller).await;
+            assert!(response.ok, "{}", response.error);
+            assert_eq!(response.delivered, 1);
+            assert_eq!(response.request_id, push.request_id);
+            assert_eq!(ack.request_id, push.request_id);
+            assert_eq!(ack.token, "ack-phone");
+            assert_eq!(ack.result, result);
+            assert!(!GLOBAL_CONTEXT
+                .pending_acks
+                .lock()
+                .await
+                .contains_key(&push.request_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_key_event_times_out() {
+        let (tx, mut rx) = crate::net::writer_queue::channel(Default::default(), Framing::Message);
+        let request_id = next_request_id();
+        GLOBAL_CONTEXT.pending_acks.lock().await.insert(
+            request_id,
+            PendingAck {
+                controller_session_id: 0,
+                token: "timeout-phone".to_string(),
+                remaining: 1,
+            },
+        );
+        expire_media_key_event_ack(
+            request_id,
+            "timeout-phone".to_string(),
+            tx.clone(),
+            Encoding::Json,
+            Duration::from_millis(10),
+        )
+        .await;
+
+        let Some(WriterMessage::Send(frame, _)) = rx.recv().await else {
+            panic!("expected a frame");
+        };
+        let Packet::MediaKeyEventAck(ack) = Packet::decode(&frame).unwrap() else {
+            panic!("expected MediaKeyEventAck");
+        };
+        assert_eq!(ack.request_id, request_id);
+        assert_eq!(ack.token, "timeout-phone");
+        assert_eq!(ack.result, MediaKeyEventResult::TimedOut);
+        assert!(!GLOBAL_CONTEXT
+            .pending_acks
+            .lock()
+            .await
+            .contains_key(&request_id));
+
+        // 已确认的按键事件不再通知
+        expire_media_key_event_ack(
+            request_id,
+            "timeout-phone".to_string(),
+            tx.clone(),
+            Encoding::Json,
+            Duration::from_millis(10),
+        )
+        .await;
+        assert_eq!(tx.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn artwork_is_fetched_from_device_once() {
+        let artwork: Vec<u8> = (0..ARTWORK_CHUNK_SIZE + 10).map(|x| x as u8).collect();
+        let hash = rmc_proto::artwork_hash(&artwork);
+
+        let mut device = connect().await;
+        hello(&mut device).await;
+        register(&mut device, DeviceRole::Device, "artwork-phone").await;
+        write_packet(
+            &mut device,
+            NowPlayingNtf {
+                artwork_hash: hash.clone(),
+                ..Default::default()
+            },
+        )
+        .await;
+        let request = read_packet!(&mut device, Packet::ArtworkRequest);
+        assert_eq!(request.hash, hash);
+
+        // 上传完成前请求的控制端在上传完成后收到封面
+        let mut controller = connect_controller(&[]).await;
+        write_packet(&mut controller, ArtworkRequest { hash: hash.clone() }).await;
+        for chunk in artwork_chunks(&hash, &artwork) {
+            write_packet(&mut device, chunk).await;
+        }
+
+        for round in 0..2 {
+            if round > 0 {
+                // 第二次直接从缓存发送
+                write_packet(&mut controller, ArtworkRequest { hash: hash.clone() }).await;
+            }
+            let response = read_packet!(&mut controller, Packet::ArtworkResponse);
+            assert!(response.ok, "{}", response.error);
+            assert_eq!(response.total_size, artwork.len() as u64);
+
+            let mut assembler = ArtworkAssembler::default();
+            let data = loop {
+                let chunk = read_packet!(&mut controller, Packet::ArtworkChunk);
+                if let Some((_, data)) = assembler.push(chunk).unwrap() {
+                    break data;
+                }
+            };
+            assert_eq!(data, artwork);
+        }
+
+        write_packet(
+            &mut controller,
+            ArtworkRequest {
+                hash: "unknown".to_string(),
+            },
+        )
+        .await;
+        let response = read_packet!(&mut controller, Packet::ArtworkResponse);
+        assert_eq!(response.error, ERROR_ARTWORK_NOT_FOUND);
+    }
+
+    #[test]
+    fn volume_changes_are_merged() {
+        let mut pending = PendingVolume::default();
+        pending.merge(VolumeChange::Relative { steps: 2 });
+        pending.merge(VolumeChange::Absolute { percent: 30 });
+        pending.merge(VolumeChange::Relative { steps: -1 });
+        pending.merge(VolumeChange::Relative { steps: -2 });
+        let changes: Vec<VolumeChange> = pending.changes().collect();
+        assert_eq!(
+            changes,
+            vec![
+                VolumeChange::Absolute { percent: 30 },
+                VolumeChange::Relative { steps: -3 },
+                VolumeChange::Relative { steps: -3 },
+                VolumeChange::Relative { steps: -3 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rapid_volume_changes_are_coalesced() {
+        let mut device = connect().await;
+        hello(&mut device).await;
+        register(&mut device, DeviceRole::Device, "volume-phone").await;
+        let mut controller = connect_controller(&["volume-phone"]).await;
+
+        for percent in [10, 20, 30] {
+            write_packet(
+                &mut controller,
+                SetVolumeRequest {
+                    token: "volume-phone".to_string(),
+                    change: VolumeChange::Absolute { percent },
+                },
+            )
+            .await;
+            let response = read_packet!(&mut controller, Packet::SetVolumeResponse);
+            assert!(response.ok, "{}", response.error);
+        }
+
+        // 第一次立即发送, 之后的调整合并为最后一次
+        for expected in [10, 30] {
+            let push = read_packet!(&mut device, Packet::PushMediaCommand);
+            assert_eq!(push.command, MediaCommand::SetVolume { percent: expected });
+        }
+
+        write_packet(
+            &mut device,
+            VolumeStateNtf {
+                percent: 30,
+                ..Default::default()
+            },
+        )
+        .await;
+        let volume = read_until(&mut controller, |packet| match packet {
+            Packet::VolumeStateNtf(volume) if volume.token == "volume-phone" => Some(volume),
+            _ => None,
+        })
+        .await;
+        assert_eq!(volume.percent, 30);
+    }
+
+    #[tokio::test]
+    async fn legacy_device_volume_falls_back_to_keys() {
+        let mut device = connect_device("legacy-volume-phone").await;
+        let mut controller = connect_controller(&[]).await;
+
+        // 旧设备没有设置绝对音量的等价按键
+        for (token, change, error) in [
+            (
+                "legacy-volume-phone",
+                VolumeChange::Absolute { percent: 30 },
+                ERROR_UNSUPPORTED_COMMAND,
+            ),
+            (
+                "legacy-volume-offline",
+                VolumeChange::Relative { steps: 1 },
+                ERROR_DEVICE_OFFLINE,
+            ),
+        ] {
+            write_packet(
+                &mut controller,
+                SetVolumeRequest {
+                    token: token.to_string(),
+                    change,
+                },
+            )
+            .await;
+            let response = read_packet!(&mut controller, Packet::SetVolumeResponse);
+            assert!(!response.ok);
+            assert_eq!(response.error, error);
+        }
+
+        write_packet(
+            &mut controller,
+            SetVolumeRequest {
+                token: "legacy-volume-phone".to_string(),
+                change: VolumeChange::Relative { steps: 2 },
+            },
+        )
+        .await;
+        let response = read_packet!(&mut controller, Packet::SetVolumeResponse);
+        assert!(response.ok, "{}", response.error);
+
+        // 每一档收到一次按下和抬起
+        for _ in 0..2 {
+            for action in [ACTION_DOWN, ACTION_UP] {
+                let push = read_packet!(&mut device, Packet::PushMediaKeyEvent);
+                assert_eq!((push.action, push.code), (action, KEYCODE_VOLUME_UP));
+            }
+        }
+    }
 }
@@ -0,0 +1,144 @@
+//! 测试共用的回环服务器和客户端
+//!
+//! 所有测试共享全局上下文和访问控制表, 每个测试应使用各自的设备token
+
+use crate::access_control::AccessControl;
+use crate::net::session_delegate::SessionDelegate;
+use crate::net::tcp_server;
+use crate::peer::Peer;
+use crate::GLOBAL_ACCESS_CONTROL;
+use hmac::{Hmac, Mac};
+use rmc_proto::codec::encode_frame;
+use rmc_proto::{
+    AuthRequest, DeviceRole, Encoding, Packet, Protocol, RegisterDeviceRequest, SubscribeRequest,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// 可控制所有设备的凭据 "default" 的密钥
+pub const SECRET: &str = "loopback-secret";
+
+/// 读取消息直到收到指定类型, 跳过认证挑战等无关消息
+macro_rules! read_packet {
+    ($stream:expr, $variant:path) => {
+        $crate::test_support::read_until($stream, |packet| match packet {
+            $variant(inner) => Some(inner),
+            _ => None,
+        })
+        .await
+    };
+}
+pub(crate) use read_packet;
+
+pub fn init_access_control() {
+    GLOBAL_ACCESS_CONTROL.get_or_init(|| AccessControl::with_authorization_code(SECRET.into()));
+}
+
+/// 启动一个TCP监听并连接
+pub async fn connect() -> TcpStream {
+    init_access_control();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        tcp_server::Builder::new(Box::new(|| -> Box<dyn SessionDelegate> {
+            Box::new(Peer::new("tcp"))
+        }))
+        .build_with_listener(listener, std::future::pending::<()>()),
+    );
+
+    TcpStream::connect(addr).await.unwrap()
+}
+
+pub async fn write_packet(stream: &mut TcpStream, packet: impl Into<Packet>) {
+    let frame = packet.into().encode(Encoding::Json).unwrap();
+    stream
+        .write_all(&encode_frame(&frame).unwrap())
+        .await
+        .unwrap();
+}
+
+pub async fn read_next(stream: &mut TcpStream) -> Packet {
+    let len = stream.read_u32().await.unwrap() as usize;
+    let mut bytes = vec![0u8; len];
+    stream.read_exact(&mut bytes).await.unwrap();
+    Packet::decode(&bytes).unwrap()
+}
+
+/// 读取消息直到 f 返回 Some
+pub async fn read_until<T>(stream: &mut TcpStream, f: impl Fn(Packet) -> Option<T>) -> T {
+    loop {
+        if let Some(result) = f(read_next(stream).await) {
+            return result;
+        }
+    }
+}
+
+pub fn compute_proof(secret: &str, nonce: &str) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(nonce.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub async fn hello(stream: &mut TcpStream) {
+    write_packet(stream, Protocol::hello()).await;
+}
+
+/// 发送注册请求, 不等待响应
+pub async fn register(stream: &mut TcpStream, role: DeviceRole, token: &str) {
+    write_packet(
+        stream,
+        RegisterDeviceRequest {
+            role,
+            token: token.to_string(),
+            device_name: "loopback".to_string(),
+            client_version: "test".to_string(),
+            capabilities: vec![],
+            encodings: vec![],
+        },
+    )
+    .await;
+}
+
+/// 读取会话开始时的挑战, 以凭据 [`name`] 认证, 不等待响应
+pub async fn authenticate(stream: &mut TcpStream, name: &str, secret: &str) {
+    let challenge = read_packet!(stream, Packet::AuthChallengeNtf);
+    write_packet(
+        stream,
+        AuthRequest {
+            name: name.to_string(),
+            proof: compute_proof(secret, &challenge.nonce).into(),
+        },
+    )
+    .await;
+}
+
+/// 以设备身份连接并注册, 不发送 Hello(旧客户端)
+pub async fn connect_device(token: &str) -> TcpStream {
+    let mut stream = connect().await;
+    register(&mut stream, DeviceRole::Device, token).await;
+    let response = read_packet!(&mut stream, Packet::RegisterDeviceResponse);
+    assert!(response.ok, "{}", response.error);
+    stream
+}
+
+/// 以控制端身份连接、注册、认证并订阅设备
+pub async fn connect_controller(tokens: &[&str]) -> TcpStream {
+    let mut stream = connect().await;
+    hello(&mut stream).await;
+    register(&mut stream, DeviceRole::Controller, "").await;
+    authenticate(&mut stream, "default", SECRET).await;
+    let response = read_packet!(&mut stream, Packet::AuthResponse);
+    assert!(response.ok, "{}", response.error);
+
+    write_packet(
+        &mut stream,
+        SubscribeRequest {
+            tokens: tokens.iter().map(|x| x.to_string()).collect(),
+        },
+    )
+    .await;
+    let response = read_packet!(&mut stream, Packet::SubscribeResponse);
+    assert!(response.ok, "{}", response.error);
+    stream
+}